@@ -40,21 +40,55 @@ use indexmap::{IndexMap, IndexSet};
 
 use yacc::Precedence;
 
+/// The name of Yacc's `error` pseudo-token.
+pub const ERROR_TOKEN: &str = "error";
+
 /// An AST representing a grammar. This is built up gradually: when it is finished, the
 /// `complete_and_validate` must be called exactly once in order to finish the set-up. At that
 /// point, any further mutations made to the struct lead to undefined behaviour.
 pub struct GrammarAST {
     pub start: Option<String>,
+    // Any further start rules (e.g. `B` and `C` in `%start A B C`), in the order declared
+    pub extra_starts: Vec<String>,
     // map from a rule name to indexes into prods
     pub rules: IndexMap<String, Vec<usize>>,
     pub prods: Vec<Production>,
     pub tokens: IndexSet<String>,
     pub precs: HashMap<String, Precedence>,
     pub implicit_tokens: Option<HashSet<String>>,
+    // Is the `error` pseudo-token referenced by any production?
+    pub error_token: bool,
     // Error pretty-printers
     pub epp: HashMap<String, String>,
+    // Synchronisation tokens for panic mode error recovery
+    pub sync: IndexSet<String>,
     pub programs: Option<String>,
-    pub actiontype: Option<String>
+    pub actiontype: Option<String>,
+    // Where in the source (as line and column) parts of the grammar were defined, so that
+    // validation errors, and code generated from actions, can point to them
+    pub(crate) locs: HashMap<Loc, (usize, usize)>
+}
+
+/// The parts of a grammar whose position in the source is recorded in `GrammarAST::locs`.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Loc {
+    /// The start rule's name (either in a `%start` declaration or, if there is none, the name of
+    /// the first rule).
+    Start,
+    /// A start rule's name other than the first in a `%start` declaration.
+    ExtraStart(String),
+    /// A symbol in a production, as (production index, symbol index).
+    Symbol(usize, usize),
+    /// The `%prec` token of a production.
+    Prec(usize),
+    /// The start of a production's action code.
+    Action(usize),
+    /// The start of the programs section.
+    Programs,
+    /// A token in a `%epp` declaration.
+    EPP(String),
+    /// A token in a `%sync` declaration.
+    Sync(String)
 }
 
 #[derive(Debug)]
@@ -84,18 +118,28 @@ pub enum GrammarValidationErrorKind {
     UnknownRuleRef,
     UnknownToken,
     NoPrecForToken,
-    UnknownEPP
+    UnknownEPP,
+    UnknownSync
 }
 
 /// `GrammarAST` validation errors return an instance of this struct.
 #[derive(Debug)]
 pub struct GrammarValidationError {
     pub kind: GrammarValidationErrorKind,
-    pub sym: Option<Symbol>
+    pub sym: Option<Symbol>,
+    line_col: Option<(usize, usize)>
 }
 
 impl Error for GrammarValidationError {}
 
+impl GrammarValidationError {
+    /// The line and column (both starting from 1) of the part of the grammar which caused the
+    /// error, if known.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        self.line_col
+    }
+}
+
 impl fmt::Display for GrammarValidationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.kind {
@@ -122,6 +166,11 @@ impl fmt::Display for GrammarValidationError {
                 f,
                 "Unknown token '{}' in %epp declaration",
                 self.sym.as_ref().unwrap()
+            ),
+            GrammarValidationErrorKind::UnknownSync => write!(
+                f,
+                "Unknown token '{}' in %sync declaration",
+                self.sym.as_ref().unwrap()
             )
         }
     }
@@ -140,15 +189,19 @@ impl GrammarAST {
     pub fn new() -> GrammarAST {
         GrammarAST {
             start: None,
+            extra_starts: Vec::new(),
             rules: IndexMap::new(), // Using an IndexMap means that we retain the order
             // of rules as they're found in the input file.
             prods: Vec::new(),
             tokens: IndexSet::new(),
             precs: HashMap::new(),
             implicit_tokens: None,
+            error_token: false,
             epp: HashMap::new(),
+            sync: IndexSet::new(),
             programs: None,
-            actiontype: None
+            actiontype: None,
+            locs: HashMap::new()
         }
     }
 
@@ -184,65 +237,76 @@ impl GrammarAST {
 
     /// After the AST has been populated, perform any final operations, and validate the grammar
     /// checking that:
-    ///   1) The start rule references a rule in the grammar
+    ///   1) The start rules reference rules in the grammar
     ///   2) Every rule reference references a rule in the grammar
-    ///   3) Every token reference references a declared token
+    ///   3) Every token reference references a declared token (or the `error` pseudo-token)
     ///   4) If a production has a precedence token, then it references a declared token
     ///   5) Every token declared with %epp matches a known token
-    /// If the validation succeeds, None is returned.
-    pub(crate) fn complete_and_validate(&mut self) -> Result<(), GrammarValidationError> {
+    ///   6) Every token declared with %sync matches a known token
+    /// If the validation succeeds, `Ok(())` is returned; otherwise every error found is returned.
+    pub(crate) fn complete_and_validate(&mut self) -> Result<(), Vec<GrammarValidationError>> {
+        let mut errs = Vec::new();
         match self.start {
-            None => {
-                return Err(GrammarValidationError {
-                    kind: GrammarValidationErrorKind::NoStartRule,
-                    sym: None
-                })
-            }
+            None => errs.push(self.mk_error(GrammarValidationErrorKind::NoStartRule, None, None)),
             Some(ref s) => {
                 if !self.rules.contains_key(s) {
-                    return Err(GrammarValidationError {
-                        kind: GrammarValidationErrorKind::InvalidStartRule,
-                        sym: Some(Symbol::Rule(s.clone()))
-                    });
+                    errs.push(self.mk_error(
+                        GrammarValidationErrorKind::InvalidStartRule,
+                        Some(Symbol::Rule(s.clone())),
+                        Some(Loc::Start)
+                    ));
                 }
             }
         }
+        for s in &self.extra_starts {
+            if !self.rules.contains_key(s) {
+                errs.push(self.mk_error(
+                    GrammarValidationErrorKind::InvalidStartRule,
+                    Some(Symbol::Rule(s.clone())),
+                    Some(Loc::ExtraStart(s.clone()))
+                ));
+            }
+        }
         for pidxs in self.rules.values() {
             for &pidx in pidxs {
                 let prod = &self.prods[pidx];
                 if let Some(ref n) = prod.precedence {
                     if !self.tokens.contains(n) {
-                        return Err(GrammarValidationError {
-                            kind: GrammarValidationErrorKind::UnknownToken,
-                            sym: Some(Symbol::Token(n.clone()))
-                        });
-                    }
-                    if !self.precs.contains_key(n) {
-                        return Err(GrammarValidationError {
-                            kind: GrammarValidationErrorKind::NoPrecForToken,
-                            sym: Some(Symbol::Token(n.clone()))
-                        });
+                        errs.push(self.mk_error(
+                            GrammarValidationErrorKind::UnknownToken,
+                            Some(Symbol::Token(n.clone())),
+                            Some(Loc::Prec(pidx))
+                        ));
+                    } else if !self.precs.contains_key(n) {
+                        errs.push(self.mk_error(
+                            GrammarValidationErrorKind::NoPrecForToken,
+                            Some(Symbol::Token(n.clone())),
+                            Some(Loc::Prec(pidx))
+                        ));
                     }
                 }
-                for sym in &prod.symbols {
-                    match *sym {
+                for (symidx, sym) in prod.symbols.iter().enumerate() {
+                    let kind = match *sym {
                         Symbol::Rule(ref name) => {
-                            if !self.rules.contains_key(name) {
-                                return Err(GrammarValidationError {
-                                    kind: GrammarValidationErrorKind::UnknownRuleRef,
-                                    sym: Some(sym.clone())
-                                });
+                            if self.rules.contains_key(name) {
+                                continue;
                             }
+                            GrammarValidationErrorKind::UnknownRuleRef
                         }
                         Symbol::Token(ref name) => {
-                            if !self.tokens.contains(name) {
-                                return Err(GrammarValidationError {
-                                    kind: GrammarValidationErrorKind::UnknownToken,
-                                    sym: Some(sym.clone())
-                                });
+                            if self.tokens.contains(name)
+                                || (self.error_token && name == ERROR_TOKEN)
+                            {
+                                continue;
                             }
+                            GrammarValidationErrorKind::UnknownToken
                         }
-                    }
+                    };
+                    errs.push(self.mk_error(
+                        kind,
+                        Some(sym.clone()),
+                        Some(Loc::Symbol(pidx, symidx))
+                    ));
                 }
             }
         }
@@ -255,12 +319,39 @@ impl GrammarAST {
                     continue;
                 }
             }
-            return Err(GrammarValidationError {
-                kind: GrammarValidationErrorKind::UnknownEPP,
-                sym: Some(Symbol::Token(k.clone()))
-            });
+            errs.push(self.mk_error(
+                GrammarValidationErrorKind::UnknownEPP,
+                Some(Symbol::Token(k.clone())),
+                Some(Loc::EPP(k.clone()))
+            ));
+        }
+        for k in &self.sync {
+            if !self.tokens.contains(k) {
+                errs.push(self.mk_error(
+                    GrammarValidationErrorKind::UnknownSync,
+                    Some(Symbol::Token(k.clone())),
+                    Some(Loc::Sync(k.clone()))
+                ));
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
+    }
+
+    fn mk_error(
+        &self,
+        kind: GrammarValidationErrorKind,
+        sym: Option<Symbol>,
+        loc: Option<Loc>
+    ) -> GrammarValidationError {
+        GrammarValidationError {
+            kind,
+            sym,
+            line_col: loc.and_then(|l| self.locs.get(&l).cloned())
         }
-        Ok(())
     }
 }
 
@@ -280,7 +371,10 @@ mod test {
     #[test]
     fn test_empty_grammar() {
         let mut grm = GrammarAST::new();
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::NoStartRule,
                 ..
@@ -294,7 +388,10 @@ mod test {
         let mut grm = GrammarAST::new();
         grm.start = Some("A".to_string());
         grm.add_prod("B".to_string(), vec![], None, None);
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::InvalidStartRule,
                 ..
@@ -303,6 +400,25 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_invalid_extra_start_rule() {
+        let mut grm = GrammarAST::new();
+        grm.start = Some("A".to_string());
+        grm.extra_starts.push("B".to_string());
+        grm.add_prod("A".to_string(), vec![], None, None);
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
+            Err(GrammarValidationError {
+                kind: GrammarValidationErrorKind::InvalidStartRule,
+                sym: Some(Symbol::Rule(ref n)),
+                ..
+            }) if n == "B" => (),
+            _ => panic!("Validation error")
+        }
+    }
+
     #[test]
     fn test_valid_start_rule() {
         let mut grm = GrammarAST::new();
@@ -325,7 +441,10 @@ mod test {
         let mut grm = GrammarAST::new();
         grm.start = Some("A".to_string());
         grm.add_prod("A".to_string(), vec![rule("B")], None, None);
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::UnknownRuleRef,
                 ..
@@ -359,7 +478,10 @@ mod test {
         let mut grm = GrammarAST::new();
         grm.start = Some("A".to_string());
         grm.add_prod("A".to_string(), vec![token("b")], None, None);
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::UnknownToken,
                 ..
@@ -373,7 +495,10 @@ mod test {
         let mut grm = GrammarAST::new();
         grm.start = Some("A".to_string());
         grm.add_prod("A".to_string(), vec![rule("b"), token("b")], None, None);
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::UnknownRuleRef,
                 ..
@@ -388,7 +513,10 @@ mod test {
         grm.start = Some("A".to_string());
         grm.add_prod("A".to_string(), vec![], None, None);
         grm.epp.insert("k".to_owned(), "v".to_owned());
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::UnknownEPP,
                 ..
@@ -397,6 +525,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_invalid_sync() {
+        let mut grm = GrammarAST::new();
+        grm.start = Some("A".to_string());
+        grm.add_prod("A".to_string(), vec![], None, None);
+        grm.sync.insert("k".to_owned());
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
+            Err(GrammarValidationError {
+                kind: GrammarValidationErrorKind::UnknownSync,
+                ..
+            }) => (),
+            _ => panic!("Validation error")
+        }
+    }
+
     #[test]
     fn test_precedence_override() {
         let mut grm = GrammarAST::new();
@@ -428,7 +574,10 @@ mod test {
             Some("b".to_string()),
             None
         );
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::UnknownToken,
                 ..
@@ -436,7 +585,10 @@ mod test {
             _ => panic!("Validation error")
         }
         grm.tokens.insert("b".to_string());
-        match grm.complete_and_validate() {
+        match grm
+            .complete_and_validate()
+            .map_err(|mut errs| errs.remove(0))
+        {
             Err(GrammarValidationError {
                 kind: GrammarValidationErrorKind::NoPrecForToken,
                 ..
@@ -30,7 +30,7 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::{cell::RefCell, collections::HashMap, error::Error, fmt};
+use std::{cell::RefCell, collections::HashMap, error::Error, fmt, iter::once};
 
 use num_traits::{self, AsPrimitive, PrimInt, Unsigned};
 
@@ -47,7 +47,7 @@ const IMPLICIT_RULE: &str = "~";
 const IMPLICIT_START_RULE: &str = "^~";
 
 use yacc::{
-    ast::{self, GrammarValidationError},
+    ast::{self, GrammarValidationError, Loc},
     parser::YaccParserError
 };
 
@@ -90,10 +90,16 @@ pub struct YaccGrammar<StorageT = u32> {
     tokens_len: TIdx<StorageT>,
     /// The offset of the EOF token.
     eof_token_idx: TIdx<StorageT>,
+    /// The offset of the `error` pseudo-token, if it is referenced by the grammar.
+    error_token_idx: Option<TIdx<StorageT>>,
+    /// The tokens declared with %sync.
+    sync_tokens: Vec<TIdx<StorageT>>,
     /// How many productions does this grammar have?
     prods_len: PIdx<StorageT>,
-    /// Which production is the sole production of the start rule?
+    /// Which production of the start rule references the first user-defined start rule?
     start_prod: PIdx<StorageT>,
+    /// The user-defined start rules, in the order they were declared.
+    start_rules: Vec<RIdx<StorageT>>,
     /// A list of all productions.
     prods: Vec<Vec<Symbol<StorageT>>>,
     /// A mapping from rules to their productions. Note that 1) the order of rules is identical to
@@ -109,12 +115,19 @@ pub struct YaccGrammar<StorageT = u32> {
     implicit_rule: Option<RIdx<StorageT>>,
     /// User defined Rust programs which can be called within actions
     actions: Vec<Option<String>>,
+    /// A mapping from `PIdx` -> the line and column at which the production's action starts in
+    /// the grammar's source (if the production has an action).
+    action_locs: Vec<Option<(usize, usize)>>,
     programs: Option<String>,
+    /// The line and column at which the programs part of the grammar starts in the grammar's
+    /// source (if there is a programs part).
+    programs_loc: Option<(usize, usize)>,
     actiontype: Option<String>
 }
 
-// Internally, we assume that a grammar's start rule has a single production. Since we manually
-// create the start rule ourselves (without relying on user input), this is a safe assumption.
+// Internally, we assume that a grammar's start rule has exactly one production for each
+// user-defined start rule, in the same order as `start_rules`. Since we manually create the start
+// rule ourselves (without relying on user input), this is a safe assumption.
 
 impl YaccGrammar<u32> {
     pub fn new(yacc_kind: YaccKind, s: &str) -> Result<Self, YaccGrammarError> {
@@ -131,15 +144,34 @@ where
     /// ([`YaccGrammarError`](grammar/enum.YaccGrammarError.html) on error).
     ///
     /// As we're compiling the `YaccGrammar`, we add a new start rule (which we'll refer to as `^`,
-    /// though the actual name is a fresh name that is guaranteed to be unique) that references the
-    /// user defined start rule.
+    /// though the actual name is a fresh name that is guaranteed to be unique) with one production
+    /// referencing each user defined start rule.
     pub fn new_with_storaget(yacc_kind: YaccKind, s: &str) -> Result<Self, YaccGrammarError> {
+        YaccGrammar::new_with_all_errors(yacc_kind, s).map_err(|mut errs| errs.remove(0))
+    }
+
+    /// As [`new_with_storaget`](#method.new_with_storaget), but if the grammar is invalid, returns
+    /// every error found rather than just the first. Parsing errors are reported in the order
+    /// they occur in `s`; if there are any, the grammar is not validated (since the resulting
+    /// errors would largely be knock-on effects of the parsing errors).
+    pub fn new_with_all_errors(
+        yacc_kind: YaccKind,
+        s: &str
+    ) -> Result<Self, Vec<YaccGrammarError>> {
         let ast = match yacc_kind {
             YaccKind::Original | YaccKind::Eco => {
                 let mut yp = YaccParser::new(yacc_kind, s.to_string());
-                yp.parse()?;
+                yp.parse().map_err(|errs| {
+                    errs.into_iter()
+                        .map(YaccGrammarError::from)
+                        .collect::<Vec<_>>()
+                })?;
                 let mut ast = yp.ast();
-                ast.complete_and_validate()?;
+                ast.complete_and_validate().map_err(|errs| {
+                    errs.into_iter()
+                        .map(YaccGrammarError::from)
+                        .collect::<Vec<_>>()
+                })?;
                 ast
             }
         };
@@ -223,6 +255,17 @@ where
         token_names.push(None);
         token_precs.push(None);
         token_epp.push(None);
+        // Like the EOF token, the `error` pseudo-token is unnamed, since it is never produced by a
+        // lexer.
+        let error_token_idx = if ast.error_token {
+            let tidx = TIdx(token_names.len().as_());
+            token_names.push(None);
+            token_precs.push(None);
+            token_epp.push(None);
+            Some(tidx)
+        } else {
+            None
+        };
         let mut token_map = HashMap::<String, TIdx<StorageT>>::new();
         for (i, v) in token_names.iter().enumerate() {
             if let Some(n) = v.as_ref() {
@@ -237,28 +280,43 @@ where
         let mut prod_precs: Vec<Option<Option<Precedence>>> = vec![None; ast.prods.len()];
         let mut prods_rules = vec![None; ast.prods.len()];
         let mut actions = vec![None; ast.prods.len()];
+        let mut action_locs = vec![None; ast.prods.len()];
         for astrulename in &rule_names {
             let ridx = rule_map[astrulename];
             if astrulename == &start_rule {
-                // Add the special start rule which has a single production which references a
-                // single rule.
-                rules_prods[usize::from(rule_map[astrulename])].push(PIdx(prods.len().as_()));
-                let start_prod = match implicit_start_rule {
-                    None => {
-                        // Add ^: S;
-                        vec![Symbol::Rule(rule_map[ast.start.as_ref().unwrap()])]
-                    }
-                    Some(ref s) => {
-                        // An implicit rule has been specified, so the special start rule
-                        // needs to reference the intermediate start rule required. Therefore add:
-                        //   ^: ^~;
-                        vec![Symbol::Rule(rule_map[s])]
-                    }
-                };
-                prods.push(Some(start_prod));
-                prod_precs.push(Some(None));
-                prods_rules.push(Some(ridx));
-                actions.push(None);
+                // Add the special start rule which has one production for each user-defined start
+                // rule, each of which references a single rule.
+                let starts = once(ast.start.as_ref().unwrap()).chain(ast.extra_starts.iter());
+                for (i, s) in starts.enumerate() {
+                    rules_prods[usize::from(ridx)].push(PIdx(prods.len().as_()));
+                    let start_prod = match implicit_start_rule {
+                        None => {
+                            // Add ^: S;
+                            vec![Symbol::Rule(rule_map[s])]
+                        }
+                        Some(ref is) if i == 0 => {
+                            // An implicit rule has been specified, so the special start rule
+                            // needs to reference the intermediate start rule required. Therefore
+                            // add:
+                            //   ^: ^~;
+                            vec![Symbol::Rule(rule_map[is])]
+                        }
+                        Some(_) => {
+                            // The intermediate start rule only handles the first start rule, so
+                            // other start rules handle implicit tokens directly:
+                            //   ^: ~ S;
+                            vec![
+                                Symbol::Rule(rule_map[implicit_rule.as_ref().unwrap()]),
+                                Symbol::Rule(rule_map[s]),
+                            ]
+                        }
+                    };
+                    prods.push(Some(start_prod));
+                    prod_precs.push(Some(None));
+                    prods_rules.push(Some(ridx));
+                    actions.push(None);
+                    action_locs.push(None);
+                }
                 continue;
             } else if implicit_start_rule
                 .as_ref()
@@ -303,7 +361,13 @@ where
                             prod.push(Symbol::Rule(rule_map[n]));
                         }
                         ast::Symbol::Token(ref n) => {
-                            prod.push(Symbol::Token(token_map[n]));
+                            match token_map.get(n) {
+                                Some(&tidx) => prod.push(Symbol::Token(tidx)),
+                                None => {
+                                    debug_assert_eq!(n, ast::ERROR_TOKEN);
+                                    prod.push(Symbol::Token(error_token_idx.unwrap()));
+                                }
+                            }
                             if implicit_rule.is_some() {
                                 prod.push(Symbol::Rule(rule_map[&implicit_rule.clone().unwrap()]));
                             }
@@ -329,10 +393,13 @@ where
                 prods_rules[prod_idx] = Some(ridx);
                 if let Some(ref s) = astprod.action {
                     actions[prod_idx] = Some(s.clone());
+                    action_locs[prod_idx] = ast.locs.get(&Loc::Action(prod_idx)).cloned();
                 }
             }
         }
 
+        let sync_tokens = ast.sync.iter().map(|n| token_map[n]).collect();
+
         assert!(!token_names.is_empty());
         assert!(!rule_names.is_empty());
         Ok(YaccGrammar {
@@ -340,17 +407,25 @@ where
             rule_names,
             tokens_len: TIdx(token_names.len().as_()),
             eof_token_idx,
+            error_token_idx,
+            sync_tokens,
             token_names,
             token_precs,
             token_epp,
             prods_len: PIdx(prods.len().as_()),
             start_prod: rules_prods[usize::from(rule_map[&start_rule])][0],
+            start_rules: once(ast.start.as_ref().unwrap())
+                .chain(ast.extra_starts.iter())
+                .map(|s| rule_map[s])
+                .collect(),
             rules_prods,
             prods_rules: prods_rules.into_iter().map(|x| x.unwrap()).collect(),
             prods: prods.into_iter().map(|x| x.unwrap()).collect(),
             prod_precs: prod_precs.into_iter().map(|x| x.unwrap()).collect(),
             implicit_rule: implicit_rule.and_then(|x| Some(rule_map[&x])),
             actions,
+            action_locs,
+            programs_loc: ast.locs.get(&Loc::Programs).cloned(),
             programs: ast.programs,
             actiontype: ast.actiontype
         })
@@ -361,6 +436,12 @@ where
         &self.programs
     }
 
+    /// Get the line and column (both starting from 1) in the grammar's source at which the
+    /// programs part of the grammar starts, if there is one.
+    pub fn programs_line_col(&self) -> Option<(usize, usize)> {
+        self.programs_loc
+    }
+
     /// Get the action return type as defined by the user
     pub fn actiontype(&self) -> &Option<String> {
         &self.actiontype
@@ -390,6 +471,12 @@ where
         &self.actions[usize::from(pidx)]
     }
 
+    /// Get the line and column (both starting from 1) in the grammar's source at which the action
+    /// for production `pidx` starts, if it has an action. Panics if `pidx` doesn't exist.
+    pub fn action_line_col(&self, pidx: PIdx<StorageT>) -> Option<(usize, usize)> {
+        self.action_locs[usize::from(pidx)]
+    }
+
     /// How many symbols does production `pidx` have? Panics if `pidx` doesn't exist.
     pub fn prod_len(&self, pidx: PIdx<StorageT>) -> SIdx<StorageT> {
         // Since we've already checked that StorageT can store all the symbols for every production
@@ -408,12 +495,19 @@ where
         self.prod_precs[usize::from(pidx)]
     }
 
-    /// Return the production index of the start rule's sole production (for Yacc grammars the
-    /// start rule is defined to have precisely one production).
+    /// Return the production index of the start rule's production for the first user-defined
+    /// start rule (the start rule has one production for each of
+    /// [`start_rules`](#method.start_rules), in the same order).
     pub fn start_prod(&self) -> PIdx<StorageT> {
         self.start_prod
     }
 
+    /// Return the user-defined start rules: those named in the `%start` declaration, in the order
+    /// they were declared, or, if there is no `%start` declaration, the first rule.
+    pub fn start_rules(&self) -> &[RIdx<StorageT>] {
+        &self.start_rules
+    }
+
     /// How many rules does this grammar have?
     pub fn rules_len(&self) -> RIdx<StorageT> {
         self.rules_len
@@ -476,6 +570,18 @@ where
         self.eof_token_idx
     }
 
+    /// Return the index of Yacc's `error` pseudo-token if it is referenced by the grammar, or
+    /// `None` otherwise.
+    pub fn error_token_idx(&self) -> Option<TIdx<StorageT>> {
+        self.error_token_idx
+    }
+
+    /// Return the tokens declared with `%sync`, which panic mode error recovery uses to
+    /// resynchronise after an error.
+    pub fn sync_tokens(&self) -> &[TIdx<StorageT>] {
+        &self.sync_tokens
+    }
+
     /// Return the name of token `tidx` (where `None` indicates "the rule has no name"). Panics if
     /// `tidx` doesn't exist.
     pub fn token_name(&self, tidx: TIdx<StorageT>) -> Option<&str> {
@@ -972,6 +1078,17 @@ pub enum YaccGrammarError {
 
 impl Error for YaccGrammarError {}
 
+impl YaccGrammarError {
+    /// The line and column (both starting from 1) in the grammar's source that the error relates
+    /// to, if known.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        match *self {
+            YaccGrammarError::YaccParserError(ref e) => Some(e.line_col()),
+            YaccGrammarError::GrammarValidationError(ref e) => e.line_col()
+        }
+    }
+}
+
 impl From<YaccParserError> for YaccGrammarError {
     fn from(err: YaccParserError) -> YaccGrammarError {
         YaccGrammarError::YaccParserError(err)
@@ -997,7 +1114,10 @@ impl fmt::Display for YaccGrammarError {
 mod test {
     use super::{rule_max_costs, rule_min_costs, IMPLICIT_RULE, IMPLICIT_START_RULE};
     use std::collections::HashMap;
-    use yacc::{AssocKind, Precedence, YaccGrammar, YaccKind};
+    use yacc::{
+        AssocKind, GrammarValidationError, GrammarValidationErrorKind, Precedence, YaccGrammar,
+        YaccGrammarError, YaccKind
+    };
     use PIdx;
     use RIdx;
     use Symbol;
@@ -1030,6 +1150,162 @@ mod test {
         assert_eq!(grm.iter_rules().collect::<Vec<_>>(), vec![RIdx(0), RIdx(1)]);
     }
 
+    #[test]
+    fn test_error_token() {
+        let grm = YaccGrammar::new(YaccKind::Original, "%start R %token T %% R: 'T';").unwrap();
+        assert_eq!(grm.error_token_idx(), None);
+
+        let grm = YaccGrammar::new(
+            YaccKind::Original,
+            "%start R %token T %% R: 'T' | error 'T';"
+        )
+        .unwrap();
+        let error_tidx = grm.error_token_idx().unwrap();
+        assert_ne!(error_tidx, grm.eof_token_idx());
+        assert_eq!(grm.tokens_len(), TIdx(3));
+        assert!(grm.token_name(error_tidx).is_none());
+        assert_eq!(grm.token_idx("error"), None);
+        let r_prod = grm.prod(grm.rules_prods[usize::from(grm.rule_idx("R").unwrap())][1]);
+        assert_eq!(
+            *r_prod,
+            [
+                Symbol::Token(error_tidx),
+                Symbol::Token(grm.token_idx("T").unwrap())
+            ]
+        );
+        assert_eq!(
+            grm.tokens_map(),
+            [("T", TIdx(0))]
+                .iter()
+                .cloned()
+                .collect::<HashMap<&str, TIdx<_>>>()
+        );
+    }
+
+    #[test]
+    fn test_all_errors() {
+        let src = "%start Q
+%token T
+%sync 'U'
+%%
+R: S;
+S: T V W;
+";
+        let errs = match YaccGrammar::<u32>::new_with_all_errors(YaccKind::Original, src) {
+            Ok(_) => panic!("Invalid grammar accepted"),
+            Err(errs) => errs
+                .iter()
+                .map(|e| (e.to_string(), e.line_col()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            errs,
+            vec![
+                (
+                    "Start rule 'Q' does not appear in grammar".to_string(),
+                    Some((1, 8))
+                ),
+                ("Unknown reference to rule 'V'".to_string(), Some((6, 6))),
+                ("Unknown reference to rule 'W'".to_string(), Some((6, 8))),
+                (
+                    "Unknown token 'U' in %sync declaration".to_string(),
+                    Some((3, 7))
+                ),
+            ]
+        );
+        match YaccGrammar::new(YaccKind::Original, src) {
+            Err(YaccGrammarError::GrammarValidationError(GrammarValidationError {
+                kind: GrammarValidationErrorKind::InvalidStartRule,
+                ..
+            })) => (),
+            _ => panic!("Validation error")
+        }
+    }
+
+    #[test]
+    fn test_multiple_starts() {
+        let grm = YaccGrammar::new(YaccKind::Original, "%start R S %% R: 'a'; S: 'b' R;").unwrap();
+        let r_ridx = grm.rule_idx("R").unwrap();
+        let s_ridx = grm.rule_idx("S").unwrap();
+        assert_eq!(grm.start_rules(), &[r_ridx, s_ridx]);
+        let start_prods = grm.rule_to_prods(grm.start_rule_idx());
+        assert_eq!(start_prods.len(), 2);
+        assert_eq!(start_prods[0], grm.start_prod());
+        assert_eq!(*grm.prod(start_prods[0]), [Symbol::Rule(r_ridx)]);
+        assert_eq!(*grm.prod(start_prods[1]), [Symbol::Rule(s_ridx)]);
+
+        // Only the first start rule goes via the intermediate start rule.
+        let grm = YaccGrammar::new(
+            YaccKind::Eco,
+            "%implicit_tokens ws\n%start R S\n%%\nR: 'a';\nS: 'b' R;"
+        )
+        .unwrap();
+        let start_prods = grm.rule_to_prods(grm.start_rule_idx());
+        assert_eq!(
+            *grm.prod(start_prods[0]),
+            [Symbol::Rule(grm.rule_idx(IMPLICIT_START_RULE).unwrap())]
+        );
+        assert_eq!(
+            *grm.prod(start_prods[1]),
+            [
+                Symbol::Rule(grm.implicit_rule().unwrap()),
+                Symbol::Rule(grm.rule_idx("S").unwrap())
+            ]
+        );
+
+        let grm = YaccGrammar::new(YaccKind::Original, "%% R: 'a'; S: 'b' R;").unwrap();
+        assert_eq!(grm.start_rules(), &[grm.rule_idx("R").unwrap()]);
+
+        match YaccGrammar::new(YaccKind::Original, "%start R T %% R: 'a';") {
+            Err(YaccGrammarError::GrammarValidationError(GrammarValidationError {
+                kind: GrammarValidationErrorKind::InvalidStartRule,
+                ..
+            })) => (),
+            _ => panic!("Incorrect error returned")
+        }
+    }
+
+    #[test]
+    fn test_action_line_col() {
+        let grm = YaccGrammar::new(
+            YaccKind::Original,
+            "%start A
+%%
+A: 'a' {  f($1) }
+ | B { g(
+       $1) }
+ | ;
+B: 'b';
+%%
+fn f() {}"
+        )
+        .unwrap();
+        let a_prods = grm.rule_to_prods(grm.rule_idx("A").unwrap());
+        assert_eq!(grm.action_line_col(a_prods[0]), Some((3, 11)));
+        assert_eq!(grm.action_line_col(a_prods[1]), Some((4, 8)));
+        assert_eq!(grm.action_line_col(a_prods[2]), None);
+        assert_eq!(grm.action_line_col(grm.start_prod()), None);
+        assert_eq!(grm.programs_line_col(), Some((9, 1)));
+    }
+
+    #[test]
+    fn test_sync_tokens() {
+        let grm = YaccGrammar::new(
+            YaccKind::Original,
+            "%start R %sync ';' %% R: 'T' ';' | R 'T' ';';"
+        )
+        .unwrap();
+        assert_eq!(grm.sync_tokens(), &[grm.token_idx(";").unwrap()]);
+
+        match YaccGrammar::new(YaccKind::Original, "%start R %sync ',' %% R: 'T';") {
+            Err(YaccGrammarError::GrammarValidationError(GrammarValidationError {
+                kind: GrammarValidationErrorKind::UnknownSync,
+                ..
+            })) => (),
+            _ => panic!("Validation error")
+        }
+    }
+
     #[test]
     fn test_rule_ref() {
         let grm =
@@ -42,11 +42,13 @@ pub use self::{
     parser::{YaccParserError, YaccParserErrorKind}
 };
 
-/// The particular Yacc variant this grammar makes use of.
+/// The particular Yacc variant this grammar makes use of. In all variants, productions can
+/// reference the special `error` pseudo-token (e.g. `stmt: error ';';`), which is used by the
+/// parser to recover from errors.
 #[derive(Clone, Copy)]
 pub enum YaccKind {
     /// The original Yacc style as documented by
-    /// [Johnson](http://dinosaur.compilertools.net/yacc/index.html)
+    /// [Johnson](http://dinosaur.compilertools.net/yacc/index.html).
     Original,
     /// The variant used in the [Eco language composition editor](http://soft-dev.org/src/eco/)
     Eco
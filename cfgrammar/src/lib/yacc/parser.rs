@@ -32,7 +32,7 @@
 
 // Note: this is the parser for both YaccKind::Original and YaccKind::Eco yacc kinds.
 
-use std::{collections::HashSet, error::Error, fmt};
+use std::{collections::HashSet, error::Error, fmt, mem};
 
 extern crate regex;
 use self::regex::Regex;
@@ -40,7 +40,7 @@ use self::regex::Regex;
 type YaccResult<T> = Result<T, YaccParserError>;
 
 use yacc::{
-    ast::{GrammarAST, Symbol},
+    ast::{GrammarAST, Loc, Symbol, ERROR_TOKEN},
     AssocKind, Precedence, YaccKind
 };
 
@@ -75,9 +75,16 @@ pub struct YaccParserError {
 
 impl Error for YaccParserError {}
 
-impl fmt::Display for YaccParserError {
+impl YaccParserError {
+    /// The line and column (both starting from 1) at which the error was found.
+    pub fn line_col(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+}
+
+impl fmt::Display for YaccParserErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let s = match self.kind {
+        let s = match *self {
             YaccParserErrorKind::IllegalName => "Illegal name",
             YaccParserErrorKind::IllegalString => "Illegal string",
             YaccParserErrorKind::IncompleteRule => "Incomplete rule",
@@ -99,7 +106,13 @@ impl fmt::Display for YaccParserError {
             }
             YaccParserErrorKind::InvalidString => "Invalid string"
         };
-        write!(f, "{} at line {} column {}", s, self.line, self.col)
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for YaccParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {} column {}", self.kind, self.line, self.col)
     }
 }
 
@@ -107,7 +120,10 @@ pub(crate) struct YaccParser {
     yacc_kind: YaccKind,
     src: String,
     newlines: Vec<usize>,
-    ast: GrammarAST
+    ast: GrammarAST,
+    /// The errors found so far. Where possible, the parser skips past an error and carries on, so
+    /// that as many errors as possible can be reported at once.
+    errs: Vec<YaccParserError>
 }
 
 lazy_static! {
@@ -123,11 +139,25 @@ impl YaccParser {
             yacc_kind,
             src,
             newlines: vec![0],
-            ast: GrammarAST::new()
+            ast: GrammarAST::new(),
+            errs: Vec::new()
         }
     }
 
-    pub(crate) fn parse(&mut self) -> YaccResult<usize> {
+    /// Parse the input, returning all the errors found (in the order they occur in the input) if
+    /// it is not a valid grammar.
+    pub(crate) fn parse(&mut self) -> Result<usize, Vec<YaccParserError>> {
+        match self.parse_sections() {
+            Ok(i) if self.errs.is_empty() => Ok(i),
+            Ok(_) => Err(mem::replace(&mut self.errs, Vec::new())),
+            Err(e) => {
+                self.errs.push(e);
+                Err(mem::replace(&mut self.errs, Vec::new()))
+            }
+        }
+    }
+
+    fn parse_sections(&mut self) -> YaccResult<usize> {
         // We pass around an index into the *bytes* of self.src. We guarantee that at all times
         // this points to the beginning of a UTF-8 character (since multibyte characters exist, not
         // every byte within the string is also a valid character).
@@ -147,105 +177,149 @@ impl YaccParser {
             if self.lookahead_is("%%", i).is_some() {
                 return Ok(i);
             }
-            if let Some(j) = self.lookahead_is("%token", i) {
-                i = self.parse_ws(j, false)?;
-                while i < self.src.len() {
-                    if self.lookahead_is("%", i).is_some() {
-                        break;
-                    }
-                    let (j, n) = self.parse_token(i)?;
-                    self.ast.tokens.insert(n);
-                    i = self.parse_ws(j, true)?;
+            i = match self.parse_declaration(i, &mut prec_level) {
+                Ok(j) => j,
+                Err(e) => {
+                    self.errs.push(e);
+                    self.skip_declaration(i)
                 }
-                continue;
-            }
-            if let Some(j) = self.lookahead_is("%type", i) {
-                i = self.parse_ws(j, false)?;
-                while i < self.src.len() {
-                    if self.lookahead_is("%", i).is_some() {
-                        break;
-                    }
-                    let (j, n) = self.parse_name(i)?;
-                    self.ast.actiontype = Some(n);
-                    i = self.parse_ws(j, true)?;
+            };
+        }
+        Err(self.mk_error(YaccParserErrorKind::PrematureEnd, i - 1))
+    }
+
+    /// Parse the declaration starting at `i`. `prec_level` is the precedence level that the next
+    /// `%left`, `%right`, or `%nonassoc` declaration will be given.
+    fn parse_declaration(&mut self, mut i: usize, prec_level: &mut u64) -> YaccResult<usize> {
+        if let Some(j) = self.lookahead_is("%token", i) {
+            i = self.parse_ws(j, false)?;
+            while i < self.src.len() {
+                if self.lookahead_is("%", i).is_some() {
+                    break;
                 }
-                continue;
+                let (j, n) = self.parse_token(i)?;
+                self.ast.tokens.insert(n);
+                i = self.parse_ws(j, true)?;
             }
-            if let Some(j) = self.lookahead_is("%start", i) {
-                if self.ast.start.is_some() {
-                    return Err(self.mk_error(YaccParserErrorKind::DuplicateStartDeclaration, i));
+            return Ok(i);
+        }
+        if let Some(j) = self.lookahead_is("%type", i) {
+            i = self.parse_ws(j, false)?;
+            while i < self.src.len() {
+                if self.lookahead_is("%", i).is_some() {
+                    break;
                 }
-                i = self.parse_ws(j, false)?;
                 let (j, n) = self.parse_name(i)?;
-                self.ast.start = Some(n);
+                self.ast.actiontype = Some(n);
                 i = self.parse_ws(j, true)?;
-                continue;
             }
-            if let Some(j) = self.lookahead_is("%epp", i) {
-                i = self.parse_ws(j, false)?;
-                let (j, n) = self.parse_token(i)?;
-                if self.ast.epp.contains_key(&n) {
-                    return Err(self.mk_error(YaccParserErrorKind::DuplicateEPP, i));
+            return Ok(i);
+        }
+        if let Some(j) = self.lookahead_is("%start", i) {
+            if self.ast.start.is_some() {
+                return Err(self.mk_error(YaccParserErrorKind::DuplicateStartDeclaration, i));
+            }
+            i = self.parse_ws(j, false)?;
+            let (j, n) = self.parse_name(i)?;
+            let lc = self.off_to_line_col(i);
+            self.ast.locs.insert(Loc::Start, lc);
+            self.ast.start = Some(n);
+            i = self.parse_ws(j, true)?;
+            // Any further names are extra start rules (repeated names are ignored).
+            while i < self.src.len() {
+                if self.lookahead_is("%", i).is_some() {
+                    break;
+                }
+                let (j, n) = self.parse_name(i)?;
+                if self.ast.start.as_ref() != Some(&n) && !self.ast.extra_starts.contains(&n) {
+                    let lc = self.off_to_line_col(i);
+                    self.ast.locs.insert(Loc::ExtraStart(n.clone()), lc);
+                    self.ast.extra_starts.push(n);
                 }
-                i = self.parse_ws(j, false)?;
-                let (j, v) = self.parse_string(i)?;
-                self.ast.epp.insert(n, v);
                 i = self.parse_ws(j, true)?;
-                continue;
             }
-            if let YaccKind::Eco = self.yacc_kind {
-                if let Some(j) = self.lookahead_is("%implicit_tokens", i) {
-                    if self.ast.implicit_tokens.is_some() {
-                        return Err(self
-                            .mk_error(YaccParserErrorKind::DuplicateImplicitTokensDeclaration, i));
-                    }
-                    let mut implicit_tokens = HashSet::new();
-                    i = self.parse_ws(j, false)?;
-                    let num_newlines = self.newlines.len();
-                    while j < self.src.len() && self.newlines.len() == num_newlines {
-                        let (j, n) = self.parse_token(i)?;
-                        self.ast.tokens.insert(n.clone());
-                        implicit_tokens.insert(n);
-                        i = self.parse_ws(j, true)?;
-                    }
-                    self.ast.implicit_tokens = Some(implicit_tokens);
-                    continue;
+            return Ok(i);
+        }
+        if let Some(j) = self.lookahead_is("%sync", i) {
+            i = self.parse_ws(j, false)?;
+            while i < self.src.len() {
+                if self.lookahead_is("%", i).is_some() {
+                    break;
                 }
+                let (j, n) = self.parse_token(i)?;
+                let lc = self.off_to_line_col(i);
+                self.ast.locs.insert(Loc::Sync(n.clone()), lc);
+                self.ast.sync.insert(n);
+                i = self.parse_ws(j, true)?;
             }
-            {
-                let k;
-                let kind;
-                if let Some(j) = self.lookahead_is("%left", i) {
-                    kind = AssocKind::Left;
-                    k = j;
-                } else if let Some(j) = self.lookahead_is("%right", i) {
-                    kind = AssocKind::Right;
-                    k = j;
-                } else if let Some(j) = self.lookahead_is("%nonassoc", i) {
-                    kind = AssocKind::Nonassoc;
-                    k = j;
-                } else {
-                    return Err(self.mk_error(YaccParserErrorKind::UnknownDeclaration, i));
+            return Ok(i);
+        }
+        if let Some(j) = self.lookahead_is("%epp", i) {
+            i = self.parse_ws(j, false)?;
+            let n_off = i;
+            let (j, n) = self.parse_token(i)?;
+            if self.ast.epp.contains_key(&n) {
+                return Err(self.mk_error(YaccParserErrorKind::DuplicateEPP, i));
+            }
+            i = self.parse_ws(j, false)?;
+            let (j, v) = self.parse_string(i)?;
+            let lc = self.off_to_line_col(n_off);
+            self.ast.locs.insert(Loc::EPP(n.clone()), lc);
+            self.ast.epp.insert(n, v);
+            i = self.parse_ws(j, true)?;
+            return Ok(i);
+        }
+        if let YaccKind::Eco = self.yacc_kind {
+            if let Some(j) = self.lookahead_is("%implicit_tokens", i) {
+                if self.ast.implicit_tokens.is_some() {
+                    return Err(
+                        self.mk_error(YaccParserErrorKind::DuplicateImplicitTokensDeclaration, i)
+                    );
                 }
-
-                i = self.parse_ws(k, false)?;
+                let mut implicit_tokens = HashSet::new();
+                i = self.parse_ws(j, false)?;
                 let num_newlines = self.newlines.len();
-                while i < self.src.len() && num_newlines == self.newlines.len() {
+                while j < self.src.len() && self.newlines.len() == num_newlines {
                     let (j, n) = self.parse_token(i)?;
-                    if self.ast.precs.contains_key(&n) {
-                        return Err(self.mk_error(YaccParserErrorKind::DuplicatePrecedence, i));
-                    }
-                    let prec = Precedence {
-                        level: prec_level,
-                        kind
-                    };
-                    self.ast.precs.insert(n, prec);
+                    self.ast.tokens.insert(n.clone());
+                    implicit_tokens.insert(n);
                     i = self.parse_ws(j, true)?;
                 }
-                prec_level += 1;
+                self.ast.implicit_tokens = Some(implicit_tokens);
+                return Ok(i);
             }
         }
-        Err(self.mk_error(YaccParserErrorKind::PrematureEnd, i - 1))
+        let k;
+        let kind;
+        if let Some(j) = self.lookahead_is("%left", i) {
+            kind = AssocKind::Left;
+            k = j;
+        } else if let Some(j) = self.lookahead_is("%right", i) {
+            kind = AssocKind::Right;
+            k = j;
+        } else if let Some(j) = self.lookahead_is("%nonassoc", i) {
+            kind = AssocKind::Nonassoc;
+            k = j;
+        } else {
+            return Err(self.mk_error(YaccParserErrorKind::UnknownDeclaration, i));
+        }
+
+        i = self.parse_ws(k, false)?;
+        let num_newlines = self.newlines.len();
+        while i < self.src.len() && num_newlines == self.newlines.len() {
+            let (j, n) = self.parse_token(i)?;
+            if self.ast.precs.contains_key(&n) {
+                return Err(self.mk_error(YaccParserErrorKind::DuplicatePrecedence, i));
+            }
+            let prec = Precedence {
+                level: *prec_level,
+                kind
+            };
+            self.ast.precs.insert(n, prec);
+            i = self.parse_ws(j, true)?;
+        }
+        *prec_level += 1;
+        Ok(i)
     }
 
     fn parse_rules(&mut self, mut i: usize) -> YaccResult<usize> {
@@ -256,7 +330,13 @@ impl YaccParser {
             if self.lookahead_is("%%", i).is_some() {
                 break;
             }
-            i = self.parse_rule(i)?;
+            i = match self.parse_rule(i) {
+                Ok(j) => j,
+                Err(e) => {
+                    self.errs.push(e);
+                    self.skip_rule(i)
+                }
+            };
             i = self.parse_ws(i, true)?;
         }
         Ok(i)
@@ -265,6 +345,8 @@ impl YaccParser {
     fn parse_rule(&mut self, mut i: usize) -> YaccResult<usize> {
         let (j, rn) = self.parse_name(i)?;
         if self.ast.start.is_none() {
+            let lc = self.off_to_line_col(i);
+            self.ast.locs.insert(Loc::Start, lc);
             self.ast.start = Some(rn.clone());
         }
         i = self.parse_ws(j, true)?;
@@ -291,8 +373,13 @@ impl YaccParser {
                 return Ok(j);
             }
 
+            // The index the production currently being parsed will have when it's added to the AST,
+            // used to record where its parts are in the source.
+            let pidx = self.ast.prods.len();
             if self.lookahead_is("\"", i).is_some() || self.lookahead_is("'", i).is_some() {
                 let (j, sym) = self.parse_token(i)?;
+                let lc = self.off_to_line_col(i);
+                self.ast.locs.insert(Loc::Symbol(pidx, syms.len()), lc);
                 i = self.parse_ws(j, true)?;
                 self.ast.tokens.insert(sym.clone());
                 syms.push(Symbol::Token(sym));
@@ -300,6 +387,8 @@ impl YaccParser {
                 i = self.parse_ws(j, true)?;
                 let (k, sym) = self.parse_token(i)?;
                 if self.ast.tokens.contains(&sym) {
+                    let lc = self.off_to_line_col(i);
+                    self.ast.locs.insert(Loc::Prec(pidx), lc);
                     prec = Some(sym);
                 } else {
                     return Err(self.mk_error(YaccParserErrorKind::PrecNotFollowedByToken, i));
@@ -307,12 +396,22 @@ impl YaccParser {
                 i = k;
             } else if self.lookahead_is("{", i).is_some() {
                 let (j, a) = self.parse_action(i)?;
+                // Record where the action's (trimmed) code starts.
+                let a_src = &self.src[i + 1..];
+                let a_off = i + 1 + a_src.len() - a_src.trim_left().len();
+                let lc = self.off_to_line_col(a_off);
+                self.ast.locs.insert(Loc::Action(pidx), lc);
                 i = j;
                 action = Some(a);
             } else {
                 let (j, sym) = self.parse_token(i)?;
+                let lc = self.off_to_line_col(i);
+                self.ast.locs.insert(Loc::Symbol(pidx, syms.len()), lc);
                 if self.ast.tokens.contains(&sym) {
                     syms.push(Symbol::Token(sym));
+                } else if sym == ERROR_TOKEN {
+                    self.ast.error_token = true;
+                    syms.push(Symbol::Token(sym));
                 } else {
                     syms.push(Symbol::Rule(sym));
                 }
@@ -372,7 +471,7 @@ impl YaccParser {
         if c > 0 {
             Err(self.mk_error(YaccParserErrorKind::IncompleteAction, j))
         } else {
-            let s = self.src[i + 1..j - 1].trim().to_string();
+            let s = self.src[i + 1..j].trim().to_string();
             Ok((j + 1, s))
         }
     }
@@ -384,6 +483,8 @@ impl YaccParser {
                 Ok(i)
             } else {
                 let prog = self.src[i..].to_string();
+                let lc = self.off_to_line_col(i);
+                self.ast.locs.insert(Loc::Programs, lc);
                 i += prog.len();
                 self.ast.add_programs(prog);
                 Ok(i)
@@ -515,6 +616,71 @@ impl YaccParser {
         Ok(i)
     }
 
+    /// After an error in the declaration starting at `i`, skip to the start of the next
+    /// declaration (a `%` which follows whitespace and isn't part of a quoted token) or the end of
+    /// the input.
+    fn skip_declaration(&mut self, i: usize) -> usize {
+        let mut j = i + self.src[i..].chars().next().unwrap().len_utf8();
+        let mut quote = None;
+        while j < self.src.len() {
+            let c = self.src[j..].chars().next().unwrap();
+            match c {
+                '\n' | '\r' => {
+                    self.skip_newline(j);
+                    quote = None;
+                }
+                _ if quote.is_some() => {
+                    if Some(c) == quote {
+                        quote = None;
+                    }
+                }
+                '\'' | '"' => quote = Some(c),
+                '%' if self.src[..j].ends_with(char::is_whitespace) => return j,
+                _ => ()
+            }
+            j += c.len_utf8();
+        }
+        j
+    }
+
+    /// After an error in the rule starting at `i`, skip past the `;` which ends it (ignoring any
+    /// in quoted tokens or actions), stopping early at `%%` or the end of the input.
+    fn skip_rule(&mut self, i: usize) -> usize {
+        let mut j = i;
+        let mut braces = 0;
+        let mut quote = None;
+        while j < self.src.len() {
+            let c = self.src[j..].chars().next().unwrap();
+            match c {
+                '\n' | '\r' => {
+                    self.skip_newline(j);
+                    quote = None;
+                }
+                _ if quote.is_some() => {
+                    if Some(c) == quote {
+                        quote = None;
+                    }
+                }
+                '\'' | '"' if braces == 0 => quote = Some(c),
+                '{' => braces += 1,
+                '}' if braces > 0 => braces -= 1,
+                ';' if braces == 0 => return j + 1,
+                '%' if braces == 0 && self.lookahead_is("%%", j).is_some() => return j,
+                _ => ()
+            }
+            j += c.len_utf8();
+        }
+        j
+    }
+
+    /// Record the newline at `i` while skipping input after an error, unless the failed parse had
+    /// already recorded it.
+    fn skip_newline(&mut self, i: usize) {
+        if i + 1 > *self.newlines.last().unwrap() {
+            self.newlines.push(i + 1);
+        }
+    }
+
     fn lookahead_is(&self, s: &'static str, i: usize) -> Option<usize> {
         if self.src[i..].starts_with(s) {
             Some(i + s.len())
@@ -561,7 +727,7 @@ mod test {
 
     fn parse(yacc_kind: YaccKind, s: &str) -> Result<GrammarAST, YaccParserError> {
         let mut yp = YaccParser::new(yacc_kind, s.to_string());
-        yp.parse()?;
+        yp.parse().map_err(|mut errs| errs.remove(0))?;
         Ok(yp.ast())
     }
 
@@ -604,6 +770,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_error_token() {
+        let src = "
+            %%
+            A : error ';' | 'b';
+        "
+        .to_string();
+        for &yk in &[YaccKind::Original, YaccKind::Eco] {
+            let grm = parse(yk, &src).unwrap();
+            assert!(grm.error_token);
+            assert!(!grm.tokens.contains("error"));
+            assert_eq!(
+                grm.prods[grm.get_rule("A").unwrap()[0]],
+                Production {
+                    symbols: vec![token("error"), token(";")],
+                    precedence: None,
+                    action: None
+                }
+            );
+        }
+    }
+
     #[test]
     fn test_rule_production_simple() {
         let src = "
@@ -929,6 +1117,34 @@ x"
         }
     }
 
+    #[test]
+    fn test_multiple_errors() {
+        let src = "%token a
+%woo x
+%left 'b' 'b'
+%%
+A: B a | 'c';
+B a;
+C: '{' { ; } | ;
+D";
+        let mut yp = YaccParser::new(YaccKind::Original, src.to_string());
+        let errs = yp
+            .parse()
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errs,
+            vec![
+                "Unknown declaration at line 2 column 1",
+                "Token already has a precedence at line 3 column 11",
+                "Missing colon at line 6 column 3",
+                "Missing colon at line 8 column 2"
+            ]
+        );
+    }
+
     #[test]
     #[rustfmt::skip]
     fn test_unknown_declaration() {
@@ -1162,6 +1378,23 @@ x"
         assert_eq!(ast.epp["G"], "a\"b");
     }
 
+    #[test]
+    fn test_sync() {
+        let ast = parse(
+            YaccKind::Original,
+            &"
+          %sync ';' '}'
+          %sync ','
+          %%
+          "
+        )
+        .unwrap();
+        assert_eq!(
+            ast.sync.iter().map(|x| x.as_str()).collect::<Vec<_>>(),
+            vec![";", "}", ","]
+        );
+    }
+
     #[test]
     fn test_duplicate_epp() {
         match parse(
@@ -1215,6 +1448,14 @@ x"
         }
     }
 
+    #[test]
+    fn test_declaration_multiple_starts() {
+        let src = "%start A B\n  C A\n%%\nA : a;\nB : b;\nC : c;".to_string();
+        let grm = parse(YaccKind::Original, &src).unwrap();
+        assert_eq!(grm.start.unwrap(), "A");
+        assert_eq!(grm.extra_starts, vec!["B".to_string(), "C".to_string()]);
+    }
+
     #[test]
     fn test_duplicate_start() {
         match parse(
@@ -1261,6 +1502,8 @@ x"
           B: 'b' 'c' { add($1, $2); }
            | 'd'
            ;
+          C: 'e' {$1}
+           ;
           "
         )
         .unwrap();
@@ -1273,6 +1516,7 @@ x"
             Some("add($1, $2);".to_string())
         );
         assert_eq!(grm.prods[grm.rules["B"][1]].action, None);
+        assert_eq!(grm.prods[grm.rules["C"][0]].action, Some("$1".to_string()));
     }
 
     #[test]
@@ -0,0 +1,111 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Compare the time taken by `LRLexer` to lex input with a lexer that has many keyword rules
+//! against a naive lexer which tries every rule's regular expression at each position. Run with
+//! `cargo bench`.
+
+extern crate lrlex;
+extern crate lrpar;
+
+use std::time::{Duration, Instant};
+
+use lrlex::{build_lex, LexerDef};
+use lrpar::Lexer;
+
+const NUM_KEYWORDS: usize = 150;
+const NUM_ITERS: usize = 10;
+
+/// Lex `s` by trying each of `lexerdef`'s rules in turn at each position, returning the number
+/// of lexemes (including skipped lexemes).
+fn naive_lex(lexerdef: &LexerDef<u32>, s: &str) -> usize {
+    let mut i = 0;
+    let mut n = 0;
+    while i < s.len() {
+        let mut longest = 0;
+        for r in lexerdef.iter_rules() {
+            if let Some(m) = r.re.find(&s[i..]) {
+                if m.end() > longest {
+                    longest = m.end();
+                }
+            }
+        }
+        assert!(longest > 0);
+        i += longest;
+        n += 1;
+    }
+    n
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let before = Instant::now();
+    for _ in 0..NUM_ITERS {
+        f();
+    }
+    before.elapsed() / NUM_ITERS as u32
+}
+
+fn main() {
+    let mut src = "%%\n".to_owned();
+    for i in 0..NUM_KEYWORDS {
+        src.push_str(&format!("kw{} 'KW{}'\n", i, i));
+    }
+    src.push_str("[a-z][a-z0-9]* 'ID'\n[0-9]+ 'INT'\n[ \\n] ;\n");
+    let lexerdef = build_lex::<u32>(&src).unwrap();
+
+    let mut input = String::new();
+    for i in 0..20_000 {
+        match i % 4 {
+            0 => input.push_str(&format!("kw{} ", i % NUM_KEYWORDS)),
+            1 => input.push_str(&format!("ident{} ", i)),
+            2 => input.push_str(&format!("{}\n", i)),
+            _ => input.push_str("x ")
+        }
+    }
+
+    let mut n = 0;
+    let automaton = time(|| {
+        let lexemes = lexerdef.lexer(&input).all_lexemes().unwrap();
+        n = lexemes.len();
+    });
+    let naive = time(|| {
+        naive_lex(&lexerdef, &input);
+    });
+    println!(
+        "Lexed {} bytes ({} lexemes) with {} rules:",
+        input.len(),
+        n,
+        lexerdef.iter_rules().count()
+    );
+    println!("  LRLexer: {:?}", automaton);
+    println!("  naive:   {:?}", naive);
+}
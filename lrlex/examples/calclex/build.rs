@@ -1,6 +1,8 @@
 extern crate lrlex;
 
-use lrlex::LexerBuilder;
+use std::{env::var, path::PathBuf};
+
+use lrlex::{LexerBuilder, LexerKind};
 
 fn main() {
     // Note that we specify the integer type (u8) we'll use for token IDs (this type *must* be big
@@ -8,4 +10,20 @@ fn main() {
     LexerBuilder::<u8>::new()
         .process_file_in_src("calc.l")
         .unwrap();
+
+    // We also generate a standalone table-driven lexer from the same file, which doesn't need to
+    // compile regular expressions at run-time. Since `process_file_in_src` would give it the same
+    // module name, we specify the output file explicitly.
+    let mut outp = PathBuf::from(var("OUT_DIR").unwrap());
+    outp.push("calc_dfa_l.rs");
+    LexerBuilder::<u8>::new()
+        .lexer_kind(LexerKind::DFA)
+        .process_file("src/calc.l", outp)
+        .unwrap();
+
+    // A lexer whose comment rule uses a callback (defined in `comment.l`'s routines section) to
+    // skip nested comments, which the tests in `src/main.rs` use.
+    LexerBuilder::<u8>::new()
+        .process_file_in_src("comment.l")
+        .unwrap();
 }
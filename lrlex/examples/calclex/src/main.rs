@@ -8,6 +8,19 @@ use lrpar::Lexer;
 
 // Using `lrlex_mod!` brings the lexer for `calc.l` into scope.
 lrlex_mod!(calc_l);
+// The same lexer, generated as a standalone DFA (see `build.rs`), which we check against the
+// `LexerDef` based lexer in the tests below.
+#[cfg(test)]
+mod dfa {
+    lrlex_mod!(calc_dfa_l);
+    pub use self::calc_l::lexer;
+}
+// A lexer with a callback (see `build.rs`), which is used in the tests below.
+#[cfg(test)]
+mod comment {
+    lrlex_mod!(comment_l);
+    pub use self::comment_l::lexerdef;
+}
 
 fn main() {
     // We need to get a `LexerDef` for the `calc` language in order that we can lex input.
@@ -30,3 +43,51 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dfa_matches_lrlexer() {
+        let lexerdef = calc_l::lexerdef();
+        for input in &[
+            "1 + 2",
+            "1.5*(2.-.3)",
+            "12.34.5",
+            "1 ? 2",
+            "",
+            "\t3/4 -5",
+            "1\n\t2 +\n3"
+        ] {
+            let mut lrlexer = lexerdef.lexer(input);
+            let mut dfalexer = dfa::lexer(input);
+            match (lrlexer.all_lexemes(), dfalexer.all_lexemes()) {
+                (Ok(x), Ok(y)) => {
+                    for l in &y {
+                        assert_eq!(lrlexer.line_and_col(l), dfalexer.line_and_col(l));
+                    }
+                    assert_eq!(x, y)
+                }
+                (Err(x), Err(y)) => assert_eq!(x.idx, y.idx),
+                (x, y) => panic!("{:?} != {:?}", x, y)
+            }
+        }
+    }
+
+    #[test]
+    fn callback() {
+        // Without its callback, the comment rule would only skip `/*`, so lexing `a` would fail.
+        let lexerdef = comment::lexerdef();
+        let mut lexer = lexerdef.lexer("1 /* a /* b */ c */ + 2");
+        let lexemes = lexer.all_lexemes().unwrap();
+        assert_eq!(
+            lexemes
+                .iter()
+                .map(|l| lexer.lexeme_str(l))
+                .collect::<Vec<_>>(),
+            vec!["1", "+", "2"]
+        );
+        assert!(lexerdef.lexer("1 /* a /* b */").all_lexemes().is_err());
+    }
+}
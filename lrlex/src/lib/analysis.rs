@@ -0,0 +1,253 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Static analysis of a `LexerDef`'s rules, finding rules which can never produce a lexeme.
+
+use std::hash::Hash;
+
+use num_traits::{PrimInt, Unsigned};
+use regex_syntax::ParserBuilder;
+
+use dfa::DFA;
+use lexer::LexerDef;
+
+/// The various kinds of warnings about a lexer's rules.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LexWarningKind {
+    /// The rule can match the empty string. The lexer never uses empty matches: if no other rule
+    /// matches at that point in the input, lexing fails.
+    EmptyMatch,
+    /// The rule can't match any non-empty string.
+    NeverMatches,
+    /// Every non-empty string the rule matches is also matched by at least one of the rules at
+    /// these indexes. Since they are earlier in the `.l` file, they take precedence, so the rule
+    /// can never produce a lexeme. This typically happens when a keyword rule is listed after a
+    /// rule for identifiers.
+    Shadowed(Vec<usize>),
+    /// The rule contains an unescaped `/` which is treated as trailing context. Before lrlex
+    /// supported trailing context, such a `/` matched a literal `/`, so a rule written for earlier
+    /// versions (e.g. `</[a-z]+>`) may now mean something different. A literal `/` can be written
+    /// as `\/`.
+    TrailingContext
+}
+
+/// A warning about the rule at index `ridx` of a `LexerDef`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LexWarning {
+    pub kind: LexWarningKind,
+    pub ridx: usize
+}
+
+impl LexWarning {
+    /// Return a pretty-printed version of this warning, using the rules in `lexerdef`.
+    pub fn pp<StorageT>(&self, lexerdef: &LexerDef<StorageT>) -> String {
+        let desc = |ridx: usize| {
+            let r = &lexerdef.rules[ridx];
+            match r.name {
+                Some(ref n) => format!("'{}'", n),
+                None => format!("`{}`", r.re_str)
+            }
+        };
+        match self.kind {
+            LexWarningKind::EmptyMatch => {
+                format!("Rule {} can match the empty string", desc(self.ridx))
+            }
+            LexWarningKind::NeverMatches => format!("Rule {} can never match", desc(self.ridx)),
+            LexWarningKind::Shadowed(ref ridxs) => format!(
+                "Rule {} is shadowed by earlier rule{} {}",
+                desc(self.ridx),
+                if ridxs.len() == 1 { "" } else { "s" },
+                ridxs
+                    .iter()
+                    .map(|&ridx| desc(ridx))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            LexWarningKind::TrailingContext => {
+                let r = &lexerdef.rules[self.ridx];
+                format!(
+                    "Rule {} uses trailing context: it matches `{}` only when followed by `{}` \
+                     (write `\\/` to match a literal `/`)",
+                    desc(self.ridx),
+                    r.head,
+                    r.trailing.as_ref().unwrap()
+                )
+            }
+        }
+    }
+}
+
+impl<StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LexerDef<StorageT> {
+    /// Analyse this lexer's rules, returning warnings (ordered by rule) about rules which use
+    /// trailing context, which can match the empty string, or which can never produce a lexeme.
+    /// The latter analysis is exact (taking into account start states, the precedence of earlier
+    /// rules, and the fact that each rule matches what `Regex::find` would), but is only performed
+    /// if all rules can be compiled into a DFA (i.e. if they don't use look-around assertions)
+    /// with at most 10,000 states (the same limit as `LexerKind::DFA`, which bounds the time and
+    /// memory this function can take).
+    /// `<<EOF>>` rules are not analysed, and a rule is not considered to be shadowed by rules
+    /// anchored with `^` unless it is itself anchored.
+    pub fn warnings(&self) -> Vec<LexWarning> {
+        let mut warnings = Vec::new();
+        let shadowed = DFA::new(self)
+            .ok()
+            .map(|dfa| dfa.shadowed_rules(self.rules.len()));
+        for (ridx, r) in self.rules.iter().enumerate() {
+            if r.eof {
+                continue;
+            }
+            if r.trailing.is_some() {
+                warnings.push(LexWarning {
+                    kind: LexWarningKind::TrailingContext,
+                    ridx
+                });
+            }
+            let matches_empty = ParserBuilder::new()
+                .multi_line(true)
+                .dot_matches_new_line(true)
+                .build()
+                .parse(&r.head)
+                .map(|hir| hir.properties().minimum_len() == Some(0))
+                .unwrap_or(false);
+            if matches_empty {
+                warnings.push(LexWarning {
+                    kind: LexWarningKind::EmptyMatch,
+                    ridx
+                });
+            }
+            if let Some(Some(ref ridxs)) = shadowed.as_ref().map(|s| &s[ridx]) {
+                if !r.bol && ridxs.iter().any(|&ridx| self.rules[ridx].bol) {
+                    continue;
+                }
+                let kind = if ridxs.is_empty() {
+                    LexWarningKind::NeverMatches
+                } else {
+                    LexWarningKind::Shadowed(ridxs.clone())
+                };
+                warnings.push(LexWarning { kind, ridx });
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::parse_lex;
+
+    fn warnings(src: &str) -> Vec<String> {
+        let lexerdef = parse_lex::<u8>(src).unwrap();
+        lexerdef
+            .warnings()
+            .iter()
+            .map(|w| w.pp(&lexerdef))
+            .collect()
+    }
+
+    #[test]
+    fn test_shadowed() {
+        assert!(warnings("%%\nif 'IF'\n[a-z]+ 'ID'\n[ ] ;").is_empty());
+        assert_eq!(
+            warnings("%%\n[a-z]+ 'ID'\nif 'IF'\n[ ] ;"),
+            vec!["Rule 'IF' is shadowed by earlier rule 'ID'"]
+        );
+        // `I` is shadowed by a combination of rules.
+        assert_eq!(
+            warnings("%%\nif 'IF'\nin 'IN'\ni[fn] 'I'\n[a-z]+ 'ID'"),
+            vec!["Rule 'I' is shadowed by earlier rules 'IF', 'IN'"]
+        );
+        // `IF` only partially overlaps with earlier rules, so it isn't shadowed.
+        assert!(warnings("%%\n[a-h]+ 'AH'\n(?:if)+ 'IF'").is_empty());
+        // Rules match what `Regex::find` would, so `AB` can never match more than `a`.
+        assert_eq!(
+            warnings("%%\na|ab 'AB'\nab 'X'\nb 'B'"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            warnings("%%\nab|a 'AB'\nab 'X'"),
+            vec!["Rule 'X' is shadowed by earlier rule 'AB'"]
+        );
+        // A rule shadowed in one start state, but not in another, can still produce lexemes.
+        assert!(warnings("%s S\n%%\n<INITIAL>[a-z]+ 'ID'\nif 'IF'").is_empty());
+        assert_eq!(
+            warnings("%s S\n%%\n[a-z]+ 'ID'\n<S>if 'IF'"),
+            vec!["Rule 'IF' is shadowed by earlier rule 'ID'"]
+        );
+        // Skipped rules are described by their regular expression.
+        assert_eq!(
+            warnings("%%\n[ \\t]+ ;\n\\t ;"),
+            vec!["Rule `\\t` is shadowed by earlier rule `[ \\t]+`"]
+        );
+        // Anchored rules don't always apply, so can't shadow unanchored rules.
+        assert!(warnings("%%\n^[a-z]+ 'LABEL'\nif 'IF'\n<<EOF>> 'EOF'").is_empty());
+        assert_eq!(
+            warnings("%%\n[a-z]+ 'ID'\n^if 'IF'"),
+            vec!["Rule 'IF' is shadowed by earlier rule 'ID'"]
+        );
+    }
+
+    #[test]
+    fn test_trailing_context() {
+        assert_eq!(
+            warnings("%%\n</[a-z]+> 'TAG'\n<\\/[a-z]+> 'END_TAG'\n[a-z<>/]+ 'X'"),
+            vec![
+                "Rule 'TAG' uses trailing context: it matches `<` only when followed by \
+                 `[a-z]+>` (write `\\/` to match a literal `/`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_and_never() {
+        assert_eq!(
+            warnings("%%\n[0-9]* 'INT'\n[ ] ;"),
+            vec!["Rule 'INT' can match the empty string"]
+        );
+        assert_eq!(
+            warnings("%%\n[a&&b] 'X'\nx 'Y'"),
+            vec!["Rule 'X' can never match"]
+        );
+        // Look-around assertions prevent the shadowing analysis, but not the empty match check.
+        assert_eq!(
+            warnings("%%\n[a-z]+ 'ID'\nif 'IF'\n\\b 'B'"),
+            vec!["Rule 'B' can match the empty string"]
+        );
+        // As do lexers whose DFA would need too many states.
+        assert_eq!(
+            warnings(&format!(
+                "%%\n[a-z]+ 'ID'\nif 'IF'\n[0-9]* 'INT'\n(a|b)*a{} 'X'",
+                "(a|b)".repeat(14)
+            )),
+            vec!["Rule 'INT' can match the empty string"]
+        );
+    }
+}
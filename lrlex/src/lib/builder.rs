@@ -35,30 +35,52 @@ use std::{
     convert::AsRef,
     env::{current_dir, var},
     error::Error,
-    fmt::Debug,
+    fmt::{self, Debug},
     fs::{self, read_to_string, File},
     hash::Hash,
     io::Write,
     path::{Path, PathBuf}
 };
 
-use num_traits::{PrimInt, Unsigned};
+use lrpar::{ctbuilder::_warning, BuildErrors, CTParserBuilder};
+use num_traits::{AsPrimitive, PrimInt, Unsigned};
+use serde::Serialize;
 use try_from::TryFrom;
 use typename::TypeName;
 
-use lexer::LexerDef;
-use parser::parse_lex;
+use dfa::DFA;
+use lexer::{LayoutToken, LexerDef};
+use parser::parse_lex_all_errors;
 
 const LEX_SUFFIX: &str = "_l";
 const LEX_FILE_EXT: &str = "l";
 const RUST_FILE_EXT: &str = "rs";
 
+/// Specify the kind of lexer generated by `LexerBuilder`.
+pub enum LexerKind {
+    /// A module with a function `lexerdef()` which returns a `LexerDef`. The rules' regular
+    /// expressions are compiled when `lexerdef()` is called.
+    LRLexer,
+    /// A standalone module with a function `lexer(s)` which returns a table-driven lexer for the
+    /// input `s`. No regular expressions are compiled at run-time, and the module only depends on
+    /// `lrpar`, so `lrlex` need only be a build dependency (the module can be included with
+    /// `include!(concat!(env!("OUT_DIR"), "/x_l.rs"))`). Lexers which use regular expression
+    /// features that can't be compiled into a DFA (e.g. look-around assertions), callbacks, or a
+    /// layout declaration cause an error at build time, as do lexers whose DFA would need more
+    /// than 10,000 states.
+    DFA
+}
+
 /// A `LexerBuilder` allows one to specify the criteria for building a statically generated
 /// lexer.
 pub struct LexerBuilder<StorageT = u32> {
+    lexerkind: LexerKind,
     rule_ids_map: Option<HashMap<String, StorageT>>,
+    callbacks: HashMap<String, String>,
     allow_missing_terms_in_lexer: bool,
-    allow_missing_tokens_in_parser: bool
+    allow_missing_tokens_in_parser: bool,
+    /// The grammar the rule IDs map came from, if known (used only in error messages).
+    grm_path: Option<PathBuf>
 }
 
 impl<StorageT> LexerBuilder<StorageT>
@@ -83,12 +105,21 @@ where
     /// ```
     pub fn new() -> Self {
         LexerBuilder {
+            lexerkind: LexerKind::LRLexer,
             rule_ids_map: None,
+            callbacks: HashMap::new(),
             allow_missing_terms_in_lexer: false,
-            allow_missing_tokens_in_parser: true
+            allow_missing_tokens_in_parser: true,
+            grm_path: None
         }
     }
 
+    /// Set the kind of lexer to be generated. Defaults to `LexerKind::LRLexer`.
+    pub fn lexer_kind(mut self, lk: LexerKind) -> Self {
+        self.lexerkind = lk;
+        self
+    }
+
     /// Set this lexer builder's map of rule IDs to `rule_ids_map`. By default, lexing rules have
     /// arbitrary, but distinct, IDs. Setting the map of rule IDs (from rule names to `StorageT`)
     /// allows users to synchronise a lexer and parser and to check that all rules are used by both
@@ -98,6 +129,15 @@ where
         self
     }
 
+    /// Link rules whose callback (see [`Callback`](type.Callback.html)) is named `name` to the
+    /// Rust function at `path` (e.g. `"::lexutil::block_comment"`). By default, a callback `name`
+    /// is linked to the function `name` in the `.l` file's routines section or, if there is no
+    /// such function, in the module which contains the `lrlex_mod!` invocation.
+    pub fn callback(mut self, name: &str, path: &str) -> Self {
+        self.callbacks.insert(name.to_owned(), path.to_owned());
+        self
+    }
+
     /// Given the filename `x.l` as input, statically compile the file `src/x.l` into a Rust module
     /// which can then be imported using `lrlex_mod!(x_l)`. This is a convenience function around
     /// [`process_file`](struct.LexerBuilder.html#method.process_file) which makes it easier to
@@ -133,7 +173,13 @@ where
 
     /// Statically compile the `.l` file `inp` into Rust, placing the output into `outp`. The
     /// latter defines a module with a function `lexerdef()`, which returns a
-    /// [`LexerDef`](struct.LexerDef.html) that can then be used as normal.
+    /// [`LexerDef`](struct.LexerDef.html) that can then be used as normal (or, if the lexer kind
+    /// is `LexerKind::DFA`, a function `lexer(s)` which returns a lexer for the input `s`). The
+    /// `.l` file's routines section, if any, is copied verbatim into the module. Warnings about
+    /// rules which can never produce a lexeme, or which can match the empty string (see
+    /// [`LexerDef::warnings`](struct.LexerDef.html#method.warnings)), are reported (via Cargo, if
+    /// called from a build script). If the `.l` file is invalid, an `lrpar::BuildErrors`
+    /// describing every error found is returned.
     pub fn process_file<P, Q>(
         self,
         inp: P,
@@ -143,8 +189,21 @@ where
         P: AsRef<Path>,
         Q: AsRef<Path>
     {
-        let inc = read_to_string(&inp).unwrap();
-        let mut lexerdef = parse_lex::<StorageT>(&inc)?;
+        let inc = read_to_string(&inp)
+            .map_err(|e| format!("Can't read {}: {}", inp.as_ref().display(), e))?;
+        let mut lexerdef = match parse_lex_all_errors::<StorageT>(&inc) {
+            Ok(lexerdef) => lexerdef,
+            Err(errs) => {
+                let mut build_errs = BuildErrors::new(&inp, &inc);
+                for e in errs {
+                    build_errs.push(e.kind.to_string(), Some(e.line_col()));
+                }
+                return Err(Box::new(build_errs));
+            }
+        };
+        for w in lexerdef.warnings() {
+            _warning(&format!("{}: {}", inp.as_ref().display(), w.pp(&lexerdef)));
+        }
         let (missing_from_lexer, missing_from_parser) = match self.rule_ids_map {
             Some(ref rim) => {
                 // Convert from HashMap<String, _> to HashMap<&str, _>
@@ -162,36 +221,90 @@ where
             None => (None, None)
         };
 
-        if !self.allow_missing_terms_in_lexer {
-            if let Some(ref mfl) = missing_from_lexer {
-                eprintln!("Error: the following tokens are used in the grammar but are not defined in the lexer:");
-                for n in mfl {
-                    eprintln!("    {}", n);
-                }
-                fs::remove_file(&outp).ok();
-                panic!();
-            }
-        }
-        if !self.allow_missing_tokens_in_parser {
-            if let Some(ref mfp) = missing_from_parser {
-                eprintln!("Error: the following tokens are defined in the lexer but not used in the grammar:");
-                for n in mfp {
-                    eprintln!("    {}", n);
-                }
-                fs::remove_file(&outp).ok();
-                panic!();
-            }
+        let mismatch = TokenMismatchError {
+            lex_path: inp.as_ref().to_path_buf(),
+            grm_path: self.grm_path.clone(),
+            missing_from_lexer: sorted(&missing_from_lexer, !self.allow_missing_terms_in_lexer),
+            missing_from_parser: sorted(&missing_from_parser, !self.allow_missing_tokens_in_parser),
+            lexer_only: sorted(&missing_from_parser, true),
+            grammar_only: sorted(&missing_from_lexer, true)
+        };
+        if !mismatch.missing_from_lexer.is_empty() || !mismatch.missing_from_parser.is_empty() {
+            fs::remove_file(&outp).ok();
+            return Err(Box::new(mismatch));
         }
 
         let mut outs = String::new();
         let mod_name = inp.as_ref().file_stem().unwrap().to_str().unwrap();
         // Header
         outs.push_str(&format!("mod {}_l {{", mod_name));
-        lexerdef.rust_pp(&mut outs);
+        outs.push_str(&self._gen_lexer(&lexerdef)?);
+
+        // Footer
+        outs.push_str("}");
+
+        // If the file we're about to write out already exists with the same contents, then we
+        // don't overwrite it (since that will force a recompile of the file, and relinking of the
+        // binary etc).
+        if let Ok(curs) = read_to_string(&outp) {
+            if curs == outs {
+                return Ok((missing_from_lexer, missing_from_parser));
+            }
+        }
+        let mut f = File::create(outp)?;
+        f.write_all(outs.as_bytes())?;
+        Ok((missing_from_lexer, missing_from_parser))
+    }
+
+    /// Generate the code which defines `lexerdef` (or, for `LexerKind::DFA`, `lexer`) and the `T_`
+    /// constants for `lexerdef`, intended to be placed inside a module. This is only public so
+    /// that `lrpar_macros` can use it.
+    #[doc(hidden)]
+    pub fn _gen_lexer(&self, lexerdef: &LexerDef<StorageT>) -> Result<String, Box<Error>> {
+        let mut outs = String::new();
+        match self.lexerkind {
+            LexerKind::LRLexer => lexerdef.rust_pp(&self.callbacks, &mut outs),
+            LexerKind::DFA => {
+                if lexerdef.layout().is_some() {
+                    return Err(From::from(
+                        "Lexers with a layout declaration can't be compiled into a DFA"
+                    ));
+                }
+                if let Some(r) = lexerdef.iter_rules().find(|r| r.callback.is_some()) {
+                    return Err(From::from(format!(
+                        "Rule '{}' can't be compiled into a DFA: it has a callback (@{})",
+                        r.re_str,
+                        r.callback.as_ref().unwrap()
+                    )));
+                }
+                if let Some(r) = lexerdef
+                    .iter_rules()
+                    .find(|r| r.bol || r.eof || r.trailing.is_some())
+                {
+                    return Err(From::from(format!(
+                        "Rule '{}' can't be compiled into a DFA: it uses `^`, `<<EOF>>`, or trailing context",
+                        r.re_str
+                    )));
+                }
+                DFA::new(&lexerdef)?.rust_pp(&lexerdef, &mut outs)
+            }
+        }
+
+        // User code
+        if let Some(s) = lexerdef.programs() {
+            outs.push_str("\n/* User code */\n\n");
+            outs.push_str(s);
+            outs.push_str("\n");
+        }
 
         // Token IDs
         if let Some(ref rim) = self.rule_ids_map {
             for (n, id) in rim {
+                // Token names such as `+` can't be turned into identifiers, so they don't get a
+                // constant.
+                if !n.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    continue;
+                }
                 outs.push_str(&format!(
                     "#[allow(dead_code)]\npub const T_{}: {} = {:?};\n",
                     n.to_ascii_uppercase(),
@@ -200,50 +313,392 @@ where
                 ));
             }
         }
+        Ok(outs)
+    }
 
-        // Footer
-        outs.push_str("}");
+    /// Generate the `parse_str` function which lexes a string with the lexer in the module
+    /// `lex_mod` (as generated by `_gen_lexer`) and parses it with the parser in the module
+    /// `grm_mod`. This is only public so that `lrpar_macros` can use it.
+    #[doc(hidden)]
+    pub fn _gen_parse_str(&self, lex_mod: &str, grm_mod: &str) -> String {
+        match self.lexerkind {
+            LexerKind::LRLexer => {
+                // Building a LexerDef compiles its regular expressions, so we only do so once per
+                // thread.
+                format!(
+                    "    thread_local! {{
+        static LEXERDEF: ::lrlex::LexerDef<{storaget}> = {lex_mod}::lexerdef();
+    }}
 
-        // If the file we're about to write out already exists with the same contents, then we
-        // don't overwrite it (since that will force a recompile of the file, and relinking of the
-        // binary etc).
-        if let Ok(curs) = read_to_string(&outp) {
-            if curs == outs {
-                return Ok((missing_from_lexer, missing_from_parser));
+    /// Lex and parse `s`.
+    pub fn parse_str(s: &str) -> {grm_mod}::ParseResult {{
+        LEXERDEF.with(|lexerdef| {grm_mod}::parse(&mut lexerdef.lexer(s)))
+    }}
+",
+                    storaget = StorageT::type_name(),
+                    lex_mod = lex_mod,
+                    grm_mod = grm_mod
+                )
             }
+            LexerKind::DFA => format!(
+                "    /// Lex and parse `s`.
+    pub fn parse_str(s: &str) -> {grm_mod}::ParseResult {{
+        {grm_mod}::parse(&mut {lex_mod}::lexer(s))
+    }}
+",
+                lex_mod = lex_mod,
+                grm_mod = grm_mod
+            )
         }
-        let mut f = File::create(outp)?;
-        f.write_all(outs.as_bytes())?;
-        Ok((missing_from_lexer, missing_from_parser))
     }
 
     /// If passed false, tokens used in the grammar but not defined in the lexer will cause a
-    /// panic at lexer generation time. Defaults to false.
+    /// [`TokenMismatchError`](struct.TokenMismatchError.html) at lexer generation time. Defaults
+    /// to false.
     pub fn allow_missing_terms_in_lexer(mut self, allow: bool) -> Self {
         self.allow_missing_terms_in_lexer = allow;
         self
     }
 
     /// If passed false, tokens defined in the lexer but not used in the grammar will cause a
-    /// panic at lexer generation time. Defaults to true (since lexers sometimes define tokens such
-    /// as reserved words, which are intentionally not in the grammar).
+    /// [`TokenMismatchError`](struct.TokenMismatchError.html) at lexer generation time. Defaults
+    /// to true (since lexers sometimes define tokens such as reserved words, which are
+    /// intentionally not in the grammar).
     pub fn allow_missing_tokens_in_parser(mut self, allow: bool) -> Self {
         self.allow_missing_tokens_in_parser = allow;
         self
     }
 }
 
+/// A `LexerParserBuilder` statically compiles a `.l` file and a `.y` file into a single module,
+/// synchronising the lexer's token IDs with the parser's, and checking that the tokens used in
+/// the grammar are defined in the lexer (and, optionally, vice versa).
+pub struct LexerParserBuilder<StorageT = u32> {
+    lexer_builder: LexerBuilder<StorageT>,
+    parser_builder: CTParserBuilder<StorageT>
+}
+
+impl<StorageT> LexerParserBuilder<StorageT>
+where
+    StorageT: 'static
+        + Copy
+        + Debug
+        + Eq
+        + Hash
+        + PrimInt
+        + Serialize
+        + TryFrom<usize>
+        + TypeName
+        + Unsigned,
+    usize: AsPrimitive<StorageT>,
+    u32: AsPrimitive<StorageT>
+{
+    /// Create a new `LexerParserBuilder` which uses a default `LexerBuilder` and
+    /// `CTParserBuilder`. `StorageT` is as for
+    /// [`LexerBuilder::new`](struct.LexerBuilder.html#method.new).
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// LexerParserBuilder::<u8>::new()
+    ///     .parser_builder(CTParserBuilder::new().action_kind(ActionKind::CustomAction))
+    ///     .process_files_in_src("calc.l", "calc.y")?;
+    /// ```
+    pub fn new() -> Self {
+        LexerParserBuilder {
+            lexer_builder: LexerBuilder::new(),
+            parser_builder: CTParserBuilder::new()
+        }
+    }
+
+    /// Use `lb` to build the lexer. Any rule IDs map set on `lb` is ignored.
+    pub fn lexer_builder(mut self, lb: LexerBuilder<StorageT>) -> Self {
+        self.lexer_builder = lb;
+        self
+    }
+
+    /// Use `pb` to build the parser.
+    pub fn parser_builder(mut self, pb: CTParserBuilder<StorageT>) -> Self {
+        self.parser_builder = pb;
+        self
+    }
+
+    /// Given the filenames `x.l` and `y.y` as input, statically compile the files `src/x.l` and
+    /// `src/y.y` into a Rust module which can then be imported using `lrpar_mod!(y)`. This is a
+    /// convenience function around
+    /// [`process_files`](struct.LexerParserBuilder.html#method.process_files) which makes it
+    /// easier to compile files stored in a project's `src/` directory.
+    pub fn process_files_in_src(self, lexp: &str, grmp: &str) -> Result<(), Box<Error>> {
+        let mut src = current_dir()?;
+        src.push("src");
+        let mut outd = PathBuf::new();
+        outd.push(var("OUT_DIR").unwrap());
+        self.process_files(src.join(lexp), src.join(grmp), outd)
+    }
+
+    /// Statically compile the `.l` file `lexp` and the `.y` file `grmp` into Rust, placing the
+    /// output file(s) into the directory `outd`. If the grammar is `y.y`, the output defines a
+    /// module `y` which contains everything that `LexerBuilder` and `CTParserBuilder` would have
+    /// generated for the two files (e.g. the parser's `parse` function and the `T_` and `R_`
+    /// constants) and a function:
+    ///
+    /// ```rust,ignore
+    /// parse_str(s: &str) -> Result<..., LexParseError<StorageT>>
+    /// ```
+    ///
+    /// which lexes and parses `s`, returning what `parse` would (a parse tree or the result of the
+    /// grammar's actions). Since `parse_str` doesn't return the lexer, callers who want the line
+    /// and column numbers of errors can use a [`LineIndex`](../lrpar/struct.LineIndex.html).
+    ///
+    /// If the grammar uses tokens which the lexer doesn't define (or, if the `LexerBuilder`
+    /// doesn't allow it, the lexer defines tokens which the grammar doesn't use), a
+    /// [`TokenMismatchError`](struct.TokenMismatchError.html) is returned.
+    pub fn process_files<P, Q, R>(self, lexp: P, grmp: Q, outd: R) -> Result<(), Box<Error>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        R: AsRef<Path>
+    {
+        let (lexp, grmp, outd) = (lexp.as_ref(), grmp.as_ref(), outd.as_ref());
+        let mod_name = grmp.file_stem().unwrap().to_str().unwrap();
+        let outp = outd.join(mod_name).with_extension(RUST_FILE_EXT);
+        let lex_mod_name = format!(
+            "{}{}",
+            lexp.file_stem().unwrap().to_str().unwrap(),
+            LEX_SUFFIX
+        );
+        let lex_outp = outd.join(&lex_mod_name).with_extension(RUST_FILE_EXT);
+        let grm_mod_name = format!("{}_y", mod_name);
+        let grm_outp = outd.join(&grm_mod_name).with_extension(RUST_FILE_EXT);
+
+        let rule_ids_map = match self.parser_builder.process_file(grmp, outd) {
+            Ok(m) => m,
+            Err(e) => {
+                fs::remove_file(&outp).ok();
+                return Err(e);
+            }
+        };
+        let mut lb = self.lexer_builder.rule_ids_map(rule_ids_map);
+        lb.grm_path = Some(grmp.to_path_buf());
+        let lex_fn = match lb.lexerkind {
+            LexerKind::LRLexer => "lexerdef",
+            LexerKind::DFA => "lexer"
+        };
+        let parse_str = lb._gen_parse_str(&lex_mod_name, &grm_mod_name);
+        if let Err(e) = lb.process_file(lexp, &lex_outp) {
+            fs::remove_file(&outp).ok();
+            return Err(e);
+        }
+
+        let mut outs = String::new();
+        // The lexer and parser modules are nested within the combined module: since the latter
+        // glob imports its parent, names in the lexer and parser modules which refer to their
+        // parent (e.g. callbacks and `super::` paths in user code) resolve as before.
+        outs.push_str(&format!(
+            "mod {mod_name} {{
+    #![allow(dead_code, unused_imports)]
+    use super::*;
+    include!({lex_outp:?});
+    include!({grm_outp:?});
+    pub use self::{lex_mod_name}::*;
+    pub use self::{grm_mod_name}::*;
+    pub use self::{grm_mod_name}::parse;
+",
+            mod_name = mod_name,
+            lex_outp = lex_outp.to_str().unwrap(),
+            grm_outp = grm_outp.to_str().unwrap(),
+            lex_mod_name = lex_mod_name,
+            grm_mod_name = grm_mod_name
+        ));
+        outs.push_str(&format!(
+            "    pub use self::{}::{};\n\n",
+            lex_mod_name, lex_fn
+        ));
+        outs.push_str(&parse_str);
+        outs.push_str("}\n");
+
+        if let Ok(curs) = read_to_string(&outp) {
+            if curs == outs {
+                return Ok(());
+            }
+        }
+        let mut f = File::create(outp)?;
+        f.write_all(outs.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// The tokens used in a grammar do not match those defined in a lexer. If the lexer was built by
+/// a [`LexerParserBuilder`](struct.LexerParserBuilder.html), the error message names both files,
+/// and suggests lexer tokens with similar names to any tokens missing from the lexer (and vice
+/// versa).
+pub struct TokenMismatchError {
+    lex_path: PathBuf,
+    grm_path: Option<PathBuf>,
+    /// The tokens used in the grammar but not defined in the lexer, if that isn't allowed.
+    missing_from_lexer: Vec<String>,
+    /// The tokens defined in the lexer but not used in the grammar, if that isn't allowed.
+    missing_from_parser: Vec<String>,
+    /// All the tokens defined in the lexer but not used in the grammar.
+    lexer_only: Vec<String>,
+    /// All the tokens used in the grammar but not defined in the lexer.
+    grammar_only: Vec<String>
+}
+
+impl TokenMismatchError {
+    /// The tokens used in the grammar but not defined in the lexer (if that is disallowed).
+    pub fn missing_from_lexer(&self) -> &[String] {
+        &self.missing_from_lexer
+    }
+
+    /// The tokens defined in the lexer but not used in the grammar (if that is disallowed).
+    pub fn missing_from_parser(&self) -> &[String] {
+        &self.missing_from_parser
+    }
+}
+
+impl Error for TokenMismatchError {}
+
+impl fmt::Display for TokenMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lexn = self.lex_path.display().to_string();
+        let grmn = match self.grm_path {
+            Some(ref p) => p.display().to_string(),
+            None => "the grammar".to_owned()
+        };
+        write!(f, "The tokens in {} and {} do not match:", lexn, grmn)?;
+        let sections = [
+            (
+                &self.missing_from_lexer,
+                &grmn,
+                &lexn,
+                "used",
+                "defined",
+                &self.lexer_only
+            ),
+            (
+                &self.missing_from_parser,
+                &lexn,
+                &grmn,
+                "defined",
+                "used",
+                &self.grammar_only
+            )
+        ];
+        for &(names, in_, notin, is, isnt, others) in &sections {
+            if names.is_empty() {
+                continue;
+            }
+            write!(f, "\n  {} in {} but not {} in {}:", is, in_, isnt, notin)?;
+            for n in names {
+                write!(f, "\n    {}", n)?;
+                if let Some(s) = suggest(n, others) {
+                    write!(f, " (did you mean {}?)", s)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Build scripts whose `main` returns `Result<(), Box<Error>>` print errors with `Debug`, so we make
+// `Debug` produce the same (multi-line) output as `Display`.
+impl fmt::Debug for TokenMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// If `keep` is true, return the names in `names` in sorted order; otherwise return an empty
+/// `Vec`.
+fn sorted(names: &Option<HashSet<String>>, keep: bool) -> Vec<String> {
+    match *names {
+        Some(ref names) if keep => {
+            let mut v = names.iter().cloned().collect::<Vec<_>>();
+            v.sort();
+            v
+        }
+        _ => Vec::new()
+    }
+}
+
+/// Return the name in `candidates` most similar to `n`, if any is similar enough to be worth
+/// suggesting: either a small edit away from `n`, or a prefix of it (or vice versa), ignoring case.
+fn suggest<'a>(n: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let n = n.to_lowercase();
+    candidates
+        .iter()
+        .filter_map(|c| {
+            let cl = c.to_lowercase();
+            let d = edit_distance(&n, &cl);
+            let (shorter, longer) = if n.len() < cl.len() {
+                (&n, &cl)
+            } else {
+                (&cl, &n)
+            };
+            if d <= (longer.chars().count() / 3).max(1)
+                || (shorter.chars().count() > 1 && longer.starts_with(shorter.as_str()))
+            {
+                Some((d, c))
+            } else {
+                None
+            }
+        })
+        .min()
+        .map(|(_, c)| c.as_str())
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ac) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, &bc) in b.iter().enumerate() {
+            let sub = prev[j] + if ac == bc { 0 } else { 1 };
+            cur.push(sub.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
 impl<StorageT: Copy + Debug + Eq + TypeName> LexerDef<StorageT> {
-    pub(crate) fn rust_pp(&self, outs: &mut String) {
+    pub(crate) fn rust_pp(&self, callbacks: &HashMap<String, String>, outs: &mut String) {
         // Header
+        outs.push_str("use lrlex::{LexerDef, Rule, StartState};\n");
+        // Callbacks without an explicit path are referred to by their bare name, which resolves to
+        // a function in the user code (which is placed in this module) if there is one, or
+        // otherwise, via a glob import, to a function in the parent module.
+        if self
+            .rules
+            .iter()
+            .filter_map(|r| r.callback.as_ref())
+            .any(|cb| !callbacks.contains_key(cb))
+        {
+            outs.push_str("#[allow(unused_imports)]\nuse super::*;\n");
+        }
         outs.push_str(&format!(
-            "use lrlex::{{LexerDef, Rule}};
-
+            "
 pub fn lexerdef() -> LexerDef<{}> {{
-    let rules = vec![",
+    let start_states = vec![",
             StorageT::type_name()
         ));
 
+        // Start states
+        for ss in &self.start_states {
+            outs.push_str(&format!(
+                "
+StartState::new({:?}, {:?}),",
+                ss.name, ss.exclusive
+            ));
+        }
+        outs.push_str(
+            "
+];
+    let rules = vec!["
+        );
+
         // Individual rules
         for r in &self.rules {
             let tok_id = match r.tok_id {
@@ -254,22 +709,121 @@ pub fn lexerdef() -> LexerDef<{}> {{
                 Some(ref n) => format!("Some({:?}.to_string())", n),
                 None => "None".to_owned()
             };
+            let target_state = match r.target_state {
+                Some(ref op) => format!("Some(::lrlex::StartStateOperation::{:?})", op),
+                None => "None".to_owned()
+            };
+            let callback = match r.callback {
+                Some(ref cb) => format!("Some({:?}.to_string())", cb),
+                None => "None".to_owned()
+            };
             outs.push_str(&format!(
                 "
-Rule::new({}, {}, \"{}\".to_string()).unwrap(),",
+Rule::new({}, {}, \"{}\".to_string(), vec!{:?}, {}, {}).unwrap(),",
                 tok_id,
                 n,
-                r.re_str.replace("\\", "\\\\").replace("\"", "\\\"")
+                r.re_str.replace("\\", "\\\\").replace("\"", "\\\""),
+                r.start_states,
+                target_state,
+                callback
+            ));
+        }
+        outs.push_str(
+            "
+];
+    #[allow(unused_mut)]
+    let mut lexerdef = LexerDef::new_with_start_states(start_states, rules);"
+        );
+
+        // Callbacks
+        let mut cbs = self
+            .rules
+            .iter()
+            .filter_map(|r| r.callback.as_ref())
+            .collect::<Vec<_>>();
+        cbs.sort();
+        cbs.dedup();
+        for cb in cbs {
+            let path = match callbacks.get(cb) {
+                Some(p) => p.clone(),
+                None => cb.clone()
+            };
+            outs.push_str(&format!(
+                "
+    lexerdef.set_callback({:?}, {});",
+                cb, path
+            ));
+        }
+
+        // Layout
+        if let Some(ref layout) = self.layout {
+            let tok = |t: &LayoutToken<StorageT>| {
+                let tok_id = match t.tok_id {
+                    Some(ref t) => format!("Some({:?})", t),
+                    None => "None".to_owned()
+                };
+                format!("::lrlex::LayoutToken::new({:?}, {})", t.name, tok_id)
+            };
+            let newline = match layout.newline {
+                Some(ref t) => format!("Some({})", tok(t)),
+                None => "None".to_owned()
+            };
+            outs.push_str(&format!(
+                "
+    lexerdef.set_layout(Some(::lrlex::Layout {{
+        indent: {},
+        dedent: {},
+        newline: {}
+    }}));",
+                tok(&layout.indent),
+                tok(&layout.dedent),
+                newline
             ));
         }
 
         // Footer
         outs.push_str(
             "
-];
-    LexerDef::new(rules)
+    lexerdef
 }
 "
         );
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_mismatch_error() {
+        let set = |names: &[&str]| Some(names.iter().map(|n| n.to_string()).collect());
+        let (mfl, mfp) = (set(&["INTEGER", "MINUS"]), set(&["FLOAT", "INT"]));
+        let e = TokenMismatchError {
+            lex_path: PathBuf::from("calc.l"),
+            grm_path: Some(PathBuf::from("calc.y")),
+            missing_from_lexer: sorted(&mfl, true),
+            missing_from_parser: sorted(&mfp, false),
+            lexer_only: sorted(&mfp, true),
+            grammar_only: sorted(&mfl, true)
+        };
+        assert_eq!(e.missing_from_lexer(), &["INTEGER", "MINUS"]);
+        assert!(e.missing_from_parser().is_empty());
+        assert_eq!(
+            e.to_string(),
+            "The tokens in calc.l and calc.y do not match:
+  used in calc.y but not defined in calc.l:
+    INTEGER (did you mean INT?)
+    MINUS"
+        );
+        assert_eq!(format!("{:?}", e), e.to_string());
+
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(
+            suggest("id", &["ID".to_owned(), "IF".to_owned()]),
+            Some("ID")
+        );
+        assert_eq!(suggest("PLUS", &["MUL".to_owned()]), None);
+    }
+}
@@ -0,0 +1,691 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Compile a `LexerDef` into a deterministic finite automaton which can be output as a
+//! standalone, table-driven, lexer that doesn't need to compile regular expressions at run-time.
+//!
+//! Each rule's regular expression is first compiled into a Thompson NFA. The NFAs are then
+//! combined into one DFA per start state using a subset construction that, like a Pike VM, keeps
+//! each rule's NFA states in priority order. This preserves the semantics of `LRLexer`: each rule
+//! matches what `Regex::find` would match (i.e. leftmost-first, not necessarily longest, for
+//! alternations and non-greedy repetitions), the longest match across rules wins, and ties are
+//! won by the earliest rule.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug
+};
+
+use regex_syntax::{
+    hir::{Class, Hir, HirKind},
+    utf8::Utf8Sequences,
+    ParserBuilder
+};
+use typename::TypeName;
+
+use lexer::{LexerDef, StartStateOperation};
+
+/// The DFA state which can never match anything.
+const DEAD: u32 = 0;
+/// The maximum number of states a DFA can have. The subset construction can, in the worst case,
+/// create exponentially many states (e.g. for `(a|b)*a(a|b)(a|b)...`), so we give up rather than
+/// exhaust memory.
+const MAX_STATES: usize = 10_000;
+
+enum NFAState {
+    /// Match a byte in the (inclusive) range and go to the given state.
+    Range(u8, u8, usize),
+    /// An epsilon transition to each of the given states, in priority order.
+    Split(Vec<usize>),
+    Match
+}
+
+struct NFA {
+    states: Vec<NFAState>
+}
+
+impl NFA {
+    fn add(&mut self, st: NFAState) -> usize {
+        self.states.push(st);
+        self.states.len() - 1
+    }
+
+    /// Compile `hir` into states which, when matched, continue at `next`, returning the initial
+    /// state.
+    fn compile(&mut self, hir: &Hir, next: usize) -> Result<usize, String> {
+        match *hir.kind() {
+            HirKind::Empty => Ok(next),
+            HirKind::Literal(ref l) => Ok(l
+                .0
+                .iter()
+                .rev()
+                .fold(next, |n, &b| self.add(NFAState::Range(b, b, n)))),
+            HirKind::Class(Class::Unicode(ref cls)) => {
+                let mut alts = Vec::new();
+                for r in cls.iter() {
+                    for seq in Utf8Sequences::new(r.start(), r.end()) {
+                        alts.push(
+                            seq.as_slice()
+                                .iter()
+                                .rev()
+                                .fold(next, |n, r| self.add(NFAState::Range(r.start, r.end, n)))
+                        );
+                    }
+                }
+                Ok(self.add(NFAState::Split(alts)))
+            }
+            HirKind::Class(Class::Bytes(ref cls)) => {
+                let alts = cls
+                    .iter()
+                    .map(|r| self.add(NFAState::Range(r.start(), r.end(), next)))
+                    .collect();
+                Ok(self.add(NFAState::Split(alts)))
+            }
+            HirKind::Look(ref l) => Err(format!("look-around assertion {:?}", l)),
+            HirKind::Repetition(ref rep) => {
+                let mut st = match rep.max {
+                    None => {
+                        let loop_st = self.add(NFAState::Split(Vec::new()));
+                        let sub_st = self.compile(&rep.sub, loop_st)?;
+                        self.states[loop_st] = NFAState::Split(if rep.greedy {
+                            vec![sub_st, next]
+                        } else {
+                            vec![next, sub_st]
+                        });
+                        loop_st
+                    }
+                    Some(max) => {
+                        let mut st = next;
+                        for _ in rep.min..max {
+                            let sub_st = self.compile(&rep.sub, st)?;
+                            st = self.add(NFAState::Split(if rep.greedy {
+                                vec![sub_st, next]
+                            } else {
+                                vec![next, sub_st]
+                            }));
+                        }
+                        st
+                    }
+                };
+                for _ in 0..rep.min {
+                    st = self.compile(&rep.sub, st)?;
+                }
+                Ok(st)
+            }
+            HirKind::Capture(ref cap) => self.compile(&cap.sub, next),
+            HirKind::Concat(ref hirs) => {
+                let mut st = next;
+                for h in hirs.iter().rev() {
+                    st = self.compile(h, st)?;
+                }
+                Ok(st)
+            }
+            HirKind::Alternation(ref hirs) => {
+                let mut alts = Vec::with_capacity(hirs.len());
+                for h in hirs {
+                    alts.push(self.compile(h, next)?);
+                }
+                Ok(self.add(NFAState::Split(alts)))
+            }
+        }
+    }
+
+    /// Append to `threads` (in priority order) the non-epsilon states reachable from `st`. Any
+    /// states after a `Match` state have lower priority than the match and are not added. Returns
+    /// true if a `Match` state was added.
+    fn closure(&self, st: usize, threads: &mut Vec<usize>, seen: &mut HashSet<usize>) -> bool {
+        let mut todo = vec![st];
+        while let Some(st) = todo.pop() {
+            if !seen.insert(st) {
+                continue;
+            }
+            match self.states[st] {
+                NFAState::Range(..) => threads.push(st),
+                NFAState::Split(ref alts) => todo.extend(alts.iter().rev()),
+                NFAState::Match => {
+                    threads.push(st);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// A DFA state: for each rule (in order) which can still match, that rule's NFA states in
+/// priority order.
+type DFAStateKey = Vec<(usize, Vec<usize>)>;
+
+pub(crate) struct DFA {
+    /// Maps each byte to its equivalence class.
+    classes: Vec<u8>,
+    num_classes: usize,
+    /// The transition table, with `num_classes` entries per state.
+    transitions: Vec<u32>,
+    /// For each state, 0 if it is not an accepting state, or the index of the matching rule + 1.
+    accepts: Vec<u32>,
+    /// For each state, the indexes of all the rules which match in that state, in order (the
+    /// first of which is the rule recorded in `accepts`).
+    matching: Vec<Vec<usize>>,
+    /// The initial DFA state for each start state.
+    starts: Vec<u32>
+}
+
+impl DFA {
+    /// Build a DFA from `lexerdef`, returning an error message if any rule uses features that
+    /// can't be compiled into a DFA or if the DFA would need more than `MAX_STATES` states. `^`
+    /// anchors are ignored (i.e. such rules are treated as if
+    /// they match everywhere) and `<<EOF>>` rules never match.
+    pub(crate) fn new<StorageT>(lexerdef: &LexerDef<StorageT>) -> Result<DFA, String> {
+        let mut nfa = NFA { states: Vec::new() };
+        let mut rule_starts = Vec::with_capacity(lexerdef.rules.len());
+        for r in &lexerdef.rules {
+            if r.eof {
+                rule_starts.push(nfa.add(NFAState::Split(Vec::new())));
+                continue;
+            }
+            let hir = ParserBuilder::new()
+                .multi_line(true)
+                .dot_matches_new_line(true)
+                .build()
+                .parse(&r.pattern())
+                .map_err(|e| e.to_string())?;
+            let match_st = nfa.add(NFAState::Match);
+            rule_starts.push(nfa.compile(&hir, match_st).map_err(|e| {
+                format!(
+                    "Rule '{}' can't be compiled into a DFA: it contains a {}",
+                    r.re_str, e
+                )
+            })?);
+        }
+
+        // Bytes which no NFA state distinguishes between can share a column in the transition
+        // table.
+        let mut boundaries = vec![false; 257];
+        for st in &nfa.states {
+            if let NFAState::Range(s, e, _) = *st {
+                boundaries[usize::from(s)] = true;
+                boundaries[usize::from(e) + 1] = true;
+            }
+        }
+        let mut classes = Vec::with_capacity(256);
+        let mut class_reprs = vec![0u8];
+        for b in 0..256 {
+            if b > 0 && boundaries[b] {
+                class_reprs.push(b as u8);
+            }
+            classes.push((class_reprs.len() - 1) as u8);
+        }
+        let num_classes = class_reprs.len();
+
+        let mut dfa = DFA {
+            classes,
+            num_classes,
+            transitions: vec![DEAD; num_classes],
+            accepts: vec![0],
+            matching: vec![Vec::new()],
+            starts: Vec::new()
+        };
+        let mut keys: HashMap<DFAStateKey, u32> = HashMap::new();
+        keys.insert(Vec::new(), DEAD);
+        let mut todo = Vec::new();
+        for stidx in 0..lexerdef.start_states.len() {
+            let mut key = Vec::new();
+            let mut matching = Vec::new();
+            for (ridx, r) in lexerdef.rules.iter().enumerate() {
+                if !r.active_in(&lexerdef.start_states, stidx) {
+                    continue;
+                }
+                let mut threads = Vec::new();
+                if nfa.closure(rule_starts[ridx], &mut threads, &mut HashSet::new()) {
+                    matching.push(ridx);
+                }
+                key.push((ridx, threads));
+            }
+            let st = dfa.state(&mut keys, &mut todo, key, matching)?;
+            dfa.starts.push(st);
+        }
+
+        while let Some((st, key)) = todo.pop() {
+            for (cls, &b) in class_reprs.iter().enumerate() {
+                let mut next_key = Vec::new();
+                let mut matching = Vec::new();
+                for &(ridx, ref threads) in &key {
+                    let mut next_threads = Vec::new();
+                    let mut seen = HashSet::new();
+                    for &t in threads {
+                        if let NFAState::Range(s, e, next) = nfa.states[t] {
+                            if s <= b && b <= e && nfa.closure(next, &mut next_threads, &mut seen) {
+                                matching.push(ridx);
+                                break;
+                            }
+                        }
+                    }
+                    if !next_threads.is_empty() {
+                        next_key.push((ridx, next_threads));
+                    }
+                }
+                let next_st = dfa.state(&mut keys, &mut todo, next_key, matching)?;
+                dfa.transitions[st as usize * num_classes + cls] = next_st;
+            }
+        }
+        Ok(dfa)
+    }
+
+    /// Return the DFA state for `key`, creating it (and adding it to `todo`) if necessary.
+    fn state(
+        &mut self,
+        keys: &mut HashMap<DFAStateKey, u32>,
+        todo: &mut Vec<(u32, DFAStateKey)>,
+        key: DFAStateKey,
+        matching: Vec<usize>
+    ) -> Result<u32, String> {
+        if let Some(&st) = keys.get(&key) {
+            return Ok(st);
+        }
+        if self.accepts.len() == MAX_STATES {
+            return Err(format!(
+                "The lexer can't be compiled into a DFA: it needs more than {} states",
+                MAX_STATES
+            ));
+        }
+        let st = self.accepts.len() as u32;
+        self.accepts
+            .push(matching.first().map(|&ridx| ridx as u32 + 1).unwrap_or(0));
+        self.matching.push(matching);
+        self.transitions.extend((0..self.num_classes).map(|_| DEAD));
+        keys.insert(key.clone(), st);
+        todo.push((st, key));
+        Ok(st)
+    }
+
+    /// Return, for each of the `num_rules` rules this DFA was built from, `None` if the rule can
+    /// produce a lexeme or, if it can't, `Some` of the (earlier) rules which take precedence over
+    /// it on the non-empty inputs it matches. A rule can produce a lexeme iff it is the first
+    /// matching rule in some state reachable by a transition (the lexer ignores empty matches,
+    /// which are recorded in start states).
+    pub(crate) fn shadowed_rules(&self, num_rules: usize) -> Vec<Option<Vec<usize>>> {
+        let mut wins = vec![false; num_rules];
+        let mut shadowers = vec![Vec::new(); num_rules];
+        for &st in &self.transitions {
+            if st == DEAD {
+                continue;
+            }
+            let matching = &self.matching[st as usize];
+            if let Some((&winner, losers)) = matching.split_first() {
+                wins[winner] = true;
+                for &ridx in losers {
+                    if !shadowers[ridx].contains(&winner) {
+                        shadowers[ridx].push(winner);
+                    }
+                }
+            }
+        }
+        wins.into_iter()
+            .zip(shadowers)
+            .map(|(w, mut s)| {
+                if w {
+                    None
+                } else {
+                    s.sort();
+                    Some(s)
+                }
+            })
+            .collect()
+    }
+
+    /// Output Rust code for a standalone lexer for `lexerdef` (from which this DFA must have been
+    /// built). The lexer is created with `lexer(s)`, and only depends on `lrpar`.
+    pub(crate) fn rust_pp<StorageT: Copy + Debug + TypeName>(
+        &self,
+        lexerdef: &LexerDef<StorageT>,
+        outs: &mut String
+    ) {
+        outs.push_str(&format!(
+            "
+const CLASSES: [u8; 256] = {:?};
+const NUM_CLASSES: usize = {};
+static TRANSITIONS: &[u32] = &{:?};
+static ACCEPTS: &[u32] = &{:?};
+static START_STATES: &[u32] = &{:?};
+",
+            self.classes, self.num_classes, self.transitions, self.accepts, self.starts
+        ));
+
+        let tok_ids = lexerdef
+            .rules
+            .iter()
+            .map(|r| match r.tok_id {
+                Some(ref t) => format!("Some({:?})", t),
+                None => "None".to_owned()
+            })
+            .collect::<Vec<_>>();
+        let named = lexerdef
+            .rules
+            .iter()
+            .map(|r| r.name.is_some())
+            .collect::<Vec<_>>();
+        let target_states = lexerdef
+            .rules
+            .iter()
+            .map(|r| match r.target_state {
+                None => (0, 0),
+                Some(StartStateOperation::ReplaceStack(stidx)) => (1, stidx),
+                Some(StartStateOperation::Push(stidx)) => (2, stidx),
+                Some(StartStateOperation::Pop) => (3, 0)
+            })
+            .collect::<Vec<_>>();
+        outs.push_str(&format!(
+            "static TOK_IDS: &[Option<{}>] = &[{}];
+static NAMED: &[bool] = &{:?};
+// For each rule, an operation (0: none, 1: replace stack, 2: push, 3: pop) and a start state.
+static TARGET_STATES: &[(u8, usize)] = &{:?};
+",
+            StorageT::type_name(),
+            tok_ids.join(", "),
+            named,
+            target_states
+        ));
+        outs.push_str(&LEXER_TEMPLATE.replace("$StorageT", &StorageT::type_name()));
+    }
+
+    /// Return the length of the longest match at the beginning of `s` in start state `stidx`, and
+    /// the index of the rule which matched.
+    #[cfg(test)]
+    fn longest_match(&self, stidx: usize, s: &str) -> Option<(usize, usize)> {
+        let mut st = self.starts[stidx] as usize;
+        let mut longest = None;
+        for (i, &b) in s.as_bytes().iter().enumerate() {
+            let cls = usize::from(self.classes[usize::from(b)]);
+            st = self.transitions[st * self.num_classes + cls] as usize;
+            if st == DEAD as usize {
+                break;
+            }
+            if self.accepts[st] != 0 {
+                longest = Some((i + 1, self.accepts[st] as usize - 1));
+            }
+        }
+        longest
+    }
+}
+
+/// The run-time part of the lexer output by `DFA::rust_pp`, which mirrors `LRLexer`.
+const LEXER_TEMPLATE: &str = "
+pub fn lexer<'a>(s: &'a str) -> DFALexer<'a> {
+    DFALexer {
+        s,
+        i: 0,
+        line_index: ::lrpar::LineIndex::new(s),
+        start_states: vec![0]
+    }
+}
+
+pub struct DFALexer<'a> {
+    s: &'a str,
+    i: usize,
+    line_index: ::lrpar::LineIndex<'a>,
+    start_states: Vec<usize>
+}
+
+impl<'a> ::lrpar::Lexer<$StorageT> for DFALexer<'a> {
+    fn next(&mut self) -> Option<Result<::lrpar::Lexeme<$StorageT>, ::lrpar::LexError>> {
+        let bytes = self.s.as_bytes();
+        while self.i < self.s.len() {
+            let old_i = self.i;
+            let mut longest = 0;
+            let mut longest_ridx = 0;
+            let mut st = START_STATES[*self.start_states.last().unwrap()] as usize;
+            for (j, &b) in bytes[old_i..].iter().enumerate() {
+                st = TRANSITIONS[st * NUM_CLASSES + CLASSES[b as usize] as usize] as usize;
+                if st == 0 {
+                    break;
+                }
+                if ACCEPTS[st] != 0 {
+                    longest = j + 1;
+                    longest_ridx = ACCEPTS[st] as usize - 1;
+                }
+            }
+            if longest > 0 {
+                match TARGET_STATES[longest_ridx] {
+                    (1, stidx) => {
+                        self.start_states.clear();
+                        self.start_states.push(stidx);
+                    }
+                    (2, stidx) => self.start_states.push(stidx),
+                    (3, _) => {
+                        if self.start_states.len() == 1 {
+                            self.i = self.s.len();
+                            return Some(Err(::lrpar::LexError { idx: old_i }));
+                        }
+                        self.start_states.pop();
+                    }
+                    _ => ()
+                }
+                if NAMED[longest_ridx] {
+                    match TOK_IDS[longest_ridx] {
+                        Some(tok_id) => {
+                            self.i += longest;
+                            return Some(Ok(::lrpar::Lexeme::new(tok_id, old_i, longest)));
+                        }
+                        None => {
+                            self.i = self.s.len();
+                            return Some(Err(::lrpar::LexError { idx: old_i }));
+                        }
+                    }
+                }
+                self.i += longest;
+            } else {
+                self.i = self.s.len();
+                return Some(Err(::lrpar::LexError { idx: old_i }));
+            }
+        }
+        None
+    }
+
+    fn line_and_col(&self, l: &::lrpar::Lexeme<$StorageT>) -> Result<(usize, usize), ()> {
+        self.line_index.line_and_col(l.start())
+    }
+
+    fn input(&self) -> &str {
+        self.s
+    }
+}
+";
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use lrpar::{LexError, Lexeme, Lexer};
+
+    use super::*;
+    use lexer::Rule;
+    use parser::parse_lex;
+
+    /// Lex `s` using `dfa` in the same way as the code output by `DFA::rust_pp`. The generated
+    /// code itself is built and checked against `LRLexer` in `examples/calclex`.
+    fn dfa_lex(dfa: &DFA, lexerdef: &LexerDef<u16>, s: &str) -> Result<Vec<Lexeme<u16>>, LexError> {
+        let mut lexemes = Vec::new();
+        let mut start_states = vec![0];
+        let mut i = 0;
+        while i < s.len() {
+            let (len, ridx) = match dfa.longest_match(*start_states.last().unwrap(), &s[i..]) {
+                Some(x) => x,
+                None => return Err(LexError { idx: i })
+            };
+            let r: &Rule<u16> = &lexerdef.rules[ridx];
+            match r.target_state {
+                Some(StartStateOperation::ReplaceStack(stidx)) => start_states = vec![stidx],
+                Some(StartStateOperation::Push(stidx)) => start_states.push(stidx),
+                Some(StartStateOperation::Pop) => {
+                    if start_states.len() == 1 {
+                        return Err(LexError { idx: i });
+                    }
+                    start_states.pop();
+                }
+                None => ()
+            }
+            if r.name.is_some() {
+                match r.tok_id {
+                    Some(tok_id) => lexemes.push(Lexeme::new(tok_id, i, len)),
+                    None => return Err(LexError { idx: i })
+                }
+            }
+            i += len;
+        }
+        Ok(lexemes)
+    }
+
+    /// Check that, for each input in `inputs`, `LRLexer` and the DFA built from `src` produce the
+    /// same output.
+    fn check(src: &str, inputs: &[&str]) {
+        let mut lexerdef = parse_lex::<u16>(src).unwrap();
+        let ids = lexerdef
+            .iter_rules()
+            .filter_map(|r| r.name.clone())
+            .enumerate()
+            .map(|(i, n)| (n, i as u16))
+            .collect::<HashMap<_, _>>();
+        lexerdef.set_rule_ids(&ids.iter().map(|(n, i)| (&**n, *i)).collect());
+        let dfa = DFA::new(&lexerdef).unwrap();
+        for input in inputs {
+            assert_eq!(
+                dfa_lex(&dfa, &lexerdef, input).map_err(|e| e.idx),
+                lexerdef.lexer(input).all_lexemes().map_err(|e| e.idx),
+                "Input: {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn longest_match_earliest_rule() {
+        check(
+            "%%
+if 'IF'
+[a-z]+ 'ID'
+[0-9]+ 'INT'
+[ \\n\\t] ;",
+            &["if iff i", "abc 123\nx", "12ab", "", " ", "if%", "\u{e9}"]
+        );
+    }
+
+    #[test]
+    fn leftmost_first() {
+        // `Regex::find` doesn't necessarily return the longest match for a rule: the DFA must
+        // match the same (shorter) text.
+        check(
+            "%%
+a|ab 'A'
+b 'B'
+c+? 'C'
+d{2,3} 'D'
+(e|ef)(f|fg)*? 'E'
+g 'G'",
+            &[
+                "ab", "aab", "ccc", "ddddd", "dd", "d", "ef", "eff", "efg", "g"
+            ]
+        );
+    }
+
+    #[test]
+    fn classes_and_unicode() {
+        check(
+            "%%
+[\u{e0}-\u{ff}]+ 'LATIN'
+\\p{Greek}+ 'GREEK'
+(?i)abc 'ABC'
+. 'ANY'",
+            &[
+                "\u{e0}\u{e9}x",
+                "\u{3b1}\u{3b2}\u{e9}",
+                "aBc",
+                "\n\u{1F600}",
+                "ab"
+            ]
+        );
+    }
+
+    #[test]
+    fn start_states() {
+        check(
+            "%x COMMENT STRING
+%%
+/\\* <+COMMENT>;
+<COMMENT>/\\* <+COMMENT>;
+<COMMENT>\\*/ <->;
+<COMMENT>. ;
+\" <STRING>'OPEN'
+<STRING>[^\"]* 'STR'
+<STRING>\" <INITIAL>'CLOSE'
+[a-z]+ 'ID'
+\\*/ <->;
+[ ] ;",
+            &[
+                "a /* b /* c */ d */ e \"f g\" h",
+                "/* unterminated",
+                "\"a",
+                "a */"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_ids() {
+        let mut lexerdef = parse_lex::<u16>("%%\n[a-z]+ 'ID'\n[0-9]+ 'INT'\n[ ] ;").unwrap();
+        let mut map = HashMap::new();
+        map.insert("ID", 0);
+        lexerdef.set_rule_ids(&map);
+        let dfa = DFA::new(&lexerdef).unwrap();
+        for input in &["a b", "a 1"] {
+            assert_eq!(
+                dfa_lex(&dfa, &lexerdef, input).map_err(|e| e.idx),
+                lexerdef.lexer(input).all_lexemes().map_err(|e| e.idx)
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        let lexerdef = parse_lex::<u16>("%%\n\\bx 'X'").unwrap();
+        assert!(DFA::new(&lexerdef).is_err());
+    }
+
+    #[test]
+    fn too_many_states() {
+        let lexerdef = parse_lex::<u16>(&format!("%%\n(a|b)*a{} 'X'", "(a|b)".repeat(14))).unwrap();
+        assert!(DFA::new(&lexerdef)
+            .err()
+            .unwrap()
+            .contains("needs more than 10000 states"));
+    }
+}
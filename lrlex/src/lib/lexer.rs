@@ -31,15 +31,76 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     hash::Hash,
+    io::Read,
     slice::Iter
 };
 
 use num_traits::{PrimInt, Unsigned};
-use regex::{self, Regex, RegexBuilder};
+use regex::{self, Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use regex_syntax::ParserBuilder;
 
-use lrpar::{LexError, Lexeme, Lexer};
+use lrpar::{ColumnKind, LexError, Lexeme, Lexer, LineIndex};
+
+use stream::StreamLexer;
+
+/// The name of the start state which all lexers begin in.
+pub const INITIAL_START_STATE: &str = "INITIAL";
+
+/// A start state (also known as a start condition), declared with `%s NAME` (inclusive) or
+/// `%x NAME` (exclusive) in a `.l` file. Rules without an explicit list of start states are active
+/// in the `INITIAL` start state and in all inclusive start states; exclusive start states only
+/// activate those rules which explicitly list them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StartState {
+    pub name: String,
+    pub exclusive: bool
+}
+
+impl StartState {
+    pub fn new(name: &str, exclusive: bool) -> StartState {
+        StartState {
+            name: name.to_string(),
+            exclusive
+        }
+    }
+}
+
+/// How a rule changes the lexer's stack of start states after it has matched.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StartStateOperation {
+    /// Replace the entire stack with the given start state (written `<NAME>` in a `.l` file).
+    ReplaceStack(usize),
+    /// Push the given start state onto the stack (written `<+NAME>`).
+    Push(usize),
+    /// Pop the top start state from the stack (written `<->`).
+    Pop
+}
+
+/// The result of calling a rule's [`Callback`](type.Callback.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CallbackResult {
+    /// Match the given number of bytes, producing a lexeme for the rule's own token (or, if the
+    /// rule has no name, skipping them).
+    Match(usize),
+    /// Match the given number of bytes, producing a lexeme for the token of the rule with the
+    /// given name.
+    Token(&'static str, usize),
+    /// Skip the given number of bytes without producing a lexeme.
+    Skip(usize),
+    /// The input at this position can't be lexed.
+    Error
+}
+
+/// A Rust function which decides what a rule matches, for tokens which can't be described by a
+/// single regular expression (e.g. nested comments). A rule names its callback with a trailing
+/// `@name` in a `.l` file, e.g. `/\* ; @block_comment`. When the rule's regular expression is the
+/// longest match at byte offset `i` of the input `s`, the lexer calls the callback with `(s, i)`
+/// and uses its result in place of the regular expression's match: the callback's match may thus
+/// be longer or shorter than the regular expression's. A callback must return a non-zero length
+/// which ends on a character boundary within `s`.
+pub type Callback = fn(&str, usize) -> CallbackResult;
 
 pub struct Rule<StorageT> {
     /// If `Some`, the ID that lexemes created against this rule will be given (lrlex gives such
@@ -50,8 +111,34 @@ pub struct Rule<StorageT> {
     /// This rule's name. If None, then text which matches this rule will be skipped (i.e. will not
     /// create a lexeme).
     pub name: Option<String>,
+    /// This rule's regular expression as written in the `.l` file (after macro expansion). As
+    /// well as the syntax of the `regex` crate, this may: start with `^`, in which case the rule
+    /// only matches at the beginning of a line; contain trailing context `r/s`, in which case the
+    /// rule matches `r` only if it is followed by `s`; or be `<<EOF>>`.
     pub re_str: String,
-    pub re: Regex
+    pub re: Regex,
+    /// True if this rule only matches at the beginning of a line (i.e. at the start of the input
+    /// or after a `\n`).
+    pub bol: bool,
+    /// True if this is an `<<EOF>>` rule, which matches (with zero width) once the end of the
+    /// input has been reached. Callbacks are not called for such rules.
+    pub eof: bool,
+    /// This rule's trailing context, if any. For the purposes of finding the longest match, the
+    /// trailing context counts as part of the match, but it is not included in the lexeme.
+    pub trailing: Option<String>,
+    /// The regular expression that the rule's lexemes match (i.e. `re_str` without any `^` or
+    /// trailing context).
+    pub(crate) head: String,
+    /// The indexes of the start states in which this rule is active. If empty, this rule is active
+    /// in all inclusive start states (including `INITIAL`).
+    pub start_states: Vec<usize>,
+    /// If `Some`, the change to the lexer's start state stack made when this rule matches.
+    pub target_state: Option<StartStateOperation>,
+    /// The name of this rule's callback, if it has one.
+    pub callback: Option<String>,
+    /// The function set for this rule's callback with `LexerDef::set_callback`. If `callback` is
+    /// `Some` but this is `None`, the rule behaves as if it had no callback.
+    pub(crate) callback_fn: Option<Callback>
 }
 
 impl<StorageT> Rule<StorageT> {
@@ -61,30 +148,251 @@ impl<StorageT> Rule<StorageT> {
     pub fn new(
         tok_id: Option<StorageT>,
         name: Option<String>,
-        re_str: String
+        re_str: String,
+        start_states: Vec<usize>,
+        target_state: Option<StartStateOperation>,
+        callback: Option<String>
     ) -> Result<Rule<StorageT>, regex::Error> {
-        let re = RegexBuilder::new(&format!("\\A(?:{})", &re_str))
-            .multi_line(true)
-            .dot_matches_new_line(true)
-            .build()?;
+        let eof = re_str == "<<EOF>>";
+        let (bol, re) = match re_str.chars().next() {
+            Some('^') => (true, &re_str[1..]),
+            _ if eof => (false, ""),
+            _ => (false, &re_str[..])
+        };
+        let (head, trailing) = match trailing_context(re) {
+            Some(j) => (re[..j].to_owned(), Some(re[j + 1..].to_owned())),
+            None => (re.to_owned(), None)
+        };
+        // When there is trailing context, the first capture group is the lexeme's text.
+        let re = RegexBuilder::new(&match trailing {
+            Some(ref t) => format!("\\A(?:({})(?:{}))", head, t),
+            None => format!("\\A(?:{})", head)
+        })
+        .multi_line(true)
+        .dot_matches_new_line(true)
+        .build()?;
         Ok(Rule {
             tok_id,
             name,
             re_str,
-            re
+            re,
+            bol,
+            eof,
+            trailing,
+            head,
+            start_states,
+            target_state,
+            callback,
+            callback_fn: None
         })
     }
+
+    /// The regular expression which determines how much input this rule matches, including any
+    /// trailing context.
+    pub(crate) fn pattern(&self) -> String {
+        match self.trailing {
+            Some(ref t) => format!("(?:{})(?:{})", self.head, t),
+            None => self.head.clone()
+        }
+    }
+
+    /// Is this rule active in the start state with index `stidx` of `start_states`?
+    pub(crate) fn active_in(&self, start_states: &[StartState], stidx: usize) -> bool {
+        if self.start_states.is_empty() {
+            !start_states[stidx].exclusive
+        } else {
+            self.start_states.contains(&stidx)
+        }
+    }
+}
+
+/// If `re` contains trailing context, return the byte offset of the `/` which separates the regular
+/// expression from its trailing context. Only the first `/` which is unescaped, outside of
+/// character classes and groups, and between two non-empty regular expressions (neither of which
+/// is itself a `/`) counts: any other `/` matches a literal `/`, so that rules such as `/` and
+/// `//[^\n]*` continue to mean what they always have.
+fn trailing_context(re: &str) -> Option<usize> {
+    let mut depth = 0; // Nesting of groups
+    let mut class_depth = 0; // Nesting of character classes
+    let mut chars = re.char_indices().peekable();
+    while let Some((j, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                class_depth += 1;
+                // A ']' at the start of a class (possibly after a '^') is a literal.
+                if let Some(&(_, '^')) = chars.peek() {
+                    chars.next();
+                }
+                if let Some(&(_, ']')) = chars.peek() {
+                    chars.next();
+                }
+            }
+            ']' if class_depth > 0 => class_depth -= 1,
+            '(' if class_depth == 0 => depth += 1,
+            ')' if class_depth == 0 && depth > 0 => depth -= 1,
+            '/' if class_depth == 0
+                && depth == 0
+                && j > 0
+                && j + 1 < re.len()
+                && !re[..j].ends_with('/')
+                && !re[j + 1..].starts_with('/') =>
+            {
+                return Some(j);
+            }
+            _ => ()
+        }
+    }
+    None
+}
+
+/// A zero-width token synthesised by a lexer in layout mode (see [`Layout`](struct.Layout.html)).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LayoutToken<StorageT> {
+    pub name: String,
+    /// The ID that lexemes for this token will be given. As with `Rule::tok_id`, if `None`, the
+    /// token should not appear in the user's input, and synthesising it is a lexing error.
+    pub tok_id: Option<StorageT>
+}
+
+impl<StorageT> LayoutToken<StorageT> {
+    pub fn new(name: &str, tok_id: Option<StorageT>) -> LayoutToken<StorageT> {
+        LayoutToken {
+            name: name.to_string(),
+            tok_id
+        }
+    }
+}
+
+/// Layout mode, for indentation-sensitive languages, declared with `%layout 'INDENT' 'DEDENT'` or
+/// `%layout 'INDENT' 'DEDENT' 'NEWLINE'` in a `.l` file. The lexer keeps a stack of indentation
+/// levels, initially containing only column 1. Whenever a lexeme is the first on its line, its
+/// column (with tabs advancing to the next multiple of 8 columns) is compared to the top of the
+/// stack: if it is greater, it is pushed and an `indent` lexeme produced; if it is smaller,
+/// levels are popped, producing a `dedent` lexeme for each, until the top of the stack is equal
+/// to it (if there is no such level, the dedent is inconsistent, which is a lexing error). If
+/// `newline` is `Some`, a `newline` lexeme is produced at the end of each line which contains at
+/// least one lexeme, before any `indent` or `dedent` lexemes for the next line. At the end of the
+/// input, a final `newline` and a `dedent` for each remaining level are produced. All such
+/// lexemes are zero-width. Lines which produce no lexemes (e.g. those containing only whitespace
+/// or comments) are ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Layout<StorageT> {
+    pub indent: LayoutToken<StorageT>,
+    pub dedent: LayoutToken<StorageT>,
+    pub newline: Option<LayoutToken<StorageT>>
+}
+
+impl<StorageT> Layout<StorageT> {
+    /// Returns an iterator over this layout's tokens.
+    pub fn iter_tokens(&self) -> impl Iterator<Item = &LayoutToken<StorageT>> {
+        Some(&self.indent)
+            .into_iter()
+            .chain(Some(&self.dedent))
+            .chain(self.newline.as_ref())
+    }
+
+    fn iter_tokens_mut(&mut self) -> impl Iterator<Item = &mut LayoutToken<StorageT>> {
+        Some(&mut self.indent)
+            .into_iter()
+            .chain(Some(&mut self.dedent))
+            .chain(self.newline.as_mut())
+    }
 }
 
 /// This struct represents, in essence, a .l file in memory. From it one can produce a `Lexer`
 /// which actually lexes inputs.
 pub struct LexerDef<StorageT> {
-    pub(crate) rules: Vec<Rule<StorageT>>
+    pub(crate) start_states: Vec<StartState>,
+    pub(crate) rules: Vec<Rule<StorageT>>,
+    pub(crate) layout: Option<Layout<StorageT>>,
+    /// The routines section of the `.l` file, if any.
+    pub(crate) programs: Option<String>,
+    /// All rules' regular expressions combined into a single automaton, so that the lexer can
+    /// find out which rules match at a given position in a single pass over the input. `None` if
+    /// the combined automaton exceeds the `regex` crate's size limits, in which case each rule is
+    /// tried in turn.
+    regex_set: Option<RegexSet>,
+    /// For each start state, the regular expressions of the rules active in it combined into a
+    /// single unanchored regular expression, so that an error-tolerant lexer can find the next
+    /// position at which a rule might match in a single pass over the input. `None` if any such
+    /// rule uses look-around assertions (since they can match differently depending on whether
+    /// the text before the rule is visible), in which case each position is tried in turn.
+    resync_res: Vec<Option<Regex>>
 }
 
 impl<StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LexerDef<StorageT> {
+    /// Create a `LexerDef` whose only start state is `INITIAL`.
     pub fn new(rules: Vec<Rule<StorageT>>) -> LexerDef<StorageT> {
-        LexerDef { rules }
+        LexerDef::new_with_start_states(vec![StartState::new(INITIAL_START_STATE, false)], rules)
+    }
+
+    /// Create a `LexerDef` with the start states `start_states`, the first of which must be
+    /// `INITIAL`.
+    pub fn new_with_start_states(
+        start_states: Vec<StartState>,
+        rules: Vec<Rule<StorageT>>
+    ) -> LexerDef<StorageT> {
+        assert_eq!(start_states[0].name, INITIAL_START_STATE);
+        // If the combined automaton is too big, we fall back to trying each rule in turn.
+        let regex_set =
+            RegexSetBuilder::new(rules.iter().map(|r| format!("\\A(?:{})", r.pattern())))
+                .multi_line(true)
+                .dot_matches_new_line(true)
+                .build()
+                .ok();
+        let mut resync_res = Vec::with_capacity(start_states.len());
+        for stidx in 0..start_states.len() {
+            let pats = rules
+                .iter()
+                .filter(|r| !r.eof && r.active_in(&start_states, stidx))
+                .map(|r| r.pattern())
+                .collect::<Vec<_>>();
+            let has_look = pats.iter().any(|p| {
+                ParserBuilder::new()
+                    .build()
+                    .parse(p)
+                    .map(|hir| !hir.properties().look_set().is_empty())
+                    .unwrap_or(true)
+            });
+            if pats.is_empty() || has_look {
+                resync_res.push(None);
+                continue;
+            }
+            let alts = pats
+                .iter()
+                .map(|p| format!("(?:{})", p))
+                .collect::<Vec<_>>();
+            // If the combined regular expression is too big, we fall back to trying each position
+            // in turn.
+            resync_res.push(
+                RegexBuilder::new(&alts.join("|"))
+                    .multi_line(true)
+                    .dot_matches_new_line(true)
+                    .build()
+                    .ok()
+            );
+        }
+        LexerDef {
+            start_states,
+            rules,
+            layout: None,
+            programs: None,
+            regex_set,
+            resync_res
+        }
+    }
+
+    /// Get the index of the start state named `n`.
+    pub fn get_start_state_by_name(&self, n: &str) -> Option<usize> {
+        self.start_states.iter().position(|ss| ss.name == n)
+    }
+
+    /// Returns an iterator over all start states in this lexer definition.
+    pub fn iter_start_states(&self) -> Iter<StartState> {
+        self.start_states.iter()
     }
 
     /// Get the `Rule` at index `idx`.
@@ -102,6 +410,23 @@ impl<StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LexerDef<StorageT> {
             .unwrap()
     }
 
+    /// Get the name of the rule or layout token with the ID `tok_id`.
+    pub fn get_name_by_id(&self, tok_id: StorageT) -> Option<&str> {
+        self.rules
+            .iter()
+            .filter(|r| r.tok_id == Some(tok_id))
+            .filter_map(|r| r.name.as_ref())
+            .chain(
+                self.layout
+                    .iter()
+                    .flat_map(|l| l.iter_tokens())
+                    .filter(|t| t.tok_id == Some(tok_id))
+                    .map(|t| &t.name)
+            )
+            .next()
+            .map(|n| n.as_str())
+    }
+
     /// Get the `Rule` instance associated with a particular name.
     pub fn get_rule_by_name(&self, n: &str) -> Option<&Rule<StorageT>> {
         self.rules
@@ -109,6 +434,35 @@ impl<StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LexerDef<StorageT> {
             .find(|r| r.name.as_ref().map(|x| x.as_str()) == Some(n))
     }
 
+    /// Get the routines section (i.e. the user code after the second `%%`) of the `.l` file this
+    /// `LexerDef` was built from, if any.
+    pub fn programs(&self) -> &Option<String> {
+        &self.programs
+    }
+
+    /// This lexer's layout mode, if it has one.
+    pub fn layout(&self) -> Option<&Layout<StorageT>> {
+        self.layout.as_ref()
+    }
+
+    /// Set this lexer's layout mode. `None` turns layout mode off.
+    pub fn set_layout(&mut self, layout: Option<Layout<StorageT>>) {
+        self.layout = layout;
+    }
+
+    /// Set the function called by rules whose callback is named `name` to `f`. Returns `false` if
+    /// no rule has a callback with that name.
+    pub fn set_callback(&mut self, name: &str, f: Callback) -> bool {
+        let mut found = false;
+        for r in &mut self.rules {
+            if r.callback.as_ref().map(|x| x.as_str()) == Some(name) {
+                r.callback_fn = Some(f);
+                found = true;
+            }
+        }
+        found
+    }
+
     /// Set the id attribute on rules to the corresponding value in `map`. This is typically used
     /// to synchronise a parser's notion of lexeme IDs with the lexers. While doing this, it keeps
     /// track of which lexemes:
@@ -128,37 +482,47 @@ impl<StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LexerDef<StorageT> {
         &'a mut self,
         rule_ids_map: &HashMap<&'a str, StorageT>
     ) -> (Option<HashSet<&'a str>>, Option<HashSet<&'a str>>) {
-        // Because we have to iter_mut over self.rules, we can't easily store a reference to the
-        // rule's name at the same time. Instead, we store the index of each such rule and
-        // recover the names later.
-        let mut missing_from_parser_idxs = Vec::new();
-        let mut rules_with_names = 0;
-        for (i, r) in self.rules.iter_mut().enumerate() {
+        for r in &mut self.rules {
             if let Some(ref n) = r.name {
-                match rule_ids_map.get(&**n) {
-                    Some(tok_id) => r.tok_id = Some(*tok_id),
-                    None => {
-                        r.tok_id = None;
-                        missing_from_parser_idxs.push(i);
-                    }
-                }
-                rules_with_names += 1;
+                r.tok_id = rule_ids_map.get(&**n).cloned();
+            }
+        }
+        if let Some(ref mut l) = self.layout {
+            for t in l.iter_tokens_mut() {
+                t.tok_id = rule_ids_map.get(&*t.name).cloned();
             }
         }
 
+        // Having set the IDs, we now only need immutable access to the names of rules and layout
+        // tokens.
+        let this: &'a LexerDef<StorageT> = self;
+        let names = this
+            .rules
+            .iter()
+            .filter_map(|r| r.name.as_ref())
+            .chain(
+                this.layout
+                    .iter()
+                    .flat_map(|l| l.iter_tokens())
+                    .map(|t| &t.name)
+            )
+            .map(|n| n.as_str())
+            .collect::<Vec<_>>();
+
+        let missing_from_parser_vec = names
+            .iter()
+            .filter(|n| !rule_ids_map.contains_key(*n))
+            .cloned()
+            .collect::<Vec<_>>();
         let missing_from_parser;
-        if missing_from_parser_idxs.is_empty() {
+        if missing_from_parser_vec.is_empty() {
             missing_from_parser = None;
         } else {
-            let mut mfp = HashSet::with_capacity(missing_from_parser_idxs.len());
-            for i in &missing_from_parser_idxs {
-                mfp.insert(self.rules[*i].name.as_ref().unwrap().as_str());
-            }
-            missing_from_parser = Some(mfp);
+            missing_from_parser = Some(missing_from_parser_vec.iter().cloned().collect());
         };
 
         let missing_from_lexer;
-        if rules_with_names - missing_from_parser_idxs.len() == rule_ids_map.len() {
+        if names.len() - missing_from_parser_vec.len() == rule_ids_map.len() {
             missing_from_lexer = None
         } else {
             missing_from_lexer = Some(
@@ -166,14 +530,7 @@ impl<StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LexerDef<StorageT> {
                     .keys()
                     .cloned()
                     .collect::<HashSet<&str>>()
-                    .difference(
-                        &self
-                            .rules
-                            .iter()
-                            .filter(|x| x.name.is_some())
-                            .map(|x| &**x.name.as_ref().unwrap())
-                            .collect::<HashSet<&str>>()
-                    )
+                    .difference(&names.iter().cloned().collect::<HashSet<&str>>())
                     .cloned()
                     .collect::<HashSet<&str>>()
             );
@@ -187,99 +544,450 @@ impl<StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LexerDef<StorageT> {
         self.rules.iter()
     }
 
+    /// Return the length of the longest match (including any trailing context) at byte offset `i`
+    /// of `s` in the start state `stidx`, and the index of the rule which matched. If no rule
+    /// matches, the length is 0. `start_bol` says whether the start of `s` is the beginning of a
+    /// line. If `acceptable` is `Some`, rules which produce lexemes whose token IDs are not in
+    /// `acceptable` are ignored, unless no other rule matches.
+    pub(crate) fn longest_match(
+        &self,
+        s: &str,
+        start_bol: bool,
+        i: usize,
+        stidx: usize,
+        acceptable: Option<&[StorageT]>
+    ) -> (usize, usize) {
+        let mut longest = 0; // Length of the longest match
+        let mut longest_ridx = 0; // This is only valid iff longest != 0
+        let bol = if i == 0 {
+            start_bol
+        } else {
+            s.as_bytes()[i - 1] == b'\n'
+        };
+        // We first use the combined automaton (if there is one) to find out which rules match at
+        // this position, and only then ask those rules for the length of their match. The indexes
+        // returned by the automaton are in ascending order.
+        let ridxs: Box<Iterator<Item = usize>> = match self.regex_set {
+            Some(ref rs) => Box::new(rs.matches(&s[i..]).into_iter()),
+            None => Box::new(0..self.rules.len())
+        };
+        for ridx in ridxs {
+            let r = &self.rules[ridx];
+            if r.eof || (r.bol && !bol) || !r.active_in(&self.start_states, stidx) {
+                continue;
+            }
+            if let Some(a) = acceptable {
+                if r.name.is_some() && !r.tok_id.map_or(false, |t| a.contains(&t)) {
+                    continue;
+                }
+            }
+            let len = match r.re.find(&s[i..]) {
+                Some(m) => m.end(),
+                None => continue
+            };
+            // Note that by using ">", we implicitly prefer an earlier over a later rule, if both
+            // match an input of the same length.
+            if len > longest {
+                longest = len;
+                longest_ridx = ridx;
+            }
+        }
+        if longest == 0 && acceptable.is_some() {
+            return self.longest_match(s, start_bol, i, stidx, None);
+        }
+        (longest, longest_ridx)
+    }
+
+    /// Return the byte offset of the first character after byte offset `i` of `s` at which a rule
+    /// matches in the start state `stidx`, or `None` if there is no such character.
+    pub(crate) fn resync(&self, s: &str, start_bol: bool, i: usize, stidx: usize) -> Option<usize> {
+        let next_char = |j: usize| s[j..].chars().next().map(|c| j + c.len_utf8());
+        let re = match self.resync_res[stidx] {
+            Some(ref re) => re,
+            None => {
+                return s[i..]
+                    .char_indices()
+                    .skip(1)
+                    .map(|(j, _)| i + j)
+                    .find(|&j| self.longest_match(s, start_bol, j, stidx, None).0 > 0)
+            }
+        };
+        // The combined regular expression finds the first position at which some rule matches,
+        // but that rule might only match the empty string, or be anchored to the beginning of a
+        // line, so we have to check each candidate position with `longest_match`. In the common
+        // case, the first candidate is the one we want, so we scan the input only once.
+        let mut j = next_char(i)?;
+        while j < s.len() {
+            j = re.find_at(s, j)?.start();
+            if self.longest_match(s, start_bol, j, stidx, None).0 > 0 {
+                return Some(j);
+            }
+            j = next_char(j)?;
+        }
+        None
+    }
+
+    /// Apply the rule at index `ridx`, which matched `longest` bytes (including any trailing
+    /// context) at byte offset `i` of `s`: call its callback (if any) and perform its start state
+    /// operation on the stack `start_states`.
+    pub(crate) fn apply_rule(
+        &self,
+        s: &str,
+        i: usize,
+        longest: usize,
+        ridx: usize,
+        start_states: &mut Vec<usize>
+    ) -> RuleResult<StorageT> {
+        let r = &self.rules[ridx];
+        // `tok` is `None` if the matched text is to be skipped, `Some(None)` if it should produce
+        // a lexeme but has no token ID, or `Some(Some(tok_id))` otherwise.
+        let mut len = match r.trailing {
+            Some(_) => r.re.captures(&s[i..]).unwrap()[1].len(),
+            None => longest
+        };
+        let mut tok = r.name.as_ref().map(|_| r.tok_id);
+        if let Some(f) = r.callback_fn {
+            match f(s, i) {
+                CallbackResult::Match(l) => len = l,
+                CallbackResult::Token(n, l) => {
+                    len = l;
+                    tok = Some(self.get_rule_by_name(n).and_then(|r| r.tok_id));
+                }
+                CallbackResult::Skip(l) => {
+                    len = l;
+                    tok = None;
+                }
+                CallbackResult::Error => return RuleResult::Error(longest)
+            }
+            assert!(
+                s.is_char_boundary(i + len),
+                "Callback {} returned an invalid length",
+                r.callback.as_ref().unwrap()
+            );
+        }
+        if len == 0 {
+            // A callback or trailing context left nothing to consume.
+            return RuleResult::Error(longest);
+        }
+        match r.target_state {
+            Some(StartStateOperation::ReplaceStack(stidx)) => {
+                start_states.clear();
+                start_states.push(stidx);
+            }
+            Some(StartStateOperation::Push(stidx)) => start_states.push(stidx),
+            Some(StartStateOperation::Pop) => {
+                if start_states.len() == 1 {
+                    // We can't pop the last start state.
+                    return RuleResult::Error(len);
+                }
+                start_states.pop();
+            }
+            None => ()
+        }
+        match tok {
+            Some(tok_id) => RuleResult::Lexeme(tok_id, len),
+            None => RuleResult::Skip(len)
+        }
+    }
+
+    /// Apply the `<<EOF>>` rule (if any) which is active in the start state `stidx`. The
+    /// resulting lengths are always 0.
+    pub(crate) fn apply_eof(&self, stidx: usize) -> RuleResult<StorageT> {
+        match self
+            .rules
+            .iter()
+            .find(|r| r.eof && r.active_in(&self.start_states, stidx))
+        {
+            Some(r) if r.name.is_some() => RuleResult::Lexeme(r.tok_id, 0),
+            _ => RuleResult::Skip(0)
+        }
+    }
+
     /// Return a lexer for the `String` `s` that will lex relative to this `LexerDef`.
     pub fn lexer<'a>(&'a self, s: &'a str) -> impl Lexer<StorageT> + 'a {
-        LRLexer::new(self, s)
+        LRLexer::new(self, s, false)
+    }
+
+    /// Return an error-tolerant lexer for the `String` `s` that will lex relative to this
+    /// `LexerDef`. Rather than stopping at the first input that can't be lexed, it returns an
+    /// error lexeme (see [`Lexeme::new_error`](../lrpar/struct.Lexeme.html#method.new_error))
+    /// covering the text up to the next point at which a rule matches, and then continues lexing.
+    /// Input matched by a rule without a token ID, or by a rule which would pop the last start
+    /// state, is also turned into an error lexeme.
+    pub fn error_tolerant_lexer<'a>(&'a self, s: &'a str) -> impl Lexer<StorageT> + 'a {
+        LRLexer::new(self, s, true)
+    }
+
+    /// Return a lexer which reads its input from `reader` (see
+    /// [`StreamLexer`](struct.StreamLexer.html)) and lexes it relative to this `LexerDef`. Panics
+    /// if this `LexerDef` has a layout.
+    pub fn stream_lexer<R: Read>(&self, reader: R) -> StreamLexer<R, StorageT> {
+        StreamLexer::new(self, reader, false)
+    }
+
+    /// Return an error-tolerant (see `error_tolerant_lexer`) lexer which reads its input from
+    /// `reader` and lexes it relative to this `LexerDef`. Panics if this `LexerDef` has a layout.
+    pub fn error_tolerant_stream_lexer<R: Read>(&self, reader: R) -> StreamLexer<R, StorageT> {
+        StreamLexer::new(self, reader, true)
     }
 }
 
+/// What a lexer should do after a rule has matched.
+pub(crate) enum RuleResult<StorageT> {
+    /// Produce a lexeme of the given length with the given token ID or, if the token ID is
+    /// `None`, report the lexeme's text as erroneous.
+    Lexeme(Option<StorageT>, usize),
+    /// Skip over the given number of bytes.
+    Skip(usize),
+    /// Report the given number of bytes as erroneous.
+    Error(usize)
+}
+
 /// A lexer holds a reference to a string and can lex it into `Lexeme`s. Although the struct is
 /// tied to a single string, no guarantees are made about whether the lexemes are cached or not.
 pub struct LRLexer<'a, StorageT: 'a> {
     lexerdef: &'a LexerDef<StorageT>,
     s: &'a str,
     i: usize,
-    newlines: Vec<usize>
+    line_index: LineIndex<'a>,
+    /// The stack of start states. This always contains at least one element, the top of which is
+    /// the current start state.
+    start_states: Vec<usize>,
+    /// If true, produce error lexemes rather than `LexError`s.
+    error_tolerant: bool,
+    /// In layout mode, the stack of indentation levels (as 0-based columns).
+    indents: Vec<usize>,
+    /// In layout mode, lexemes (or errors) which have been produced but not yet returned.
+    pending: VecDeque<Result<Lexeme<StorageT>, LexError>>,
+    /// In layout mode, the line on which the last lexeme ended, and the byte offset of its end.
+    last: Option<(usize, usize)>,
+    /// True once the end of the input (or a `LexError`) has been reached and `<<EOF>>` rules no
+    /// longer need to be considered.
+    eof_done: bool
 }
 
-impl<'a, StorageT: Copy + Eq> LRLexer<'a, StorageT> {
-    fn new(lexerdef: &'a LexerDef<StorageT>, s: &'a str) -> LRLexer<'a, StorageT> {
+impl<'a, StorageT: Copy + Eq + Hash + PrimInt + Unsigned> LRLexer<'a, StorageT> {
+    fn new(
+        lexerdef: &'a LexerDef<StorageT>,
+        s: &'a str,
+        error_tolerant: bool
+    ) -> LRLexer<'a, StorageT> {
         LRLexer {
             lexerdef,
             s,
             i: 0,
-            newlines: Vec::new()
+            line_index: LineIndex::new(s).tab_width(8),
+            start_states: vec![0],
+            error_tolerant,
+            indents: vec![0],
+            pending: VecDeque::new(),
+            last: None,
+            eof_done: false
         }
     }
-}
 
-impl<'a, StorageT: Copy + Eq + Hash + PrimInt + Unsigned> Lexer<StorageT>
-    for LRLexer<'a, StorageT>
-{
-    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+    /// Return the length of the longest match (including any trailing context) at byte offset `i`
+    /// in the current start state, and the index of the rule which matched. If no rule matches,
+    /// the length is 0. See `LexerDef::longest_match` for `acceptable`.
+    fn longest_match(&self, i: usize, acceptable: Option<&[StorageT]>) -> (usize, usize) {
+        self.lexerdef.longest_match(
+            self.s,
+            true,
+            i,
+            *self.start_states.last().unwrap(),
+            acceptable
+        )
+    }
+
+    /// Return the next lexeme produced by the rules (i.e. ignoring layout mode), preferring
+    /// lexemes whose token IDs are in `acceptable` (if it is `Some`).
+    fn next_lexeme(
+        &mut self,
+        acceptable: Option<&[StorageT]>
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
         while self.i < self.s.len() {
             let old_i = self.i;
-            let mut longest = 0; // Length of the longest match
-            let mut longest_ridx = 0; // This is only valid iff longest != 0
-            for (ridx, r) in self.lexerdef.iter_rules().enumerate() {
-                if let Some(m) = r.re.find(&self.s[old_i..]) {
-                    let len = m.end();
-                    // Note that by using ">", we implicitly prefer an earlier over a later rule, if
-                    // both match an input of the same length.
-                    if len > longest {
-                        longest = len;
-                        longest_ridx = ridx;
-                    }
-                }
-            }
+            let (longest, ridx) = self.longest_match(old_i, acceptable);
             if longest > 0 {
-                self.newlines.extend(
-                    self.s[old_i..old_i + longest]
-                        .chars()
-                        .enumerate()
-                        .filter(|&(_, c)| c == '\n')
-                        .map(|(j, _)| old_i + j + 1)
-                );
-                let r = &self.lexerdef.get_rule(longest_ridx).unwrap();
-                if r.name.is_some() {
-                    match r.tok_id {
-                        Some(tok_id) => {
-                            self.i += longest;
-                            return Some(Ok(Lexeme::new(tok_id, old_i, longest)));
-                        }
-                        None => {
-                            self.i = self.s.len();
-                            return Some(Err(LexError { idx: old_i }));
-                        }
+                match self
+                    .lexerdef
+                    .apply_rule(self.s, old_i, longest, ridx, &mut self.start_states)
+                {
+                    RuleResult::Lexeme(Some(tok_id), len) => {
+                        self.i += len;
+                        return Some(Ok(Lexeme::new(tok_id, old_i, len)));
                     }
+                    RuleResult::Lexeme(None, len) | RuleResult::Error(len) => {
+                        return self.error(old_i, old_i + len)
+                    }
+                    RuleResult::Skip(len) => self.i += len
                 }
-                self.i += longest;
+            } else if self.error_tolerant {
+                // Skip characters until we reach one where a rule matches again.
+                let end = self
+                    .lexerdef
+                    .resync(self.s, true, old_i, *self.start_states.last().unwrap())
+                    .unwrap_or_else(|| self.s.len());
+                return self.error(old_i, end);
             } else {
-                self.i = self.s.len();
-                return Some(Err(LexError { idx: old_i }));
+                return self.error(old_i, self.s.len());
             }
         }
-        None
+        if self.eof_done {
+            return None;
+        }
+        self.eof_done = true;
+        match self.lexerdef.apply_eof(*self.start_states.last().unwrap()) {
+            RuleResult::Lexeme(Some(tok_id), _) => Some(Ok(Lexeme::new(tok_id, self.i, 0))),
+            RuleResult::Lexeme(None, _) | RuleResult::Error(_) => {
+                let i = self.i;
+                self.error(i, i)
+            }
+            RuleResult::Skip(_) => None
+        }
     }
 
-    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
-        if l.start() > self.s.len() {
-            return Err(());
+    /// Queue the layout lexemes which precede the lexeme `l`, followed by `l` itself.
+    fn layout_lexeme(&mut self, layout: &Layout<StorageT>, l: Lexeme<StorageT>) {
+        let line = self.line_index.line(l.start()).unwrap();
+        if self.last.map(|(last_line, _)| last_line) != Some(line) {
+            // `l` is the first lexeme on its line.
+            if let (Some(ref t), Some((_, last_end))) = (&layout.newline, self.last) {
+                self.push_layout(t, last_end);
+            }
+            let (_, col) = self
+                .line_index
+                .line_and_col_kind(l.start(), ColumnKind::Char)
+                .unwrap();
+            let indent = col - 1;
+            if indent > *self.indents.last().unwrap() {
+                self.indents.push(indent);
+                self.push_layout(&layout.indent, l.start());
+            } else {
+                while indent < *self.indents.last().unwrap() {
+                    self.indents.pop();
+                    self.push_layout(&layout.dedent, l.start());
+                }
+                if indent != *self.indents.last().unwrap() {
+                    // An inconsistent dedent: report the line's indentation as erroneous.
+                    let line_start = self.line_index.line_span(line).unwrap().0;
+                    let e = self.layout_error(line_start, l.start());
+                    self.pending.push_back(e);
+                }
+            }
         }
+        self.last = Some((self.line_index.line(l.end() - 1).unwrap(), l.end()));
+        self.pending.push_back(Ok(l));
+    }
 
-        if self.newlines.is_empty() || l.start() < self.newlines[0] {
-            return Ok((1, l.start() + 1));
+    /// Queue the layout lexemes which follow the last lexeme in the input.
+    fn layout_eof(&mut self, layout: &Layout<StorageT>) {
+        let end = self.s.len();
+        if let (Some(ref t), Some((_, last_end))) = (&layout.newline, self.last) {
+            self.push_layout(t, last_end);
+        }
+        while self.indents.len() > 1 {
+            self.indents.pop();
+            self.push_layout(&layout.dedent, end);
         }
+        self.indents.clear();
+    }
 
-        for i in 0..self.newlines.len() - 1 {
-            if self.newlines[i + 1] > l.start() {
-                return Ok((i + 2, l.start() - self.newlines[i] + 1));
+    /// Queue a zero-width lexeme for the layout token `t` at byte offset `off`.
+    fn push_layout(&mut self, t: &LayoutToken<StorageT>, off: usize) {
+        let r = match t.tok_id {
+            Some(tok_id) => Ok(Lexeme::new(tok_id, off, 0)),
+            None => self.layout_error(off, off)
+        };
+        self.pending.push_back(r);
+    }
+
+    /// Report that the layout of the input between byte offsets `start` and `end` is erroneous.
+    /// Unlike `error`, this doesn't move the lexer on to `end` in error-tolerant mode, since
+    /// layout errors are found only after the lexemes which follow them have been lexed.
+    fn layout_error(&mut self, start: usize, end: usize) -> Result<Lexeme<StorageT>, LexError> {
+        if self.error_tolerant {
+            Ok(Lexeme::new_error(start, end - start))
+        } else {
+            self.i = self.s.len();
+            self.eof_done = true;
+            Err(LexError { idx: start })
+        }
+    }
+
+    /// Report that the input between byte offsets `start` and `end` can't be lexed.
+    fn error(&mut self, start: usize, end: usize) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        if self.error_tolerant {
+            self.i = end;
+            Some(Ok(Lexeme::new_error(start, end - start)))
+        } else {
+            self.i = self.s.len();
+            self.eof_done = true;
+            Some(Err(LexError { idx: start }))
+        }
+    }
+}
+
+impl<'a, StorageT: Copy + Eq + Hash + PrimInt + Unsigned> Lexer<StorageT>
+    for LRLexer<'a, StorageT>
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let layout = match self.lexerdef.layout {
+            Some(ref layout) => layout,
+            None => return self.next_lexeme(None)
+        };
+        if self.pending.is_empty() {
+            match self.next_lexeme(None) {
+                Some(Ok(l)) => {
+                    if l.is_error() {
+                        return Some(Ok(l));
+                    }
+                    if l.start() == self.s.len() {
+                        // A lexeme from an `<<EOF>>` rule follows the end of the input's layout.
+                        self.layout_eof(layout);
+                        self.pending.push_back(Ok(l));
+                    } else {
+                        self.layout_lexeme(layout, l);
+                    }
+                }
+                Some(Err(e)) => {
+                    self.indents.clear();
+                    return Some(Err(e));
+                }
+                None => {
+                    // `indents` always contains at least one element until we've reached the end
+                    // of the input (or a `LexError`), at which point it is emptied.
+                    if self.indents.is_empty() {
+                        return None;
+                    }
+                    self.layout_eof(layout);
+                }
             }
         }
-        Ok((
-            self.newlines.len() + 1,
-            l.start() - self.newlines[self.newlines.len() - 1] + 1
-        ))
+        let r = self.pending.pop_front();
+        if let Some(Err(_)) = r {
+            self.pending.clear();
+            self.indents.clear();
+        }
+        r
+    }
+
+    /// Only consider rules which produce lexemes whose token IDs are in `acceptable` (and rules
+    /// which skip input), unless no such rule matches, in which case all rules are considered. In
+    /// layout mode, `acceptable` is ignored, since the lexer synthesises layout lexemes in front of
+    /// the lexemes whose positions determine them.
+    fn next_acceptable(
+        &mut self,
+        acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        if self.lexerdef.layout.is_some() {
+            return self.next();
+        }
+        self.next_lexeme(Some(acceptable))
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        self.line_index.line_and_col(l.start())
     }
 
     fn input(&self) -> &str {
@@ -360,6 +1068,93 @@ if 'IF'
         assert_eq!(lex2.len(), 2);
     }
 
+    #[test]
+    fn test_combined_automaton() {
+        // The combined automaton only tells us which rules match: check that the longest match,
+        // and the earliest rule on a tie, still win, in each start state.
+        let src = "%x S
+%%
+if 'IF'
+[a-z]+ 'ID'
+<S>i[a-z] 'I2'
+<INITIAL,S>[a-z]{3} 'ID3'
+<S>[a-z]+ 'IDS'
+[ ] ;"
+            .to_string();
+        let lexerdef = parse_lex::<u8>(&src).unwrap();
+        let name = |(len, ridx): (usize, usize)| match len {
+            0 => None,
+            _ => lexerdef.rules[ridx].name.clone()
+        };
+        let cases = [
+            (0, "if", Some("IF")),
+            (0, "iff", Some("ID")),
+            (0, "ifs ", Some("ID")),
+            (0, "abc", Some("ID")),
+            (0, " ", None),
+            (1, "if", Some("I2")),
+            (1, "iff", Some("ID3")),
+            (1, "iffy", Some("IDS")),
+            (1, " ", None)
+        ];
+        for &(stidx, input, expected) in &cases {
+            assert_eq!(
+                name(lexerdef.longest_match(input, true, 0, stidx, None)),
+                expected.map(|s| s.to_owned())
+            );
+        }
+
+        // The same results as trying each rule in turn.
+        for &(stidx, input, _) in &cases {
+            for i in 0..input.len() {
+                let mut expected = (0, 0);
+                for (ridx, r) in lexerdef.rules.iter().enumerate() {
+                    if !r.active_in(&lexerdef.start_states, stidx) {
+                        continue;
+                    }
+                    if let Some(m) = r.re.find(&input[i..]) {
+                        if m.end() > expected.0 {
+                            expected = (m.end(), ridx);
+                        }
+                    }
+                }
+                assert_eq!(
+                    lexerdef.longest_match(input, true, i, stidx, None),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_combined_automaton_too_big() {
+        // Each rule is small enough to compile on its own, but together they exceed the regex
+        // crate's size limits, so the lexer falls back to trying each rule in turn.
+        let src = format!(
+            "%%\n{}[ ] ;\n",
+            (0..5)
+                .map(|i| format!("\\w{{60}}x{} 'X{}'\n", i, i))
+                .collect::<String>()
+        );
+        let mut lexerdef = parse_lex::<u8>(&src).unwrap();
+        assert!(lexerdef.regex_set.is_none());
+        let mut map = HashMap::new();
+        for i in 0..5 {
+            map.insert(format!("X{}", i), i);
+        }
+        let map = map.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+        let input = format!("{}x3 {}x1", "a".repeat(60), "b".repeat(60));
+        let lexemes = lexerdef.lexer(&input).all_lexemes().unwrap();
+        assert_eq!(
+            lexemes
+                .iter()
+                .map(|l| (l.tok_id(), l.start(), l.len()))
+                .collect::<Vec<_>>(),
+            vec![(3, 0, 62), (1, 63, 62)]
+        );
+    }
+
     #[test]
     fn test_line_and_col() {
         let src = "%%
@@ -393,6 +1188,12 @@ if 'IF'
         if let Ok(_) = lexer.line_and_col(&fake_lexeme) {
             panic!("line_and_col returned Ok(_) when it should have returned Err.");
         }
+
+        // Lexemes which haven't yet been lexed can still be queried.
+        let mut lexer = lexerdef.lexer(" a\nb\n  c d");
+        let lexeme = lexer.next().unwrap().unwrap();
+        assert_eq!(lexer.line_and_col(&lexeme).unwrap(), (1, 2));
+        assert_eq!(lexer.line_and_col(&Lexeme::new(0, 9, 1)).unwrap(), (3, 5));
     }
 
     #[test]
@@ -419,4 +1220,442 @@ if 'IF'
             Err(e) => panic!("Incorrect error returned {:?}", e)
         };
     }
+
+    #[test]
+    fn test_start_states() {
+        let src = r#"%x COMMENT STRING
+%%
+/\* <+COMMENT>;
+<COMMENT>/\* <+COMMENT>;
+<COMMENT>\*/ <->;
+<COMMENT>. ;
+" <STRING>;
+<STRING>[^"]* 'STR'
+<STRING>" <INITIAL>;
+[a-z]+ 'ID'
+[ ] ;"#;
+        let mut lexerdef = parse_lex(&src).unwrap();
+        let mut map = HashMap::new();
+        map.insert("ID", 0u8);
+        map.insert("STR", 1u8);
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+
+        // Note that since rules without a start state aren't active in exclusive start states, the
+        // space in the string doesn't split it into two lexemes.
+        let lexemes = lexerdef
+            .lexer(&"a /* b /* c */ d */ e \"f g\" h")
+            .all_lexemes()
+            .unwrap();
+        assert_eq!(
+            lexemes,
+            vec![
+                Lexeme::new(0, 0, 1),
+                Lexeme::new(0, 20, 1),
+                Lexeme::new(1, 23, 3),
+                Lexeme::new(0, 28, 1),
+            ]
+        );
+
+        // Popping the last start state is an error.
+        let src = r"%%
+a <->;";
+        let lexerdef = parse_lex::<u8>(&src).unwrap();
+        match lexerdef.lexer(&"a").all_lexemes() {
+            Ok(_) => panic!("Invalid input lexed"),
+            Err(LexError { idx: 0 }) => (),
+            Err(e) => panic!("Incorrect error returned {:?}", e)
+        };
+    }
+
+    #[test]
+    fn test_inclusive_start_states() {
+        let src = r"%s KW
+%%
+<KW>if 'IF'
+kw <KW>;
+[a-z]+ 'ID'
+[ ] ;";
+        let mut lexerdef = parse_lex(&src).unwrap();
+        let mut map = HashMap::new();
+        map.insert("IF", 0u8);
+        map.insert("ID", 1u8);
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+
+        let lexemes = lexerdef.lexer(&"if kw if x").all_lexemes().unwrap();
+        assert_eq!(
+            lexemes.iter().map(|l| l.tok_id()).collect::<Vec<_>>(),
+            vec![1, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let src = r"DIGIT [0-9]
+%%
+{DIGIT}+\.{DIGIT}+ 'float'
+{DIGIT}+ 'int'
+[ ] ;";
+        let mut lexerdef = parse_lex(&src).unwrap();
+        let mut map = HashMap::new();
+        map.insert("float", 0u8);
+        map.insert("int", 1u8);
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+
+        let lexemes = lexerdef.lexer(&"12 3.45").all_lexemes().unwrap();
+        assert_eq!(lexemes, vec![Lexeme::new(1, 0, 2), Lexeme::new(0, 3, 4)]);
+    }
+
+    #[test]
+    fn test_error_tolerant() {
+        let src = "%x STR
+%%
+[a-z]+ 'ID'
+[0-9]+ 'INT'
+\" <+STR>;
+<STR>\" <->;
+<STR>[^\"]+ 'STR'
+\\) <->;
+[ \\n] ;"
+            .to_string();
+        let mut lexerdef = parse_lex(&src).unwrap();
+        let mut map = HashMap::new();
+        map.insert("ID", 0u8);
+        map.insert("STR", 1u8);
+        assert_eq!(
+            lexerdef.set_rule_ids(&map),
+            (None, Some(vec!["INT"].into_iter().collect()))
+        );
+
+        let lexemes = lexerdef
+            .error_tolerant_lexer(&"a ?! b\n12 \u{e9}c")
+            .all_lexemes()
+            .unwrap();
+        assert_eq!(
+            lexemes,
+            vec![
+                Lexeme::new(0, 0, 1),
+                Lexeme::new_error(2, 2),
+                Lexeme::new(0, 5, 1),
+                Lexeme::new_error(7, 2),
+                Lexeme::new_error(10, 2),
+                Lexeme::new(0, 12, 1),
+            ]
+        );
+        assert!(lexemes[1].is_error() && !lexemes[2].is_error());
+
+        // A rule which would pop the last start state produces an error lexeme.
+        let mut lexer = lexerdef.error_tolerant_lexer(&")\"a\")");
+        let lexemes = lexer.all_lexemes().unwrap();
+        assert_eq!(
+            lexemes,
+            vec![
+                Lexeme::new_error(0, 1),
+                Lexeme::new(1, 2, 1),
+                Lexeme::new_error(4, 1)
+            ]
+        );
+
+        let mut lexer = lexerdef.error_tolerant_lexer(&"a\n?\nb");
+        let lexemes = lexer.all_lexemes().unwrap();
+        assert_eq!(lexer.line_and_col(&lexemes[1]).unwrap(), (2, 1));
+        assert_eq!(lexer.line_and_col(&lexemes[2]).unwrap(), (3, 1));
+    }
+
+    #[test]
+    fn test_resync() {
+        // The first lexer can use a single combined regular expression to resynchronise, but it
+        // has rules which can match the empty string or only at the start of a line, so not every
+        // position it finds is one at which a rule matches. The second lexer uses a look-around
+        // assertion, so has to try each position in turn.
+        let srcs = [
+            "%%\n^#[a-z]+ 'DIR'\n[a-z]* 'ID'\n[ \\n] ;",
+            "%%\n\\bq 'Q'\n[a-z]+ 'ID'\n[ ] ;"
+        ];
+        for src in &srcs {
+            let lexerdef = parse_lex::<u8>(src).unwrap();
+            for input in &["?#a ?? b", "?\n#a", "??\u{e9}#\n?", "?aq ?q"] {
+                for (i, _) in input.char_indices() {
+                    let expected = input[i..]
+                        .char_indices()
+                        .skip(1)
+                        .map(|(j, _)| i + j)
+                        .find(|&j| lexerdef.longest_match(input, true, j, 0, None).0 > 0);
+                    assert_eq!(lexerdef.resync(input, true, i, 0), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_callbacks() {
+        // Nested block comments.
+        fn comment(s: &str, i: usize) -> CallbackResult {
+            let mut depth = 0;
+            let mut j = i;
+            while j < s.len() {
+                if s[j..].starts_with("/*") {
+                    depth += 1;
+                    j += 2;
+                } else if s[j..].starts_with("*/") {
+                    depth -= 1;
+                    j += 2;
+                    if depth == 0 {
+                        return CallbackResult::Skip(j - i);
+                    }
+                } else {
+                    j += s[j..].chars().next().unwrap().len_utf8();
+                }
+            }
+            CallbackResult::Error
+        }
+
+        // `if` is only a keyword if it isn't preceded by a `.`.
+        fn ctx_if(s: &str, i: usize) -> CallbackResult {
+            if s[..i].ends_with('.') {
+                CallbackResult::Token("ID", 2)
+            } else {
+                CallbackResult::Match(2)
+            }
+        }
+
+        let src = "%%
+/\\* ; @comment
+if 'IF' @ctx_if
+[a-z]+ 'ID'
+\\. '.'
+[ ] ;"
+            .to_string();
+        let mut lexerdef = parse_lex(&src).unwrap();
+        let mut map = HashMap::new();
+        map.insert("IF", 0u8);
+        map.insert("ID", 1);
+        map.insert(".", 2);
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+
+        // Until callbacks are set, rules behave as if they had none.
+        let lexemes = lexerdef.lexer("x.if /* iff").all_lexemes().unwrap();
+        assert_eq!(
+            lexemes.iter().map(|l| l.tok_id()).collect::<Vec<_>>(),
+            vec![1, 2, 0, 1]
+        );
+
+        assert!(lexerdef.set_callback("comment", comment));
+        assert!(lexerdef.set_callback("ctx_if", ctx_if));
+        assert!(!lexerdef.set_callback("unknown", comment));
+        let lexemes = lexerdef
+            .lexer("if x.if /* a /* b */ c */ iff")
+            .all_lexemes()
+            .unwrap();
+        assert_eq!(
+            lexemes,
+            vec![
+                Lexeme::new(0, 0, 2),
+                Lexeme::new(1, 3, 1),
+                Lexeme::new(2, 4, 1),
+                Lexeme::new(1, 5, 2),
+                Lexeme::new(1, 26, 3),
+            ]
+        );
+        match lexerdef.lexer("x /* y").all_lexemes() {
+            Err(LexError { idx: 2 }) => (),
+            _ => panic!()
+        };
+    }
+
+    #[test]
+    fn test_layout() {
+        let src = "%layout 'INDENT' 'DEDENT' 'NEWLINE'
+%%
+[a-z]+ 'ID'
+: ':'
+#[^\\n]* ;
+[ \\t\\n]+ ;"
+            .to_string();
+        let mut lexerdef = parse_lex(&src).unwrap();
+        let mut map = HashMap::new();
+        for (i, n) in ["ID", ":", "INDENT", "DEDENT", "NEWLINE"]
+            .iter()
+            .enumerate()
+        {
+            map.insert(*n, i as u8);
+        }
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+
+        let names = |lexemes: &[Lexeme<u8>]| {
+            lexemes
+                .iter()
+                .map(|l| {
+                    if l.is_error() {
+                        "ERROR"
+                    } else {
+                        lexerdef.get_name_by_id(l.tok_id()).unwrap()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let lexemes = lexerdef
+            .lexer("if:\n  a\n\n  # comment\n  if:\n\tb\nc\n")
+            .all_lexemes()
+            .unwrap();
+        assert_eq!(
+            names(&lexemes),
+            "ID : NEWLINE INDENT ID NEWLINE ID : NEWLINE INDENT ID NEWLINE DEDENT DEDENT ID NEWLINE"
+        );
+        // Layout lexemes are zero-width: NEWLINE is placed at the end of the preceding lexeme, and
+        // INDENT and DEDENT at the start of the following lexeme.
+        assert_eq!(lexemes[2], Lexeme::new(4, 3, 0));
+        assert_eq!(lexemes[3], Lexeme::new(2, 6, 0));
+        assert_eq!(lexemes[12], Lexeme::new(3, 30, 0));
+        assert_eq!(lexemes[15], Lexeme::new(4, 31, 0));
+
+        let lexemes = lexerdef.lexer("a:\n  b").all_lexemes().unwrap();
+        assert_eq!(names(&lexemes), "ID : NEWLINE INDENT ID NEWLINE DEDENT");
+        assert_eq!(lexerdef.lexer("").all_lexemes().unwrap(), vec![]);
+
+        // Inconsistent dedents.
+        match lexerdef.lexer("a\n    b\n  c").all_lexemes() {
+            Err(LexError { idx: 8 }) => (),
+            _ => panic!()
+        };
+        let lexemes = lexerdef
+            .error_tolerant_lexer("a\n    b\n  c")
+            .all_lexemes()
+            .unwrap();
+        assert_eq!(
+            names(&lexemes),
+            "ID NEWLINE INDENT ID NEWLINE DEDENT ERROR ID NEWLINE"
+        );
+        assert_eq!(lexemes[6], Lexeme::new_error(8, 2));
+    }
+
+    #[test]
+    fn test_anchors_and_trailing_context() {
+        let src = "%x COMMENT
+%%
+^#[a-z]+ 'DIRECTIVE'
+# '#'
+DO/[a-z0-9 ]*=[a-z0-9 ]*, 'DO'
+[a-z][a-z0-9]* 'ID'
+[0-9]+ 'INT'
+= '='
+, ','
+/\\* <+COMMENT>;
+<COMMENT>\\*/ <->;
+<COMMENT>[^*]+|\\* ;
+[ \\n]+ ;
+<<EOF>> 'EOF'
+<COMMENT><<EOF>> 'UNTERMINATED'"
+            .to_string();
+        let mut lexerdef = parse_lex(&src).unwrap();
+        let mut map = HashMap::new();
+        for (i, n) in ["DIRECTIVE", "#", "DO", "ID", "INT", "=", ",", "EOF"]
+            .iter()
+            .enumerate()
+        {
+            map.insert(*n, i as u8);
+        }
+        assert_eq!(
+            lexerdef.set_rule_ids(&map),
+            (None, Some(["UNTERMINATED"].iter().cloned().collect()))
+        );
+        let lex = |s: &str| {
+            lexerdef
+                .lexer(s)
+                .all_lexemes()
+                .map(|lexemes| {
+                    lexemes
+                        .iter()
+                        .map(|l| {
+                            format!(
+                                "{}:{}",
+                                lexerdef.get_name_by_id(l.tok_id()).unwrap(),
+                                &s[l.start()..l.end()]
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .map_err(|e| e.idx)
+        };
+
+        // `^` only matches at the beginning of a line.
+        assert_eq!(
+            lex("#if x\n #if"),
+            Ok("DIRECTIVE:#if ID:x #:# ID:if EOF:".to_string())
+        );
+        // Trailing context isn't consumed, but does count towards the longest match.
+        assert_eq!(
+            lex("DO1 i=1,2"),
+            Ok("DO:DO INT:1 ID:i =:= INT:1 ,:, INT:2 EOF:".to_string())
+        );
+        assert_eq!(lex("DO1 i=1"), Err(0));
+        // `<<EOF>>` rules match once, in the appropriate start state, at the end of the input.
+        let lexemes = lexerdef.lexer("x").all_lexemes().unwrap();
+        assert_eq!(lexemes[1], Lexeme::new(7, 1, 0));
+        assert_eq!(lex(""), Ok("EOF:".to_string()));
+        assert_eq!(lex("/* x */"), Ok("EOF:".to_string()));
+        assert_eq!(lex("x /* y"), Err(6));
+        let lexemes = lexerdef
+            .error_tolerant_lexer("x /* y")
+            .all_lexemes()
+            .unwrap();
+        assert_eq!(lexemes.len(), 2);
+        assert!(lexemes[1].is_error());
+        assert_eq!((lexemes[1].start(), lexemes[1].len()), (6, 0));
+
+        // In layout mode, `<<EOF>>` lexemes come after the final layout lexemes.
+        let src = "%layout 'INDENT' 'DEDENT' 'NEWLINE'
+%%
+[a-z]+ 'ID'
+[ \\n]+ ;
+<<EOF>> 'EOF'"
+            .to_string();
+        let mut lexerdef = parse_lex::<u8>(&src).unwrap();
+        let mut map = HashMap::new();
+        for (i, n) in ["ID", "EOF", "INDENT", "DEDENT", "NEWLINE"]
+            .iter()
+            .enumerate()
+        {
+            map.insert(*n, i as u8);
+        }
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+        let lexemes = lexerdef.lexer("a\n  b").all_lexemes().unwrap();
+        assert_eq!(
+            lexemes
+                .iter()
+                .map(|l| lexerdef.get_name_by_id(l.tok_id()).unwrap())
+                .collect::<Vec<_>>(),
+            vec!["ID", "NEWLINE", "INDENT", "ID", "NEWLINE", "DEDENT", "EOF"]
+        );
+        assert_eq!(
+            lexerdef.lexer("").all_lexemes().unwrap(),
+            vec![Lexeme::new(1, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_next_acceptable() {
+        let src = "%%
+where 'WHERE'
+[a-z]+ 'ID'
+[0-9]+ 'INT'
+[ ]+ ;"
+            .to_string();
+        let mut lexerdef = parse_lex::<u8>(&src).unwrap();
+        let mut map = HashMap::new();
+        map.insert("WHERE", 0);
+        map.insert("ID", 1);
+        map.insert("INT", 2);
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+
+        let mut lexer = lexerdef.lexer("where where where 12");
+        assert_eq!(lexer.next_acceptable(&[1]).unwrap().unwrap().tok_id(), 1);
+        assert_eq!(lexer.next_acceptable(&[0, 1]).unwrap().unwrap().tok_id(), 0);
+        assert_eq!(lexer.next().unwrap().unwrap().tok_id(), 0);
+        // If no acceptable rule matches, all rules are considered.
+        assert_eq!(lexer.next_acceptable(&[1]).unwrap().unwrap().tok_id(), 2);
+        assert!(lexer.next_acceptable(&[1]).is_none());
+    }
 }
@@ -30,9 +30,15 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+#[cfg(test)]
+extern crate cfgrammar;
 extern crate lrpar;
+#[cfg(test)]
+extern crate lrtable;
 extern crate num_traits;
 extern crate regex;
+extern crate regex_syntax;
+extern crate serde;
 extern crate try_from;
 extern crate typename;
 
@@ -41,13 +47,21 @@ use std::{error::Error, fmt, hash::Hash};
 use num_traits::{PrimInt, Unsigned};
 use try_from::TryFrom;
 
+pub mod analysis;
 mod builder;
+mod dfa;
 mod lexer;
 mod parser;
+mod stream;
 
-pub use builder::LexerBuilder;
-pub use lexer::{LexerDef, Rule};
+pub use analysis::{LexWarning, LexWarningKind};
+pub use builder::{LexerBuilder, LexerKind, LexerParserBuilder, TokenMismatchError};
+pub use lexer::{
+    Callback, CallbackResult, Layout, LayoutToken, LexerDef, Rule, StartState, StartStateOperation,
+    INITIAL_START_STATE
+};
 use parser::parse_lex;
+pub use stream::StreamLexer;
 
 pub type LexBuildResult<T> = Result<T, LexBuildError>;
 
@@ -65,27 +79,60 @@ impl Error for LexBuildError {}
 #[derive(Debug)]
 pub enum LexErrorKind {
     PrematureEnd,
+    #[deprecated(note = "routines sections are now supported, so this error is never returned")]
     RoutinesNotSupported,
     UnknownDeclaration,
     MissingSpace,
     InvalidName,
     DuplicateName,
-    RegexError
+    RegexError,
+    InvalidStartStateName,
+    DuplicateStartState,
+    UnknownStartState,
+    DuplicateMacro,
+    UnknownMacro,
+    RecursiveMacro,
+    InvalidCallbackName,
+    InvalidLayout,
+    DuplicateLayout
 }
 
-impl fmt::Display for LexBuildError {
+impl LexBuildError {
+    /// The line and column (both starting from 1) at which the error was found.
+    pub fn line_col(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s;
-        match self.kind {
+        match *self {
             LexErrorKind::PrematureEnd => s = "File ends prematurely",
             LexErrorKind::RoutinesNotSupported => s = "Routines not currently supported",
             LexErrorKind::UnknownDeclaration => s = "Unknown declaration",
             LexErrorKind::MissingSpace => s = "Rule is missing a space",
             LexErrorKind::InvalidName => s = "Invalid rule name",
             LexErrorKind::DuplicateName => s = "Rule name already exists",
-            LexErrorKind::RegexError => s = "Invalid regular expression"
+            LexErrorKind::RegexError => s = "Invalid regular expression",
+            LexErrorKind::InvalidStartStateName => s = "Invalid start state name",
+            LexErrorKind::DuplicateStartState => s = "Start state already exists",
+            LexErrorKind::UnknownStartState => s = "Unknown start state",
+            LexErrorKind::DuplicateMacro => s = "Macro already exists",
+            LexErrorKind::UnknownMacro => s = "Unknown macro",
+            LexErrorKind::RecursiveMacro => s = "Recursive macro",
+            LexErrorKind::InvalidCallbackName => s = "Invalid callback name",
+            LexErrorKind::InvalidLayout => s = "Layout must declare two or three token names",
+            LexErrorKind::DuplicateLayout => s = "Layout already declared"
         }
-        write!(f, "{} at line {} column {}", s, self.line, self.col)
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for LexBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {} column {}", self.kind, self.line, self.col)
     }
 }
 
@@ -35,7 +35,9 @@ use std::hash::Hash;
 use num_traits::{PrimInt, Unsigned};
 use try_from::TryFrom;
 
-use lexer::{LexerDef, Rule};
+use lexer::{
+    Layout, LayoutToken, LexerDef, Rule, StartState, StartStateOperation, INITIAL_START_STATE
+};
 use LexBuildError;
 use LexBuildResult;
 use LexErrorKind;
@@ -43,18 +45,42 @@ use LexErrorKind;
 pub struct LexParser<StorageT> {
     src: String,
     newlines: Vec<usize>,
-    rules: Vec<Rule<StorageT>>
+    start_states: Vec<StartState>,
+    /// The named regular expressions ("macros") from the declarations section, as `(name, byte
+    /// offset of the regular expression, regular expression)` triples.
+    macros: Vec<(String, usize, String)>,
+    /// The names of the tokens declared by `%layout`, if any, and their byte offsets.
+    layout_names: Option<Vec<(usize, String)>>,
+    rules: Vec<Rule<StorageT>>,
+    layout: Option<Layout<StorageT>>,
+    programs: Option<String>,
+    /// The errors found so far. Since declarations and rules each take up a single line, after an
+    /// error the parser skips to the next line and carries on, so that as many errors as possible
+    /// can be reported at once.
+    errs: Vec<LexBuildError>
 }
 
 impl<StorageT: TryFrom<usize>> LexParser<StorageT> {
-    fn new(src: String) -> LexBuildResult<LexParser<StorageT>> {
+    fn new(src: String) -> Result<LexParser<StorageT>, Vec<LexBuildError>> {
         let mut p = LexParser {
             src,
             newlines: vec![0],
-            rules: Vec::new()
+            start_states: vec![StartState::new(INITIAL_START_STATE, false)],
+            macros: Vec::new(),
+            layout_names: None,
+            rules: Vec::new(),
+            layout: None,
+            programs: None,
+            errs: Vec::new()
         };
-        p.parse()?;
-        Ok(p)
+        match p.parse() {
+            Ok(_) if p.errs.is_empty() => Ok(p),
+            Ok(_) => Err(p.errs),
+            Err(e) => {
+                p.errs.push(e);
+                Err(p.errs)
+            }
+        }
     }
 
     fn mk_error(&self, kind: LexErrorKind, off: usize) -> LexBuildError {
@@ -87,14 +113,35 @@ impl<StorageT: TryFrom<usize>> LexParser<StorageT> {
     fn parse(&mut self) -> LexBuildResult<usize> {
         let mut i = self.parse_declarations(0)?;
         i = self.parse_rules(i)?;
-        // We don't currently support the subroutines part of a specification. One day we might...
+        // Layout tokens are given IDs after those of the rules.
+        if let Some(ref names) = self.layout_names {
+            let rules_len = self.rules.len();
+            let mut toks = names
+                .iter()
+                .enumerate()
+                .map(|(j, n)| LayoutToken::new(&n.1, Some(new_tok_id(rules_len + j))))
+                .collect::<Vec<_>>();
+            let newline = if toks.len() == 3 { toks.pop() } else { None };
+            let dedent = toks.pop().unwrap();
+            let indent = toks.pop().unwrap();
+            self.layout = Some(Layout {
+                indent,
+                dedent,
+                newline
+            });
+        }
+        self.parse_programs(i)
+    }
+
+    /// Parse the optional routines section after the second `%%`, which is kept verbatim.
+    fn parse_programs(&mut self, i: usize) -> LexBuildResult<usize> {
         match self.lookahead_is("%%", i) {
             Some(j) => {
-                if self.parse_ws(j)? == self.src.len() {
-                    Ok(i)
-                } else {
-                    Err(self.mk_error(LexErrorKind::RoutinesNotSupported, i))
+                let k = self.parse_ws(j)?;
+                if k < self.src.len() {
+                    self.programs = Some(self.src[k..].to_string());
                 }
+                Ok(self.src.len())
             }
             None => {
                 assert_eq!(i, self.src.len());
@@ -104,14 +151,290 @@ impl<StorageT: TryFrom<usize>> LexParser<StorageT> {
     }
 
     fn parse_declarations(&mut self, mut i: usize) -> LexBuildResult<usize> {
-        i = self.parse_ws(i)?;
-        if let Some(j) = self.lookahead_is("%%", i) {
-            return Ok(j);
+        loop {
+            i = self.parse_ws(i)?;
+            if let Some(j) = self.lookahead_is("%%", i) {
+                // Check that all macros can be expanded, so that errors are reported even for
+                // unused macros.
+                for k in 0..self.macros.len() {
+                    let (ref name, off, ref re) = self.macros[k];
+                    if let Err(e) = self.expand_macros(re, off, &mut vec![name.clone()]) {
+                        self.push_error(e);
+                    }
+                }
+                return Ok(j);
+            }
+            if i == self.src.len() {
+                return Err(self.mk_error(LexErrorKind::PrematureEnd, i - 1));
+            }
+            i = match self.parse_declaration(i) {
+                Ok(j) => j,
+                Err(e) => {
+                    self.push_error(e);
+                    self.skip_line(i)
+                }
+            };
+        }
+    }
+
+    /// Parse the declaration (a macro, `%layout`, `%x`, or `%s`) starting at `i`.
+    fn parse_declaration(&mut self, i: usize) -> LexBuildResult<usize> {
+        if self.src[i..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return self.parse_macro(i);
+        }
+        if let Some(j) = self.lookahead_is("%layout", i) {
+            return self.parse_layout(i, j);
+        }
+        let exclusive = if self.lookahead_is("%x", i).is_some() {
+            true
+        } else if self.lookahead_is("%s", i).is_some() {
+            false
+        } else {
+            return Err(self.mk_error(LexErrorKind::UnknownDeclaration, i));
+        };
+        match self.src[i + 2..].chars().next() {
+            Some(' ') | Some('\t') => (),
+            _ => return Err(self.mk_error(LexErrorKind::UnknownDeclaration, i))
+        }
+        self.parse_start_states(i + 2, exclusive)
+    }
+
+    /// Parse a layout declaration `%layout 'INDENT' 'DEDENT'` or `%layout 'INDENT' 'DEDENT'
+    /// 'NEWLINE'` starting at byte offset `decl_off`, whose names start at byte offset `i`.
+    fn parse_layout(&mut self, decl_off: usize, mut i: usize) -> LexBuildResult<usize> {
+        if self.layout_names.is_some() {
+            return Err(self.mk_error(LexErrorKind::DuplicateLayout, decl_off));
+        }
+        let line_end = self.src[i..]
+            .find(|c| c == '\n')
+            .map(|j| i + j)
+            .unwrap_or_else(|| self.src.len());
+        match self.src[i..line_end].chars().next() {
+            Some(' ') | Some('\t') => (),
+            _ => return Err(self.mk_error(LexErrorKind::UnknownDeclaration, decl_off))
+        }
+        let mut names: Vec<(usize, String)> = Vec::new();
+        loop {
+            i += self.src[i..line_end].len() - self.src[i..line_end].trim_left().len();
+            if i == line_end {
+                break;
+            }
+            let name_end = self.src[i..line_end]
+                .find(char::is_whitespace)
+                .map(|j| i + j)
+                .unwrap_or(line_end);
+            let name = match unquote_name(&self.src[i..name_end]) {
+                Some(n) => n.to_string(),
+                None => return Err(self.mk_error(LexErrorKind::InvalidName, i))
+            };
+            if names.iter().any(|n| n.1 == name) {
+                return Err(self.mk_error(LexErrorKind::DuplicateName, i));
+            }
+            names.push((i, name));
+            i = name_end;
+        }
+        if names.len() < 2 || names.len() > 3 {
+            return Err(self.mk_error(LexErrorKind::InvalidLayout, decl_off));
+        }
+        self.layout_names = Some(names);
+        Ok(i)
+    }
+
+    /// Parse a macro definition of the form `NAME regex` up to the end of the line.
+    fn parse_macro(&mut self, i: usize) -> LexBuildResult<usize> {
+        let line_end = self.src[i..]
+            .find(|c| c == '\n')
+            .map(|j| i + j)
+            .unwrap_or_else(|| self.src.len());
+        let name_end = match self.src[i..line_end].find(|c| c == ' ' || c == '\t') {
+            Some(j) => i + j,
+            None => return Err(self.mk_error(LexErrorKind::MissingSpace, i))
+        };
+        let name = &self.src[i..name_end];
+        if !is_identifier(name) {
+            return Err(self.mk_error(LexErrorKind::InvalidName, i));
+        }
+        if self.macros.iter().any(|&(ref n, _, _)| n == name) {
+            return Err(self.mk_error(LexErrorKind::DuplicateMacro, i));
+        }
+        let re = self.src[name_end..line_end].trim();
+        let re_off = line_end - self.src[name_end..line_end].trim_left().len();
+        if re.is_empty() {
+            return Err(self.mk_error(LexErrorKind::MissingSpace, i));
+        }
+        self.macros.push((name.to_owned(), re_off, re.to_owned()));
+        Ok(line_end)
+    }
+
+    /// Expand all references to macros (of the form `{NAME}`) in the regular expression `re`,
+    /// which starts at byte offset `off` in the source. Each macro is wrapped in a non-capturing
+    /// group, so `{DIGIT}+` has the expected meaning. `{` characters that are escaped, that occur
+    /// within a character class, or that are not followed by `NAME}` (e.g. the repetition
+    /// `a{2,3}`) are left untouched. `expanding` is the stack of macros currently being expanded,
+    /// used to detect recursive macros.
+    fn expand_macros(
+        &self,
+        re: &str,
+        off: usize,
+        expanding: &mut Vec<String>
+    ) -> LexBuildResult<String> {
+        let mut out = String::with_capacity(re.len());
+        let mut in_class = false;
+        let mut chars = re.char_indices().peekable();
+        while let Some((j, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    out.push(c);
+                    if let Some((_, c)) = chars.next() {
+                        out.push(c);
+                        // Escapes such as `\p{Greek}` and `\x{1F600}` take a braced argument,
+                        // which isn't a macro reference.
+                        if let Some(&(_, '{')) = chars.peek() {
+                            if c.is_ascii_alphabetic() {
+                                while let Some((_, c)) = chars.next() {
+                                    out.push(c);
+                                    if c == '}' {
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    continue;
+                }
+                '[' if !in_class => {
+                    in_class = true;
+                    out.push(c);
+                    // A ']' at the start of a class (possibly after a '^') is a literal.
+                    if let Some(&(_, '^')) = chars.peek() {
+                        out.push(chars.next().unwrap().1);
+                    }
+                    if let Some(&(_, ']')) = chars.peek() {
+                        out.push(chars.next().unwrap().1);
+                    }
+                    continue;
+                }
+                ']' if in_class => in_class = false,
+                '{' if !in_class => {
+                    let name = re[j + 1..].split('}').next().unwrap();
+                    if re[j + 1 + name.len()..].starts_with('}') && is_identifier(name) {
+                        if expanding.iter().any(|n| n == name) {
+                            return Err(self.mk_error(LexErrorKind::RecursiveMacro, off + j));
+                        }
+                        match self.macros.iter().find(|&&(ref n, _, _)| n == name) {
+                            Some(&(_, m_off, ref m_re)) => {
+                                expanding.push(name.to_owned());
+                                let expanded = self.expand_macros(m_re, m_off, expanding)?;
+                                expanding.pop();
+                                out.push_str("(?:");
+                                out.push_str(&expanded);
+                                out.push(')');
+                            }
+                            None => return Err(self.mk_error(LexErrorKind::UnknownMacro, off + j))
+                        }
+                        for _ in 0..name.len() + 1 {
+                            chars.next();
+                        }
+                        continue;
+                    }
+                }
+                _ => ()
+            }
+            out.push(c);
+        }
+        Ok(out)
+    }
+
+    /// Parse the (space separated) names of a `%x` or `%s` declaration up to the end of the line.
+    fn parse_start_states(&mut self, mut i: usize, exclusive: bool) -> LexBuildResult<usize> {
+        let line_end = self.src[i..]
+            .find(|c| c == '\n')
+            .map(|j| i + j)
+            .unwrap_or_else(|| self.src.len());
+        let num_start_states = self.start_states.len();
+        loop {
+            i += self.src[i..line_end].len() - self.src[i..line_end].trim_left().len();
+            if i == line_end {
+                break;
+            }
+            let name_end = self.src[i..line_end]
+                .find(char::is_whitespace)
+                .map(|j| i + j)
+                .unwrap_or(line_end);
+            let name = &self.src[i..name_end];
+            if !is_identifier(name) {
+                return Err(self.mk_error(LexErrorKind::InvalidStartStateName, i));
+            }
+            if self.start_states.iter().any(|ss| ss.name == name) {
+                return Err(self.mk_error(LexErrorKind::DuplicateStartState, i));
+            }
+            self.start_states.push(StartState::new(name, exclusive));
+            i = name_end;
+        }
+        if self.start_states.len() == num_start_states {
+            return Err(self.mk_error(LexErrorKind::InvalidStartStateName, i));
+        }
+        Ok(i)
+    }
+
+    /// If `s` starts with `<NAME, ...>` or `<*>`, return the byte offset just after the `>` and
+    /// the indexes of the start states referenced. If `s` doesn't start with a start state list,
+    /// return `Ok(None)` (since it may be a regular expression that starts with `<`). If a start
+    /// state is referenced which has not been declared, an error is returned.
+    fn parse_start_state_list(
+        &self,
+        s: &str,
+        i: usize
+    ) -> LexBuildResult<Option<(usize, Vec<usize>)>> {
+        if !s.starts_with('<') {
+            return Ok(None);
+        }
+        let gt = match s.find('>') {
+            Some(j) => j,
+            None => return Ok(None)
+        };
+        if &s[1..gt] == "*" {
+            return Ok(Some((gt + 1, (0..self.start_states.len()).collect())));
+        }
+        if !s[1..gt].split(',').all(|n| is_identifier(n.trim())) {
+            return Ok(None);
+        }
+        let mut stidxs = Vec::new();
+        let mut j = 1;
+        for n in s[1..gt].split(',') {
+            match self.start_states.iter().position(|ss| ss.name == n.trim()) {
+                Some(stidx) => stidxs.push(stidx),
+                None => return Err(self.mk_error(LexErrorKind::UnknownStartState, i + j))
+            }
+            j += n.len() + 1;
+        }
+        Ok(Some((gt + 1, stidxs)))
+    }
+
+    /// Parse a start state operation (`<NAME>`, `<+NAME>`, or `<->`) at the beginning of `s`,
+    /// returning the byte offset just after the `>` and the operation.
+    fn parse_start_state_op(
+        &self,
+        s: &str,
+        i: usize
+    ) -> LexBuildResult<(usize, StartStateOperation)> {
+        let gt = match s.find('>') {
+            Some(j) => j,
+            None => return Err(self.mk_error(LexErrorKind::InvalidName, i))
+        };
+        let op = &s[1..gt];
+        if op == "-" {
+            return Ok((gt + 1, StartStateOperation::Pop));
         }
-        if i < self.src.len() {
-            Err(self.mk_error(LexErrorKind::UnknownDeclaration, i))
+        let (push, n) = if op.starts_with('+') {
+            (true, &op[1..])
         } else {
-            Err(self.mk_error(LexErrorKind::PrematureEnd, i - 1))
+            (false, op)
+        };
+        match self.start_states.iter().position(|ss| ss.name == n) {
+            Some(stidx) if push => Ok((gt + 1, StartStateOperation::Push(stidx))),
+            Some(stidx) => Ok((gt + 1, StartStateOperation::ReplaceStack(stidx))),
+            None => Err(self.mk_error(LexErrorKind::UnknownStartState, i + 1))
         }
     }
 
@@ -124,7 +447,13 @@ impl<StorageT: TryFrom<usize>> LexParser<StorageT> {
             if self.lookahead_is("%%", i).is_some() {
                 break;
             }
-            i = self.parse_rule(i)?;
+            i = match self.parse_rule(i) {
+                Ok(j) => j,
+                Err(e) => {
+                    self.push_error(e);
+                    self.skip_line(i)
+                }
+            };
         }
         Ok(i)
     }
@@ -133,42 +462,73 @@ impl<StorageT: TryFrom<usize>> LexParser<StorageT> {
         let line_len = self.src[i..]
             .find(|c| c == '\n')
             .unwrap_or(self.src.len() - i);
-        let line = self.src[i..i + line_len].trim_right();
+        let line_end = i + line_len;
+        let mut line = self.src[i..i + line_len].trim_right();
+        let (start_states, i) = match self.parse_start_state_list(line, i)? {
+            Some((j, stidxs)) => {
+                line = &line[j..];
+                (stidxs, i + j)
+            }
+            None => (Vec::new(), i)
+        };
+        // A trailing `@name` names the rule's callback.
+        let mut callback = None;
+        if let Some(j) = line.rfind(' ') {
+            if line[j + 1..].starts_with('@') {
+                let n = &line[j + 2..];
+                if !is_identifier(n) {
+                    return Err(self.mk_error(LexErrorKind::InvalidCallbackName, i + j + 1));
+                }
+                callback = Some(n.to_string());
+                line = line[..j].trim_right();
+            }
+        }
         let rspace = match line.rfind(' ') {
-            Some(j) => j,
-            None => return Err(self.mk_error(LexErrorKind::MissingSpace, i))
+            Some(j) if !line[..j].trim().is_empty() => j,
+            _ => return Err(self.mk_error(LexErrorKind::MissingSpace, i))
         };
 
         let name;
-        let orig_name = &line[rspace + 1..];
+        let mut orig_name = &line[rspace + 1..];
+        let mut target_state = None;
+        if orig_name.starts_with('<') {
+            let (j, op) = self.parse_start_state_op(orig_name, i + rspace + 1)?;
+            orig_name = &orig_name[j..];
+            target_state = Some(op);
+        }
+        let name_off = i + line.len() - orig_name.len();
         if orig_name == ";" {
             name = None;
         } else {
-            debug_assert!(!orig_name.is_empty());
-            if !((orig_name.starts_with('\'') && orig_name.ends_with('\''))
-                || (orig_name.starts_with('\"') && orig_name.ends_with('"')))
-            {
-                return Err(self.mk_error(LexErrorKind::InvalidName, i + rspace + 1));
-            }
-            name = Some(orig_name[1..orig_name.len() - 1].to_string());
+            name = match unquote_name(orig_name) {
+                Some(n) => Some(n.to_string()),
+                None => return Err(self.mk_error(LexErrorKind::InvalidName, name_off))
+            };
             if self.rules.iter().any(|r| {
                 r.name
                     .as_ref()
                     .map_or(false, |n| n == name.as_ref().unwrap())
+            }) || self.layout_names.as_ref().map_or(false, |names| {
+                names.iter().any(|n| n.1 == *name.as_ref().unwrap())
             }) {
-                return Err(self.mk_error(LexErrorKind::DuplicateName, i + rspace + 1));
+                return Err(self.mk_error(LexErrorKind::DuplicateName, name_off));
             }
         }
 
-        let re_str = line[..rspace].trim_right().to_string();
-        let rules_len = self.rules.len();
-        let tok_id = StorageT::try_from(rules_len)
-                           .unwrap_or_else(|_| panic!("StorageT::try_from failed on {} (if StorageT is an unsigned integer type, this probably means that {} exceeds the type's maximum value)", rules_len, rules_len));
+        let re_str = self.expand_macros(line[..rspace].trim_right(), i, &mut Vec::new())?;
+        let tok_id = new_tok_id(self.rules.len());
 
-        let rule = Rule::new(Some(tok_id), name, re_str)
-            .map_err(|_| self.mk_error(LexErrorKind::RegexError, i))?;
+        let rule = Rule::new(
+            Some(tok_id),
+            name,
+            re_str,
+            start_states,
+            target_state,
+            callback
+        )
+        .map_err(|_| self.mk_error(LexErrorKind::RegexError, i))?;
         self.rules.push(rule);
-        Ok(i + line_len)
+        Ok(line_end)
     }
 
     fn parse_ws(&mut self, i: usize) -> LexBuildResult<usize> {
@@ -184,6 +544,22 @@ impl<StorageT: TryFrom<usize>> LexParser<StorageT> {
         Ok(j)
     }
 
+    /// Record the error `e`, unless an error has already been recorded at the same position (as
+    /// happens when, for example, a rule uses a macro which refers to an unknown macro).
+    fn push_error(&mut self, e: LexBuildError) {
+        if !self.errs.iter().any(|x| x.line_col() == e.line_col()) {
+            self.errs.push(e);
+        }
+    }
+
+    /// Return the offset of the end of the line containing `i`.
+    fn skip_line(&self, i: usize) -> usize {
+        self.src[i..]
+            .find(|c| c == '\n')
+            .map(|j| i + j)
+            .unwrap_or_else(|| self.src.len())
+    }
+
     fn lookahead_is(&self, s: &'static str, i: usize) -> Option<usize> {
         if self.src[i..].starts_with(s) {
             Some(i + s.len())
@@ -193,18 +569,79 @@ impl<StorageT: TryFrom<usize>> LexParser<StorageT> {
     }
 }
 
+/// Convert `n` into a `StorageT` token ID, panicking if it doesn't fit.
+fn new_tok_id<StorageT: TryFrom<usize>>(n: usize) -> StorageT {
+    StorageT::try_from(n)
+        .unwrap_or_else(|_| panic!("StorageT::try_from failed on {} (if StorageT is an unsigned integer type, this probably means that {} exceeds the type's maximum value)", n, n))
+}
+
+/// If `s` is a name quoted with `'` or `"`, return the name without its quotes.
+fn unquote_name(s: &str) -> Option<&str> {
+    if s.len() < 2
+        || !((s.starts_with('\'') && s.ends_with('\'')) || (s.starts_with('"') && s.ends_with('"')))
+    {
+        return None;
+    }
+    Some(&s[1..s.len() - 1])
+}
+
+/// Is `n` a valid identifier (as used for start state, macro, and callback names)?
+fn is_identifier(n: &str) -> bool {
+    let mut cs = n.chars();
+    match cs.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false
+    }
+    cs.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 pub fn parse_lex<StorageT: Copy + Eq + Hash + PrimInt + TryFrom<usize> + Unsigned>(
     s: &str
 ) -> LexBuildResult<LexerDef<StorageT>> {
-    LexParser::new(s.to_string()).map(|p| LexerDef::new(p.rules))
+    parse_lex_all_errors(s).map_err(|mut errs| errs.remove(0))
+}
+
+/// As `parse_lex`, but returns every error found (in the order they occur in `s`) rather than
+/// just the first.
+pub fn parse_lex_all_errors<StorageT: Copy + Eq + Hash + PrimInt + TryFrom<usize> + Unsigned>(
+    s: &str
+) -> Result<LexerDef<StorageT>, Vec<LexBuildError>> {
+    LexParser::new(s.to_string()).map(|p| {
+        let mut lexerdef = LexerDef::new_with_start_states(p.start_states, p.rules);
+        lexerdef.set_layout(p.layout);
+        lexerdef.programs = p.programs;
+        lexerdef
+    })
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use lexer::{Layout, LayoutToken, StartState, StartStateOperation};
     use LexBuildError;
     use LexErrorKind;
 
+    #[test]
+    fn test_multiple_errors() {
+        let src = "%x X X
+%%
+[0-9]+ 'INT'
+<Y>[a-z]+ 'ID'
+[ ] 'INT'
+";
+        match parse_lex_all_errors::<u8>(&src) {
+            Ok(_) => panic!("Invalid lexer parsed"),
+            Err(errs) => assert_eq!(
+                errs.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+                vec![
+                    "Start state already exists at line 1 column 6",
+                    "Unknown start state at line 4 column 2",
+                    "Rule name already exists at line 5 column 5"
+                ]
+            )
+        }
+    }
+
     #[test]
     fn test_nooptions() {
         let src = "
@@ -338,6 +775,251 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_start_states() {
+        let src = "%x COMMENT STRING
+%s EXTRA
+%%
+/\\* <+COMMENT>;
+<COMMENT>\\*/ <->;
+<COMMENT,EXTRA>. ;
+<*>\" <STRING>'QUOTE'
+[a-z]+ 'ID'
+"
+        .to_string();
+        let ast = parse_lex::<u8>(&src).unwrap();
+        assert_eq!(
+            ast.iter_start_states().cloned().collect::<Vec<_>>(),
+            vec![
+                StartState::new("INITIAL", false),
+                StartState::new("COMMENT", true),
+                StartState::new("STRING", true),
+                StartState::new("EXTRA", false),
+            ]
+        );
+        let comment = ast.get_start_state_by_name("COMMENT").unwrap();
+        let string = ast.get_start_state_by_name("STRING").unwrap();
+        let extra = ast.get_start_state_by_name("EXTRA").unwrap();
+        let r0 = ast.get_rule(0).unwrap();
+        assert_eq!(r0.re_str, "/\\*");
+        assert!(r0.start_states.is_empty());
+        assert_eq!(r0.target_state, Some(StartStateOperation::Push(comment)));
+        let r1 = ast.get_rule(1).unwrap();
+        assert_eq!(r1.re_str, "\\*/");
+        assert_eq!(r1.start_states, vec![comment]);
+        assert_eq!(r1.target_state, Some(StartStateOperation::Pop));
+        assert_eq!(ast.get_rule(2).unwrap().start_states, vec![comment, extra]);
+        let quote = ast.get_rule_by_name("QUOTE").unwrap();
+        assert_eq!(quote.start_states, vec![0, comment, string, extra]);
+        assert_eq!(
+            quote.target_state,
+            Some(StartStateOperation::ReplaceStack(string))
+        );
+        let id = ast.get_rule_by_name("ID").unwrap();
+        assert!(id.start_states.is_empty());
+        assert!(id.target_state.is_none());
+    }
+
+    #[test]
+    fn test_regex_starting_with_lt() {
+        let src = "%%
+<= 'LE'
+<[a-z]+> 'TAG'
+"
+        .to_string();
+        let ast = parse_lex::<u8>(&src).unwrap();
+        assert_eq!(ast.get_rule_by_name("LE").unwrap().re_str, "<=");
+        assert_eq!(ast.get_rule_by_name("TAG").unwrap().re_str, "<[a-z]+>");
+    }
+
+    #[test]
+    fn test_start_state_errors() {
+        fn check(src: &str, kind: LexErrorKind, line: usize, col: usize) {
+            match parse_lex::<u8>(src) {
+                Ok(_) => panic!("Invalid start states parsed"),
+                Err(e) => {
+                    assert_eq!(format!("{:?}", e.kind), format!("{:?}", kind), "{}", e);
+                    assert_eq!((e.line, e.col), (line, col));
+                }
+            }
+        }
+        check("%x\n%%", LexErrorKind::UnknownDeclaration, 1, 1);
+        check("%xA\n%%", LexErrorKind::UnknownDeclaration, 1, 1);
+        check("%x A 1B\n%%", LexErrorKind::InvalidStartStateName, 1, 6);
+        check("%x A\n%s A\n%%", LexErrorKind::DuplicateStartState, 2, 4);
+        check("%x INITIAL\n%%", LexErrorKind::DuplicateStartState, 1, 4);
+        check("%x A\n%%\n<B>a 'a'", LexErrorKind::UnknownStartState, 3, 2);
+        check(
+            "%x A\n%%\n<A,B>a 'a'",
+            LexErrorKind::UnknownStartState,
+            3,
+            4
+        );
+        check("%x A\n%%\na <+B>'a'", LexErrorKind::UnknownStartState, 3, 4);
+        check("%x A\n%%\na <A'a'", LexErrorKind::InvalidName, 3, 3);
+        check("%x A\n%%\n<A> 'a'", LexErrorKind::MissingSpace, 3, 4);
+    }
+
+    #[test]
+    fn test_macros() {
+        let src = r"DIGIT [0-9]
+ID_START [a-zA-Z_]
+ID {ID_START}({ID_START}|{DIGIT})*
+%%
+{DIGIT}+ 'int'
+{ID} 'id'
+\{DIGIT\} 'braces'
+[{DIGIT}] 'class'
+a{2,3} 'rep'
+\p{Greek}\x{41} 'esc'
+"
+        .to_string();
+        let ast = parse_lex::<u8>(&src).unwrap();
+        assert_eq!(ast.get_rule_by_name("int").unwrap().re_str, "(?:[0-9])+");
+        assert_eq!(
+            ast.get_rule_by_name("id").unwrap().re_str,
+            "(?:(?:[a-zA-Z_])((?:[a-zA-Z_])|(?:[0-9]))*)"
+        );
+        assert_eq!(
+            ast.get_rule_by_name("braces").unwrap().re_str,
+            "\\{DIGIT\\}"
+        );
+        assert_eq!(ast.get_rule_by_name("class").unwrap().re_str, "[{DIGIT}]");
+        assert_eq!(ast.get_rule_by_name("rep").unwrap().re_str, "a{2,3}");
+        assert_eq!(
+            ast.get_rule_by_name("esc").unwrap().re_str,
+            "\\p{Greek}\\x{41}"
+        );
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        fn check(src: &str, kind: LexErrorKind, line: usize, col: usize) {
+            match parse_lex::<u8>(src) {
+                Ok(_) => panic!("Invalid macros parsed"),
+                Err(e) => {
+                    assert_eq!(format!("{:?}", e.kind), format!("{:?}", kind), "{}", e);
+                    assert_eq!((e.line, e.col), (line, col));
+                }
+            }
+        }
+        check("D [0-9]\n%%\n{E}+ 'int'", LexErrorKind::UnknownMacro, 3, 1);
+        check("D [0-9]{E}\n%%", LexErrorKind::UnknownMacro, 1, 8);
+        check("A a{B}\nB b{A}\n%%", LexErrorKind::RecursiveMacro, 2, 4);
+        check("A a{A}\n%%", LexErrorKind::RecursiveMacro, 1, 4);
+        check("D [0-9]\nD [a-z]\n%%", LexErrorKind::DuplicateMacro, 2, 1);
+        check("D\n%%", LexErrorKind::MissingSpace, 1, 1);
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let src = "%%
+/\\* ; @comment
+if <+INITIAL>'IF' @ctx_if
+[a-z@]+ 'ID'
+@ '@'
+"
+        .to_string();
+        let ast = parse_lex::<u8>(&src).unwrap();
+        let rules = ast.iter_rules().collect::<Vec<_>>();
+        assert_eq!(rules[0].re_str, "/\\*");
+        assert_eq!(rules[0].name, None);
+        assert_eq!(rules[0].callback, Some("comment".to_string()));
+        assert_eq!(rules[1].re_str, "if");
+        assert_eq!(rules[1].name, Some("IF".to_string()));
+        assert_eq!(rules[1].target_state, Some(StartStateOperation::Push(0)));
+        assert_eq!(rules[1].callback, Some("ctx_if".to_string()));
+        assert_eq!(rules[2].re_str, "[a-z@]+");
+        assert_eq!(rules[2].callback, None);
+        assert_eq!(rules[3].re_str, "@");
+        assert_eq!(rules[3].callback, None);
+
+        match parse_lex::<u8>("%%\n[0-9]+ 'INT' @1x") {
+            Ok(_) => panic!("Invalid callback name parsed"),
+            Err(LexBuildError {
+                kind: LexErrorKind::InvalidCallbackName,
+                line: 2,
+                col: 14
+            }) => (),
+            Err(e) => panic!("Incorrect error returned {}", e)
+        }
+        match parse_lex::<u8>("%%\n[0-9]+ @int") {
+            Ok(_) => panic!("Rule without a name parsed"),
+            Err(LexBuildError {
+                kind: LexErrorKind::MissingSpace,
+                ..
+            }) => (),
+            Err(e) => panic!("Incorrect error returned {}", e)
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let src = "%layout 'INDENT' \"DEDENT\" 'NEWLINE'
+%%
+[a-z]+ 'ID'
+[ \\n] ;"
+            .to_string();
+        let ast = parse_lex::<u8>(&src).unwrap();
+        assert_eq!(
+            ast.layout(),
+            Some(&Layout {
+                indent: LayoutToken::new("INDENT", Some(2)),
+                dedent: LayoutToken::new("DEDENT", Some(3)),
+                newline: Some(LayoutToken::new("NEWLINE", Some(4)))
+            })
+        );
+        let ast = parse_lex::<u8>("%layout 'I' 'D'\n%%").unwrap();
+        assert_eq!(ast.layout().unwrap().newline, None);
+        assert_eq!(parse_lex::<u8>("%%").unwrap().layout(), None);
+
+        fn check(src: &str, kind: LexErrorKind, line: usize, col: usize) {
+            match parse_lex::<u8>(src) {
+                Ok(_) => panic!("Invalid layout parsed"),
+                Err(e) => {
+                    assert_eq!(format!("{:?}", e.kind), format!("{:?}", kind), "{}", e);
+                    assert_eq!((e.line, e.col), (line, col));
+                }
+            }
+        }
+        check("%layout 'I'\n%%", LexErrorKind::InvalidLayout, 1, 1);
+        check(
+            "%layout 'I' 'D' 'N' 'X'\n%%",
+            LexErrorKind::InvalidLayout,
+            1,
+            1
+        );
+        check("%layout\n%%", LexErrorKind::UnknownDeclaration, 1, 1);
+        check("%layout 'I' D\n%%", LexErrorKind::InvalidName, 1, 13);
+        check("%layout 'I' 'I'\n%%", LexErrorKind::DuplicateName, 1, 13);
+        check(
+            "%layout 'I' 'D'\n%layout 'A' 'B'\n%%",
+            LexErrorKind::DuplicateLayout,
+            2,
+            1
+        );
+        check(
+            "%layout 'I' 'D'\n%%\nx 'D'",
+            LexErrorKind::DuplicateName,
+            3,
+            3
+        );
+    }
+
+    #[test]
+    fn test_programs() {
+        let src = "%%
+[0-9]+ 'int'
+%%
+fn foo() {}
+"
+        .to_string();
+        let ast = parse_lex::<u8>(&src).unwrap();
+        assert_eq!(ast.programs(), &Some("fn foo() {}\n".to_string()));
+        assert_eq!(parse_lex::<u8>("%%\n%%\n  \n").unwrap().programs(), &None);
+        assert_eq!(parse_lex::<u8>("%%").unwrap().programs(), &None);
+    }
+
     #[test]
     #[should_panic]
     fn exceed_tok_id_capacity() {
@@ -349,4 +1031,43 @@ mod test {
         }
         parse_lex::<u8>(&src).ok();
     }
+
+    #[test]
+    fn test_anchors_and_trailing_context() {
+        let src = "%x S
+%%
+^#[a-z]+ 'DIRECTIVE'
+[0-9]+/\\.\\. 'INT'
+[a-z]+/[ ]*\\( 'FUNC'
+(a/b) 'A'
+[/]/x 'SLASH'
+//[^\\n]* ;
+/ 'DIV'
+<<EOF>> 'EOF'
+<S><<EOF>> ;
+"
+        .to_string();
+        let ast = parse_lex::<u8>(&src).unwrap();
+        let rules = ast.iter_rules().collect::<Vec<_>>();
+        assert!(rules[0].bol);
+        assert_eq!(rules[0].head, "#[a-z]+");
+        assert_eq!(rules[0].trailing, None);
+        assert!(!rules[1].bol);
+        assert_eq!(rules[1].head, "[0-9]+");
+        assert_eq!(rules[1].trailing, Some("\\.\\.".to_string()));
+        assert_eq!(rules[2].head, "[a-z]+");
+        assert_eq!(rules[2].trailing, Some("[ ]*\\(".to_string()));
+        // A `/` inside a group, next to another `/`, or without a regular expression on both sides
+        // is a literal.
+        assert_eq!(rules[3].trailing, None);
+        assert_eq!(rules[4].head, "[/]");
+        assert_eq!(rules[4].trailing, Some("x".to_string()));
+        assert_eq!(rules[5].trailing, None);
+        assert_eq!(rules[6].trailing, None);
+        assert!(!rules[6].eof);
+        assert!(rules[7].eof);
+        assert_eq!(rules[7].start_states, vec![]);
+        assert!(rules[8].eof);
+        assert_eq!(rules[8].start_states, vec![1]);
+    }
 }
@@ -0,0 +1,614 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A lexer which reads its input incrementally from an `io::Read`, rather than requiring the whole
+//! input to be read before lexing starts.
+
+use std::{
+    hash::Hash,
+    io::{self, Read},
+    rc::Rc,
+    str
+};
+
+use num_traits::{PrimInt, Unsigned};
+
+use lexer::{LexerDef, RuleResult};
+use lrpar::{LexError, Lexeme, Lexer};
+
+/// The default for `StreamLexer::lookahead`.
+const DEFAULT_LOOKAHEAD: usize = 64 * 1024;
+
+/// A lexer which reads its input from an `io::Read`, created with
+/// [`LexerDef::stream_lexer`](struct.LexerDef.html#method.stream_lexer). It holds only a window
+/// of the input in memory, but its lexemes' offsets are relative to the start of the whole input,
+/// exactly as they would be if the input had been lexed with `LexerDef::lexer`.
+///
+/// `Lexer::lexeme_str` can only return the text of lexemes in the window of input currently in
+/// memory (which always includes the lexeme most recently returned), returning `""` for other
+/// lexemes. The text of a lexeme can instead be obtained as an owned `String` from
+/// `next_with_text`, or from `Lexer::owned_lexeme_str`: the parser uses the latter to hand each
+/// lexeme's text to actions (or to store it in the parse tree).
+///
+/// Note that this does not make parsing a stream run in bounded memory. The lexer records the
+/// start of every line it has seen (so that `line_and_col` works for any lexeme); and
+/// `RTParserBuilder` lexes the whole input before it starts parsing, keeping the text of every
+/// lexeme until the parser has consumed it. Lexing with `next_with_text` alone needs memory
+/// proportional to the number of lines in the input.
+///
+/// When matching, the lexer makes sure that at least `lookahead` bytes of input after the end of
+/// the longest match are in memory (reading more input and retrying the match if necessary), so
+/// lexemes longer than `lookahead` are lexed correctly. However, a rule which can only match
+/// after seeing more than `lookahead` bytes of input beyond the longest match of the other rules
+/// (e.g. a comment rule faced with a comment longer than `lookahead` bytes, if a shorter rule also
+/// matches) may be missed. Callbacks are passed the window of input in memory, rather than the
+/// whole input. Layout mode is not supported.
+pub struct StreamLexer<'a, R, StorageT: 'a> {
+    lexerdef: &'a LexerDef<StorageT>,
+    reader: R,
+    lookahead: usize,
+    /// The window of input currently in memory.
+    buf: String,
+    /// The byte offset in the whole input of the start of `buf`.
+    buf_off: usize,
+    /// Is the start of `buf` the beginning of a line?
+    buf_bol: bool,
+    /// Bytes read from `reader` which are not yet a complete UTF-8 character.
+    partial: Vec<u8>,
+    /// True once `reader` has been exhausted (or has failed).
+    reader_done: bool,
+    io_error: Option<io::Error>,
+    /// The lexer's position in `buf`.
+    i: usize,
+    /// The stack of start states. This always contains at least one element, the top of which is
+    /// the current start state.
+    start_states: Vec<usize>,
+    error_tolerant: bool,
+    /// True once the end of the input (or a `LexError`) has been reached.
+    done: bool,
+    /// The byte offset in the whole input of the start of every line seen so far.
+    line_starts: Vec<usize>
+}
+
+impl<'a, R: Read, StorageT: Copy + Eq + Hash + PrimInt + Unsigned> StreamLexer<'a, R, StorageT> {
+    pub(crate) fn new(
+        lexerdef: &'a LexerDef<StorageT>,
+        reader: R,
+        error_tolerant: bool
+    ) -> StreamLexer<'a, R, StorageT> {
+        assert!(
+            lexerdef.layout().is_none(),
+            "Stream lexers do not support layout mode"
+        );
+        StreamLexer {
+            lexerdef,
+            reader,
+            lookahead: DEFAULT_LOOKAHEAD,
+            buf: String::new(),
+            buf_off: 0,
+            buf_bol: true,
+            partial: Vec::new(),
+            reader_done: false,
+            io_error: None,
+            i: 0,
+            start_states: vec![0],
+            error_tolerant,
+            done: false,
+            line_starts: vec![0]
+        }
+    }
+
+    /// Set the minimum number of bytes of input after the current position which the lexer keeps
+    /// in memory (and the size of its reads). Panics if `lookahead` is 0.
+    pub fn lookahead(mut self, lookahead: usize) -> Self {
+        assert!(lookahead > 0);
+        self.lookahead = lookahead;
+        self
+    }
+
+    /// Return the next lexeme and an owned copy of its text.
+    pub fn next_with_text(&mut self) -> Option<Result<(Lexeme<StorageT>, String), LexError>> {
+        self.next_lexeme(None)
+            .map(|r| r.map(|l| (l, self.window_str(&l).to_owned())))
+    }
+
+    /// If lexing stopped because reading from the input failed (or the input was not valid
+    /// UTF-8), return the error. The lexer reports such failures as a `LexError` at the point
+    /// where reading failed.
+    pub fn io_error(&mut self) -> Option<io::Error> {
+        self.io_error.take()
+    }
+
+    /// The text of the lexeme `l`, which must be in the window of input currently in memory.
+    fn window_str(&self, l: &Lexeme<StorageT>) -> &str {
+        &self.buf[l.start() - self.buf_off..l.end() - self.buf_off]
+    }
+
+    /// Return the next lexeme, preferring lexemes whose token IDs are in `acceptable` (if it is
+    /// `Some`).
+    fn next_lexeme(
+        &mut self,
+        acceptable: Option<&[StorageT]>
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        if self.done {
+            return None;
+        }
+        loop {
+            while self.buf.len() - self.i < self.lookahead && self.read_more() {}
+            if self.i == self.buf.len() {
+                if self.io_error.is_some() {
+                    // Everything read before the failure has been lexed.
+                    self.done = true;
+                    return Some(Err(LexError {
+                        idx: self.buf_off + self.buf.len()
+                    }));
+                }
+                break;
+            }
+            let stidx = *self.start_states.last().unwrap();
+            let (longest, ridx) =
+                self.lexerdef
+                    .longest_match(&self.buf, self.buf_bol, self.i, stidx, acceptable);
+            if self.buf.len() - (self.i + longest) < self.lookahead && self.read_more() {
+                // A longer match might depend on input we hadn't yet read.
+                continue;
+            }
+            if longest > 0 {
+                match self.lexerdef.apply_rule(
+                    &self.buf,
+                    self.i,
+                    longest,
+                    ridx,
+                    &mut self.start_states
+                ) {
+                    RuleResult::Lexeme(Some(tok_id), len) => {
+                        let l = Lexeme::new(tok_id, self.buf_off + self.i, len);
+                        self.i += len;
+                        return Some(Ok(l));
+                    }
+                    RuleResult::Lexeme(None, len) | RuleResult::Error(len) => {
+                        return self.error(len)
+                    }
+                    RuleResult::Skip(len) => self.i += len
+                }
+            } else if self.error_tolerant {
+                // Skip characters until we reach one where a rule matches again.
+                let len = loop {
+                    if let Some(j) = self.lexerdef.resync(&self.buf, self.buf_bol, self.i, stidx) {
+                        break j - self.i;
+                    }
+                    if !self.read_more() {
+                        break self.buf.len() - self.i;
+                    }
+                };
+                return self.error(len);
+            } else {
+                return self.error(0);
+            }
+        }
+        self.done = true;
+        match self.lexerdef.apply_eof(*self.start_states.last().unwrap()) {
+            RuleResult::Lexeme(Some(tok_id), _) => {
+                Some(Ok(Lexeme::new(tok_id, self.buf_off + self.i, 0)))
+            }
+            RuleResult::Lexeme(None, _) | RuleResult::Error(_) => self.error(0),
+            RuleResult::Skip(_) => None
+        }
+    }
+
+    /// Report that the `len` bytes of input at the lexer's position can't be lexed.
+    fn error(&mut self, len: usize) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let start = self.buf_off + self.i;
+        if self.error_tolerant {
+            self.i += len;
+            Some(Ok(Lexeme::new_error(start, len)))
+        } else {
+            self.done = true;
+            Some(Err(LexError { idx: start }))
+        }
+    }
+
+    /// Discard the input before the lexer's position and read more input into `buf`, returning
+    /// `false` if no more input can be read.
+    fn read_more(&mut self) -> bool {
+        if self.reader_done {
+            return false;
+        }
+        if self.i > 0 {
+            self.buf_bol = self.buf[..self.i].ends_with('\n');
+            self.buf.drain(..self.i);
+            self.buf_off += self.i;
+            self.i = 0;
+        }
+        let mut chunk = vec![0; self.lookahead];
+        let n = loop {
+            match self.reader.read(&mut chunk) {
+                Ok(n) => break n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.reader_done = true;
+                    self.io_error = Some(e);
+                    return false;
+                }
+            }
+        };
+        if n == 0 {
+            self.reader_done = true;
+            if !self.partial.is_empty() {
+                self.io_error = Some(invalid_utf8());
+            }
+            return false;
+        }
+        self.partial.extend_from_slice(&chunk[..n]);
+        let valid = match str::from_utf8(&self.partial) {
+            Ok(s) => s.len(),
+            Err(e) => {
+                if e.error_len().is_some() {
+                    // The input before the invalid UTF-8 can still be lexed, but we stop reading.
+                    self.reader_done = true;
+                    self.io_error = Some(invalid_utf8());
+                }
+                e.valid_up_to()
+            }
+        };
+        let old_len = self.buf.len();
+        self.buf
+            .push_str(str::from_utf8(&self.partial[..valid]).unwrap());
+        if self.reader_done {
+            self.partial.clear();
+            if valid == 0 {
+                return false;
+            }
+        } else {
+            self.partial.drain(..valid);
+        }
+        let buf_off = self.buf_off;
+        self.line_starts.extend(
+            self.buf[old_len..]
+                .bytes()
+                .enumerate()
+                .filter(|&(_, b)| b == b'\n')
+                .map(|(j, _)| buf_off + old_len + j + 1)
+        );
+        true
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream did not contain valid UTF-8"
+    )
+}
+
+impl<'a, R: Read, StorageT: Copy + Eq + Hash + PrimInt + Unsigned> Lexer<StorageT>
+    for StreamLexer<'a, R, StorageT>
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        self.next_lexeme(None)
+    }
+
+    /// Only consider rules which produce lexemes whose token IDs are in `acceptable` (and rules
+    /// which skip input), unless no such rule matches, in which case all rules are considered.
+    fn next_acceptable(
+        &mut self,
+        acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        self.next_lexeme(Some(acceptable))
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        if l.start() > self.buf_off + self.buf.len() {
+            return Err(());
+        }
+        let line = match self.line_starts.binary_search(&l.start()) {
+            Ok(i) => i + 1,
+            Err(i) => i
+        };
+        Ok((line, l.start() - self.line_starts[line - 1] + 1))
+    }
+
+    fn input(&self) -> &str {
+        &self.buf
+    }
+
+    fn input_len(&self) -> usize {
+        self.buf_off + self.buf.len()
+    }
+
+    /// Return the text of the lexeme `l` if it is in the window of input currently in memory, or
+    /// `""` otherwise.
+    fn lexeme_str(&self, l: &Lexeme<StorageT>) -> &str {
+        if l.is_empty() || l.start() < self.buf_off || l.end() > self.buf_off + self.buf.len() {
+            ""
+        } else {
+            self.window_str(l)
+        }
+    }
+
+    fn owned_lexeme_str(&self, l: &Lexeme<StorageT>) -> Option<Rc<str>> {
+        Some(Rc::from(self.lexeme_str(l)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cfgrammar::yacc::{YaccGrammar, YaccKind};
+    use lrpar::{parser::AStackType, LexParseError, LexerExt, Node, RTParserBuilder, RecoveryKind};
+    use lrtable::{from_yacc, Minimiser};
+    use parser::parse_lex;
+    use std::{cmp::min, collections::HashMap};
+
+    /// A reader which returns at most `n` bytes per read.
+    struct ChunkReader<'a> {
+        s: &'a [u8],
+        n: usize
+    }
+
+    impl<'a> Read for ChunkReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = min(min(self.n, buf.len()), self.s.len());
+            buf[..n].copy_from_slice(&self.s[..n]);
+            self.s = &self.s[n..];
+            Ok(n)
+        }
+    }
+
+    fn lexerdef() -> LexerDef<u8> {
+        let src = "%x COMMENT
+%%
+^#[a-z]+ 'DIRECTIVE'
+[a-zα-ω]+/[ ]*\\( 'FUNC'
+[a-zα-ω]+ 'ID'
+[0-9]+ 'INT'
+\\( '('
+\\) ')'
+/\\* <+COMMENT>;
+<COMMENT>\\*/ <->;
+<COMMENT>[^*]+|\\* ;
+[ \\n]+ ;
+<<EOF>> 'EOF'";
+        let mut lexerdef = parse_lex(src).unwrap();
+        let mut map = HashMap::new();
+        for (i, n) in ["DIRECTIVE", "FUNC", "ID", "INT", "(", ")", "EOF"]
+            .iter()
+            .enumerate()
+        {
+            map.insert(*n, i as u8);
+        }
+        assert_eq!(lexerdef.set_rule_ids(&map), (None, None));
+        lexerdef
+    }
+
+    #[test]
+    fn test_stream() {
+        let lexerdef = lexerdef();
+        for s in &[
+            "",
+            "#if f (x)\n  #y /* a long\n comment */ αβγ 12345 g(\nh) #z",
+            "abcdefghijklmnopqrstuvwxyz (0) /* * ** */\n#abc",
+            "x y % z",
+            "/* unterminated"
+        ] {
+            let expected = lexerdef.error_tolerant_lexer(s).all_lexemes().unwrap();
+            // Reading a byte at a time splits multi-byte characters across reads.
+            for &(n, lookahead) in &[(1, 4), (3, 4), (7, 8), (100, 100)] {
+                let mut lexer = lexerdef
+                    .error_tolerant_stream_lexer(ChunkReader { s: s.as_bytes(), n })
+                    .lookahead(lookahead);
+                let mut lexemes = Vec::new();
+                while let Some(r) = lexer.next() {
+                    let l = r.unwrap();
+                    // The most recent lexeme is always in memory.
+                    assert_eq!(lexer.lexeme_str(&l), &s[l.start()..l.end()]);
+                    assert_eq!(
+                        lexer.owned_lexeme_str(&l).as_ref().map(|t| &**t),
+                        Some(&s[l.start()..l.end()])
+                    );
+                    lexemes.push(l);
+                }
+                assert_eq!(lexemes, expected);
+            }
+        }
+
+        let s = "ab\n cd /* x */ ef\n\ng";
+        let mut lexer = lexerdef
+            .stream_lexer(ChunkReader {
+                s: s.as_bytes(),
+                n: 2
+            })
+            .lookahead(2);
+        let mut lexemes = Vec::new();
+        while let Some(r) = lexer.next_with_text() {
+            let (l, t) = r.unwrap();
+            assert_eq!(t, &s[l.start()..l.end()]);
+            lexemes.push(l);
+        }
+        assert_eq!(
+            lexemes
+                .iter()
+                .map(|l| lexer.line_and_col(l).unwrap())
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (2, 2), (2, 13), (4, 1), (4, 2)]
+        );
+        // Input which has been lexed is discarded.
+        assert_eq!(lexer.lexeme_str(&lexemes[0]), "");
+
+        match lexerdef.stream_lexer("x y % z".as_bytes()).all_lexemes() {
+            Err(LexError { idx: 4 }) => (),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        // Only part of a stream lexer's input is in memory at once, so the offsets of the second
+        // lexer's lexemes must be based on the length of the whole stream.
+        let lexerdef = lexerdef();
+        let s = "ab cd\nef ";
+        let mut lexer = lexerdef
+            .stream_lexer(ChunkReader {
+                s: s.as_bytes(),
+                n: 2
+            })
+            .lookahead(1)
+            .chain(lexerdef.lexer("gh\n(i)"));
+        let mut lexemes = Vec::new();
+        while let Some(r) = lexer.next() {
+            let l = r.unwrap();
+            let t = match lexer.owned_lexeme_str(&l) {
+                Some(t) => t.to_string(),
+                None => lexer.lexeme_str(&l).to_owned()
+            };
+            lexemes.push((t, l));
+        }
+        assert_eq!(
+            lexemes
+                .iter()
+                .map(|&(ref t, l)| (t.as_str(), l.start(), lexer.line_and_col(&l).unwrap()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("ab", 0, (1, 1)),
+                ("cd", 3, (1, 4)),
+                ("ef", 6, (2, 1)),
+                ("", 9, (2, 4)),
+                ("gh", 9, (2, 4)),
+                ("(", 12, (3, 1)),
+                ("i", 13, (3, 2)),
+                (")", 14, (3, 3)),
+                ("", 15, (3, 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        // The parser is given the text of each lexeme as it lexes it, so neither actions nor parse
+        // trees depend on the lexer keeping lexemes' text in memory.
+        let grm = YaccGrammar::<u8>::new_with_storaget(
+            YaccKind::Original,
+            "%start E\n%%\nE: E '+' 'INT' | 'INT';"
+        )
+        .unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let mut lexerdef = parse_lex("%%\n[0-9]+ 'INT'\n\\+ '+'\n[ ]+ ;").unwrap();
+        let rule_ids = grm
+            .tokens_map()
+            .iter()
+            .map(|(&n, &tidx)| (n, tidx.0))
+            .collect::<HashMap<_, _>>();
+        lexerdef.set_rule_ids(&rule_ids);
+        let stream = |s: &'static str| {
+            lexerdef
+                .stream_lexer(ChunkReader {
+                    s: s.as_bytes(),
+                    n: 1
+                })
+                .lookahead(1)
+        };
+
+        let concat = |lexer: &Lexer<u8>, args: &[AStackType<String, u8>]| {
+            args.iter()
+                .map(|a| match *a {
+                    AStackType::ActionType(ref s) => s.clone(),
+                    AStackType::Lexeme(_, Some(ref t)) => t.to_string(),
+                    AStackType::Lexeme(l, None) => lexer.lexeme_str(&l).to_owned()
+                })
+                .collect::<String>()
+        };
+        let actions = grm
+            .iter_pidxs()
+            .map(|_| Some(&concat as &Fn(&Lexer<u8>, &[AStackType<String, u8>]) -> String))
+            .collect::<Vec<_>>();
+        let r = RTParserBuilder::new(&grm, &sgraph, &stable)
+            .parse2(&mut stream("1 + 22 + 333"), &actions);
+        assert_eq!(r.ok().unwrap(), "1+22+333");
+
+        // Repairs insert lexemes which the lexer didn't produce (and so has no text for).
+        let mut lexer = stream("1 + ");
+        let tree = match RTParserBuilder::new(&grm, &sgraph, &stable)
+            .recoverer(RecoveryKind::MF)
+            .parse(&mut lexer)
+        {
+            Err(LexParseError::ParseError {
+                tree: Some(tree), ..
+            }) => tree,
+            _ => panic!()
+        };
+        assert_eq!(tree.pp(&grm, ""), "E\n E\n  INT 1\n + +\n INT \n");
+        let mut todo = vec![&tree];
+        let mut terms = Vec::new();
+        while let Some(n) = todo.pop() {
+            match *n {
+                Node::Term { lexeme, ref text } => terms.push((
+                    lexer.lexeme_str(&lexeme),
+                    text.as_ref().map(|t| t.to_string())
+                )),
+                Node::Nonterm { ref nodes, .. } => todo.extend(nodes.iter().rev())
+            }
+        }
+        assert_eq!(
+            terms,
+            vec![
+                ("", Some("1".to_owned())),
+                ("", Some("+".to_owned())),
+                ("", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let lexerdef = lexerdef();
+        let mut lexer = lexerdef.stream_lexer(&b"ab \xff cd"[..]);
+        // The input before the invalid UTF-8 is lexed as normal.
+        assert_eq!(lexer.next().unwrap().unwrap().len(), 2);
+        match lexer.next() {
+            Some(Err(LexError { idx: 3 })) => (),
+            _ => panic!()
+        }
+        assert!(lexer.next().is_none());
+        assert_eq!(
+            lexer.io_error().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+        // A truncated multi-byte character is detected at the end of the input.
+        let mut lexer = lexerdef.stream_lexer(&b"ab \xce"[..]).lookahead(1);
+        assert_eq!(lexer.next().unwrap().unwrap().len(), 2);
+        match lexer.next() {
+            Some(Err(LexError { idx: 3 })) => (),
+            _ => panic!()
+        }
+        assert!(lexer.io_error().is_some());
+    }
+}
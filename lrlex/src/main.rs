@@ -92,12 +92,21 @@ fn main() {
         writeln!(&mut stderr(), "{}: {}", &lex_l_path, &s).ok();
         process::exit(1);
     });
+    for w in lexerdef.warnings() {
+        writeln!(
+            &mut stderr(),
+            "{}: Warning: {}",
+            &lex_l_path,
+            w.pp(&lexerdef)
+        )
+        .ok();
+    }
     let input = &read_file(&matches.free[1]);
     let lexemes = lexerdef.lexer(input).all_lexemes().unwrap();
     for l in &lexemes {
         println!(
             "{} {}",
-            lexerdef.get_rule_by_id(l.tok_id()).name.as_ref().unwrap(),
+            lexerdef.get_name_by_id(l.tok_id()).unwrap(),
             &input[l.start()..l.start() + l.len()]
         );
     }
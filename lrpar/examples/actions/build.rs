@@ -33,21 +33,19 @@
 extern crate lrlex;
 extern crate lrpar;
 
-use lrlex::LexerBuilder;
+use lrlex::LexerParserBuilder;
 use lrpar::{ActionKind, CTParserBuilder};
 
 fn main() -> Result<(), Box<std::error::Error>> {
-    // First we create the parser, which returns a HashMap of all the tokens used, then we pass
-    // that HashMap to the lexer.
+    // LexerParserBuilder creates the parser, passes the tokens it uses to the lexer, and checks
+    // that the lexer defines all of them. It then generates a single module `calc` which contains
+    // both the lexer and the parser.
     //
     // Note that we specify the integer type (u8) we'll use for token IDs (this type *must* be big
-    // enough to fit all IDs in) as well as the input file (which must end in ".y" for lrpar, and
-    // ".l" for lrlex).
-    let lex_rule_ids_map = CTParserBuilder::<u8>::new()
-        .action_kind(ActionKind::CustomAction)
-        .process_file_in_src("calc.y")?;
-    LexerBuilder::new()
-        .rule_ids_map(lex_rule_ids_map)
-        .process_file_in_src("calc.l")?;
+    // enough to fit all IDs in) as well as the input files (which must end in ".l" for the lexer,
+    // and ".y" for the grammar).
+    LexerParserBuilder::<u8>::new()
+        .parser_builder(CTParserBuilder::new().action_kind(ActionKind::CustomAction))
+        .process_files_in_src("calc.l", "calc.y")?;
     Ok(())
 }
@@ -40,9 +40,9 @@ fn main() {
                     // Parsing failed, but with the help of error recovery a parse tree was
                     // produced. However, we simply report the error to the user and don't attempt
                     // to do any sort of evaluation.
-                    Err(LexParseError::ParseError(_, errs)) => {
+                    Err(LexParseError::ParseError { errors, .. }) => {
                         // One or more errors were detected during parsing.
-                        for e in errs {
+                        for e in errors {
                             let (line, col) = lexer.line_and_col(e.lexeme()).unwrap();
                             assert_eq!(line, 1);
                             println!("Parsing error at column {}.", col);
@@ -93,7 +93,7 @@ impl<'a> Eval<'a> {
                 ref nodes
             } if ridx == calc_y::R_FACTOR => {
                 if nodes.len() == 1 {
-                    if let Node::Term { lexeme } = nodes[0] {
+                    if let Node::Term { lexeme, .. } = nodes[0] {
                         self.s[lexeme.start()..lexeme.end()].parse().unwrap()
                     } else {
                         unreachable!();
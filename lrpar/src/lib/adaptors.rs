@@ -0,0 +1,802 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Adaptors which change the stream of lexemes between a lexer and a parser.
+//!
+//! Each adaptor wraps a `Lexer` and is itself a `Lexer`, so adaptors can be stacked, and the
+//! result passed to a parser like any other lexer. [`LexerExt`](trait.LexerExt.html) provides
+//! methods for building adaptors, in the style of `Iterator`'s adaptors:
+//!
+//! ```text
+//! let mut lexer = lexerdef
+//!     .lexer(input)
+//!     .filter_lexemes(|l| l.tok_id() != comment_id)
+//!     .map_lexemes(|l, s| if s == "async" { Lexeme::new(async_id, l.start(), l.len()) } else { l });
+//! ```
+//!
+//! Unless stated otherwise, an adaptor answers `line_and_col`, `input`, `lexeme_str`, and
+//! `owned_lexeme_str` queries by asking the lexer it wraps.
+
+use std::{collections::HashSet, hash::Hash, rc::Rc};
+
+use num_traits::{PrimInt, Unsigned};
+
+use lex::{LexError, Lexeme, Lexer};
+
+/// Methods for building lexer adaptors. This trait is implemented for every `Lexer`.
+pub trait LexerExt<StorageT: Hash + PrimInt + Unsigned>: Lexer<StorageT> + Sized {
+    /// Return a lexer which only produces those lexemes for which `f` returns `true`. Errors are
+    /// always passed through.
+    fn filter_lexemes<F>(self, f: F) -> Filter<Self, F>
+    where
+        F: FnMut(&Lexeme<StorageT>) -> bool
+    {
+        Filter { lexer: self, f }
+    }
+
+    /// Return a lexer which replaces each lexeme `l` with `f(l, s)`, where `s` is the text of `l`.
+    /// This is typically used to change a lexeme's token ID (e.g. to turn an identifier into a
+    /// keyword); the lexeme returned by `f` must refer to a part of the input. Errors are always
+    /// passed through.
+    fn map_lexemes<F>(self, f: F) -> Map<Self, F>
+    where
+        F: FnMut(Lexeme<StorageT>, &str) -> Lexeme<StorageT>
+    {
+        Map { lexer: self, f }
+    }
+
+    /// Return a lexer which can insert a lexeme between any two adjacent lexemes. `f` is called
+    /// with the lexer, the previous lexeme (`None` at the start of the input), and the next lexeme
+    /// (`None` at the end of the input); if it returns `Some(l)` then `l` is produced before the
+    /// next lexeme. At most one lexeme is inserted in each gap. Inserted lexemes are typically
+    /// zero-length (e.g. `Lexeme::new(semi_id, prev.end(), 0)`); their text is always the empty
+    /// string.
+    fn insert_lexemes<F>(self, f: F) -> Insert<Self, StorageT, F>
+    where
+        F: FnMut(
+            &Self,
+            Option<&Lexeme<StorageT>>,
+            Option<&Lexeme<StorageT>>
+        ) -> Option<Lexeme<StorageT>>
+    {
+        Insert {
+            lexer: self,
+            f,
+            prev: None,
+            pending: None,
+            done: false
+        }
+    }
+
+    /// Return a lexer which produces all of this lexer's lexemes followed by all of `other`'s.
+    /// The input of the returned lexer is this lexer's input followed by `other`'s, so if the two
+    /// lexers lex adjacent parts of a larger string (e.g. a host language and an embedded
+    /// language), lexemes' offsets and line and column numbers are relative to that larger string.
+    fn chain<L>(self, other: L) -> Chain<Self, L>
+    where
+        L: Lexer<StorageT>
+    {
+        Chain::new(self, other)
+    }
+}
+
+impl<StorageT: Hash + PrimInt + Unsigned, L: Lexer<StorageT>> LexerExt<StorageT> for L {}
+
+impl<'a, StorageT: Hash + PrimInt + Unsigned, L: Lexer<StorageT> + ?Sized> Lexer<StorageT>
+    for &'a mut L
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        (**self).next()
+    }
+
+    fn next_acceptable(
+        &mut self,
+        acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        (**self).next_acceptable(acceptable)
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        (**self).line_and_col(l)
+    }
+
+    fn input(&self) -> &str {
+        (**self).input()
+    }
+
+    fn input_len(&self) -> usize {
+        (**self).input_len()
+    }
+
+    fn lexeme_str(&self, l: &Lexeme<StorageT>) -> &str {
+        (**self).lexeme_str(l)
+    }
+
+    fn owned_lexeme_str(&self, l: &Lexeme<StorageT>) -> Option<Rc<str>> {
+        (**self).owned_lexeme_str(l)
+    }
+}
+
+/// A lexer which drops some lexemes. See [`LexerExt::filter_lexemes`](trait.LexerExt.html#method.filter_lexemes).
+pub struct Filter<L, F> {
+    lexer: L,
+    f: F
+}
+
+impl<L, F> Filter<L, F> {
+    /// Return the lexer this adaptor wraps.
+    pub fn into_inner(self) -> L {
+        self.lexer
+    }
+
+    fn filter<StorageT>(
+        &mut self,
+        mut r: Option<Result<Lexeme<StorageT>, LexError>>,
+        acceptable: Option<&[StorageT]>
+    ) -> Option<Result<Lexeme<StorageT>, LexError>>
+    where
+        StorageT: Hash + PrimInt + Unsigned,
+        L: Lexer<StorageT>,
+        F: FnMut(&Lexeme<StorageT>) -> bool
+    {
+        loop {
+            match r {
+                Some(Ok(l)) if !(self.f)(&l) => (),
+                _ => return r
+            }
+            r = match acceptable {
+                Some(a) => self.lexer.next_acceptable(a),
+                None => self.lexer.next()
+            };
+        }
+    }
+}
+
+impl<StorageT, L, F> Lexer<StorageT> for Filter<L, F>
+where
+    StorageT: Hash + PrimInt + Unsigned,
+    L: Lexer<StorageT>,
+    F: FnMut(&Lexeme<StorageT>) -> bool
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let r = self.lexer.next();
+        self.filter(r, None)
+    }
+
+    fn next_acceptable(
+        &mut self,
+        acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let r = self.lexer.next_acceptable(acceptable);
+        self.filter(r, Some(acceptable))
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        self.lexer.line_and_col(l)
+    }
+
+    fn input(&self) -> &str {
+        self.lexer.input()
+    }
+
+    fn input_len(&self) -> usize {
+        self.lexer.input_len()
+    }
+
+    fn lexeme_str(&self, l: &Lexeme<StorageT>) -> &str {
+        self.lexer.lexeme_str(l)
+    }
+
+    fn owned_lexeme_str(&self, l: &Lexeme<StorageT>) -> Option<Rc<str>> {
+        self.lexer.owned_lexeme_str(l)
+    }
+}
+
+/// A lexer which rewrites lexemes. See [`LexerExt::map_lexemes`](trait.LexerExt.html#method.map_lexemes).
+pub struct Map<L, F> {
+    lexer: L,
+    f: F
+}
+
+impl<L, F> Map<L, F> {
+    /// Return the lexer this adaptor wraps.
+    pub fn into_inner(self) -> L {
+        self.lexer
+    }
+
+    fn map<StorageT>(
+        &mut self,
+        r: Option<Result<Lexeme<StorageT>, LexError>>
+    ) -> Option<Result<Lexeme<StorageT>, LexError>>
+    where
+        StorageT: Hash + PrimInt + Unsigned,
+        L: Lexer<StorageT>,
+        F: FnMut(Lexeme<StorageT>, &str) -> Lexeme<StorageT>
+    {
+        match r {
+            Some(Ok(l)) => Some(Ok((self.f)(l, self.lexer.lexeme_str(&l)))),
+            _ => r
+        }
+    }
+}
+
+impl<StorageT, L, F> Lexer<StorageT> for Map<L, F>
+where
+    StorageT: Hash + PrimInt + Unsigned,
+    L: Lexer<StorageT>,
+    F: FnMut(Lexeme<StorageT>, &str) -> Lexeme<StorageT>
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let r = self.lexer.next();
+        self.map(r)
+    }
+
+    fn next_acceptable(
+        &mut self,
+        acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let r = self.lexer.next_acceptable(acceptable);
+        self.map(r)
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        self.lexer.line_and_col(l)
+    }
+
+    fn input(&self) -> &str {
+        self.lexer.input()
+    }
+
+    fn input_len(&self) -> usize {
+        self.lexer.input_len()
+    }
+
+    fn lexeme_str(&self, l: &Lexeme<StorageT>) -> &str {
+        self.lexer.lexeme_str(l)
+    }
+
+    fn owned_lexeme_str(&self, l: &Lexeme<StorageT>) -> Option<Rc<str>> {
+        self.lexer.owned_lexeme_str(l)
+    }
+}
+
+/// A lexer which inserts lexemes between other lexemes. See
+/// [`LexerExt::insert_lexemes`](trait.LexerExt.html#method.insert_lexemes).
+pub struct Insert<L, StorageT, F> {
+    lexer: L,
+    f: F,
+    /// The last lexeme produced.
+    prev: Option<Lexeme<StorageT>>,
+    /// A lexeme from `lexer` which is held back while an inserted lexeme is produced.
+    pending: Option<Lexeme<StorageT>>,
+    /// Has `lexer` finished (or errored)?
+    done: bool
+}
+
+impl<L, StorageT, F> Insert<L, StorageT, F> {
+    /// Return the lexer this adaptor wraps.
+    pub fn into_inner(self) -> L {
+        self.lexer
+    }
+}
+
+impl<StorageT, L, F> Insert<L, StorageT, F>
+where
+    StorageT: Hash + PrimInt + Unsigned,
+    L: Lexer<StorageT>,
+    F: FnMut(&L, Option<&Lexeme<StorageT>>, Option<&Lexeme<StorageT>>) -> Option<Lexeme<StorageT>>
+{
+    fn insert(
+        &mut self,
+        r: Option<Result<Lexeme<StorageT>, LexError>>
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        match r {
+            Some(Ok(l)) => {
+                let next = match (self.f)(&self.lexer, self.prev.as_ref(), Some(&l)) {
+                    Some(ins) => {
+                        self.pending = Some(l);
+                        ins
+                    }
+                    None => l
+                };
+                self.prev = Some(next);
+                Some(Ok(next))
+            }
+            Some(Err(e)) => {
+                self.done = true;
+                Some(Err(e))
+            }
+            None => {
+                self.done = true;
+                let ins = (self.f)(&self.lexer, self.prev.as_ref(), None);
+                self.prev = ins;
+                ins.map(Ok)
+            }
+        }
+    }
+}
+
+impl<StorageT, L, F> Lexer<StorageT> for Insert<L, StorageT, F>
+where
+    StorageT: Hash + PrimInt + Unsigned,
+    L: Lexer<StorageT>,
+    F: FnMut(&L, Option<&Lexeme<StorageT>>, Option<&Lexeme<StorageT>>) -> Option<Lexeme<StorageT>>
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        if let Some(l) = self.pending.take() {
+            self.prev = Some(l);
+            return Some(Ok(l));
+        }
+        if self.done {
+            return None;
+        }
+        let r = self.lexer.next();
+        self.insert(r)
+    }
+
+    fn next_acceptable(
+        &mut self,
+        acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        if let Some(l) = self.pending.take() {
+            self.prev = Some(l);
+            return Some(Ok(l));
+        }
+        if self.done {
+            return None;
+        }
+        let r = self.lexer.next_acceptable(acceptable);
+        self.insert(r)
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        self.lexer.line_and_col(l)
+    }
+
+    fn input(&self) -> &str {
+        self.lexer.input()
+    }
+
+    fn input_len(&self) -> usize {
+        self.lexer.input_len()
+    }
+
+    fn lexeme_str(&self, l: &Lexeme<StorageT>) -> &str {
+        if l.is_empty() {
+            ""
+        } else {
+            self.lexer.lexeme_str(l)
+        }
+    }
+
+    fn owned_lexeme_str(&self, l: &Lexeme<StorageT>) -> Option<Rc<str>> {
+        if l.is_empty() {
+            None
+        } else {
+            self.lexer.owned_lexeme_str(l)
+        }
+    }
+}
+
+/// A lexer which produces one lexer's lexemes followed by another's. See
+/// [`LexerExt::chain`](trait.LexerExt.html#method.chain).
+///
+/// The offsets of the second lexer's lexemes are shifted by the length of the first lexer's input
+/// (as given by `Lexer::input_len` once the first lexer has finished), and their line and column
+/// numbers are adjusted accordingly (assuming the second lexer counts columns in bytes, as
+/// `lrlex`'s lexers do). The `Chain` records every lexeme the second lexer produces, so that
+/// `line_and_col` and `lexeme_str` pass each lexeme to the lexer it came from. `input` returns a
+/// copy of both lexers' inputs, taken when the `Chain` was created: if either lexer doesn't hold
+/// all of its input in memory at once, `input` contains only the part each held at that point.
+pub struct Chain<A, B> {
+    a: A,
+    b: B,
+    input: String,
+    /// The length of `a`'s input, once `a` has finished.
+    a_len: Option<usize>,
+    /// The line and column of the end of `a`'s input (`Err` until `a` has finished).
+    a_end: Result<(usize, usize), ()>,
+    /// The (shifted) start, length, and token ID of each lexeme `b` has produced.
+    b_lexemes: HashSet<(usize, usize, usize)>,
+    /// Has `a` produced an error?
+    failed: bool
+}
+
+impl<A, B> Chain<A, B> {
+    fn new<StorageT>(a: A, b: B) -> Self
+    where
+        StorageT: Hash + PrimInt + Unsigned,
+        A: Lexer<StorageT>,
+        B: Lexer<StorageT>
+    {
+        let mut input = String::with_capacity(a.input().len() + b.input().len());
+        input.push_str(a.input());
+        input.push_str(b.input());
+        Chain {
+            a,
+            b,
+            input,
+            a_len: None,
+            a_end: Err(()),
+            b_lexemes: HashSet::new(),
+            failed: false
+        }
+    }
+
+    /// Return the lexers this adaptor wraps.
+    pub fn into_inner(self) -> (A, B) {
+        (self.a, self.b)
+    }
+
+    /// Record that `a` has finished.
+    fn finish_a<StorageT>(&mut self)
+    where
+        StorageT: Hash + PrimInt + Unsigned,
+        A: Lexer<StorageT>
+    {
+        let a_len = self.a.input_len();
+        self.a_len = Some(a_len);
+        self.a_end = self
+            .a
+            .line_and_col(&Lexeme::<StorageT>::new_error(a_len, 0));
+    }
+
+    /// Is `l` (which has already been shifted) one of `b`'s lexemes? Lexemes which the `Chain`
+    /// didn't produce (e.g. those inserted by the parser when repairing errors) are attributed
+    /// by their offset, with empty lexemes at the end of `a`'s input attributed to `a`.
+    fn in_b<StorageT: PrimInt>(&self, l: &Lexeme<StorageT>) -> bool {
+        match self.a_len {
+            Some(a_len) => {
+                self.b_lexemes.contains(&key(l))
+                    || l.start() > a_len
+                    || (l.start() == a_len && !l.is_empty())
+            }
+            None => false
+        }
+    }
+
+    /// Convert a lexeme from `b` into one relative to `b`'s input.
+    fn unshift<StorageT: PrimInt>(&self, l: &Lexeme<StorageT>) -> Lexeme<StorageT> {
+        Lexeme::new(l.tok_id(), l.start() - self.a_len.unwrap(), l.len())
+    }
+
+    /// Convert a result from `b` into one relative to the chained input, recording any lexeme.
+    fn shift<StorageT: PrimInt>(
+        &mut self,
+        r: Option<Result<Lexeme<StorageT>, LexError>>
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let a_len = self.a_len.unwrap();
+        match r {
+            Some(Ok(l)) => {
+                let l = Lexeme::new(l.tok_id(), l.start() + a_len, l.len());
+                self.b_lexemes.insert(key(&l));
+                Some(Ok(l))
+            }
+            Some(Err(e)) => Some(Err(LexError { idx: e.idx + a_len })),
+            None => None
+        }
+    }
+}
+
+/// The key by which `Chain` records a lexeme.
+fn key<StorageT: PrimInt>(l: &Lexeme<StorageT>) -> (usize, usize, usize) {
+    (l.start(), l.len(), l.tok_id().to_usize().unwrap())
+}
+
+impl<StorageT, A, B> Lexer<StorageT> for Chain<A, B>
+where
+    StorageT: Hash + PrimInt + Unsigned,
+    A: Lexer<StorageT>,
+    B: Lexer<StorageT>
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        if self.failed {
+            return None;
+        }
+        if self.a_len.is_none() {
+            match self.a.next() {
+                None => self.finish_a(),
+                Some(Err(e)) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+                r => return r
+            }
+        }
+        let r = self.b.next();
+        self.shift(r)
+    }
+
+    fn next_acceptable(
+        &mut self,
+        acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        if self.failed {
+            return None;
+        }
+        if self.a_len.is_none() {
+            match self.a.next_acceptable(acceptable) {
+                None => self.finish_a(),
+                Some(Err(e)) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+                r => return r
+            }
+        }
+        let r = self.b.next_acceptable(acceptable);
+        self.shift(r)
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        if !self.in_b(l) {
+            return self.a.line_and_col(l);
+        }
+        let (a_line, a_col) = self.a_end?;
+        let (line, col) = self.b.line_and_col(&self.unshift(l))?;
+        if line == 1 {
+            Ok((a_line, a_col + col - 1))
+        } else {
+            Ok((a_line + line - 1, col))
+        }
+    }
+
+    fn input(&self) -> &str {
+        &self.input
+    }
+
+    fn input_len(&self) -> usize {
+        self.a_len.unwrap_or_else(|| self.a.input_len()) + self.b.input_len()
+    }
+
+    fn lexeme_str(&self, l: &Lexeme<StorageT>) -> &str {
+        if self.in_b(l) {
+            self.b.lexeme_str(&self.unshift(l))
+        } else {
+            self.a.lexeme_str(l)
+        }
+    }
+
+    fn owned_lexeme_str(&self, l: &Lexeme<StorageT>) -> Option<Rc<str>> {
+        if self.in_b(l) {
+            self.b.owned_lexeme_str(&self.unshift(l))
+        } else {
+            self.a.owned_lexeme_str(l)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lineindex::LineIndex;
+
+    const ID: u8 = 0;
+    const INT: u8 = 1;
+    const KW: u8 = 2;
+    const SEMI: u8 = 3;
+
+    /// A lexer for whitespace-separated identifiers and integers, which errors on anything else.
+    struct WordLexer<'a> {
+        line_index: LineIndex<'a>,
+        i: usize
+    }
+
+    impl<'a> WordLexer<'a> {
+        fn new(s: &'a str) -> Self {
+            WordLexer {
+                line_index: LineIndex::new(s),
+                i: 0
+            }
+        }
+    }
+
+    impl<'a> Lexer<u8> for WordLexer<'a> {
+        fn next(&mut self) -> Option<Result<Lexeme<u8>, LexError>> {
+            let s = self.line_index.input();
+            self.i += s[self.i..].len() - s[self.i..].trim_start().len();
+            let c = s[self.i..].chars().next()?;
+            let tok_id = if c.is_alphabetic() {
+                ID
+            } else if c.is_ascii_digit() {
+                INT
+            } else {
+                return Some(Err(LexError { idx: self.i }));
+            };
+            let len = s[self.i..]
+                .find(char::is_whitespace)
+                .unwrap_or(s.len() - self.i);
+            let l = Lexeme::new(tok_id, self.i, len);
+            self.i += len;
+            Some(Ok(l))
+        }
+
+        fn line_and_col(&self, l: &Lexeme<u8>) -> Result<(usize, usize), ()> {
+            self.line_index.line_and_col(l.start())
+        }
+
+        fn input(&self) -> &str {
+            self.line_index.input()
+        }
+    }
+
+    /// A lexeme's token ID, text, and line and column.
+    type Lexed = (u8, String, (usize, usize));
+
+    /// Lex `lexer` to completion, returning the `Lexed` form of each lexeme.
+    fn lex<L: Lexer<u8>>(mut lexer: L) -> (Vec<Lexed>, L) {
+        let mut lxs = Vec::new();
+        while let Some(r) = lexer.next() {
+            let l = r.unwrap();
+            lxs.push((
+                l.tok_id(),
+                lexer.lexeme_str(&l).to_owned(),
+                lexer.line_and_col(&l).unwrap()
+            ));
+        }
+        (lxs, lexer)
+    }
+
+    fn toks(lxs: &[Lexed]) -> Vec<(u8, &str)> {
+        lxs.iter().map(|&(t, ref s, _)| (t, s.as_str())).collect()
+    }
+
+    #[test]
+    fn test_filter_and_map() {
+        let (lxs, lexer) = lex(WordLexer::new("if x 1\n  then 23 y")
+            .filter_lexemes(|l| l.tok_id() != INT)
+            .map_lexemes(|l, s| match s {
+                "if" | "then" => Lexeme::new(KW, l.start(), l.len()),
+                _ => l
+            }));
+        assert_eq!(
+            toks(&lxs),
+            vec![(KW, "if"), (ID, "x"), (KW, "then"), (ID, "y")]
+        );
+        assert_eq!(lxs[2].2, (2, 3));
+        assert_eq!(lexer.input(), "if x 1\n  then 23 y");
+
+        // Adaptors can also wrap a borrowed lexer, which can then be used again afterwards.
+        let mut wl = WordLexer::new("a 1 b");
+        {
+            let mut f = (&mut wl).filter_lexemes(|l| l.tok_id() == INT);
+            assert_eq!(f.next().unwrap().unwrap(), Lexeme::new(INT, 2, 1));
+        }
+        assert_eq!(wl.next().unwrap().unwrap(), Lexeme::new(ID, 4, 1));
+    }
+
+    #[test]
+    fn test_insert() {
+        // Insert a semicolon at the end of every non-empty line, as in Go.
+        let asi = |lexer: &WordLexer, prev: Option<&Lexeme<u8>>, next: Option<&Lexeme<u8>>| {
+            let prev = prev?;
+            if prev.tok_id() == SEMI {
+                return None;
+            }
+            let on_new_line = match next {
+                Some(n) => lexer.line_and_col(n).unwrap().0 > lexer.line_and_col(prev).unwrap().0,
+                None => true
+            };
+            if on_new_line {
+                Some(Lexeme::new(SEMI, prev.end(), 0))
+            } else {
+                None
+            }
+        };
+        let (lxs, _) = lex(WordLexer::new("a b\n\nc\n  d 1").insert_lexemes(asi));
+        assert_eq!(
+            toks(&lxs),
+            vec![
+                (ID, "a"),
+                (ID, "b"),
+                (SEMI, ""),
+                (ID, "c"),
+                (SEMI, ""),
+                (ID, "d"),
+                (INT, "1"),
+                (SEMI, "")
+            ]
+        );
+        assert_eq!(lxs[2].2, (1, 4));
+        assert_eq!(lxs[7].2, (4, 6));
+
+        let (lxs, _) = lex(WordLexer::new("").insert_lexemes(asi));
+        assert!(lxs.is_empty());
+
+        // Errors stop the stream, without anything being inserted after them.
+        let mut lexer = WordLexer::new("a\n!").insert_lexemes(asi);
+        assert_eq!(lexer.next().unwrap().unwrap(), Lexeme::new(ID, 0, 1));
+        match lexer.next() {
+            Some(Err(LexError { idx: 2 })) => (),
+            _ => panic!()
+        }
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_chain() {
+        let host = "a 1\nb ";
+        let embedded = "c\n d";
+        let (lxs, lexer) = lex(WordLexer::new(host)
+            .chain(WordLexer::new(embedded))
+            .chain(WordLexer::new(" e")));
+        assert_eq!(
+            lxs,
+            vec![
+                (ID, "a".to_owned(), (1, 1)),
+                (INT, "1".to_owned(), (1, 3)),
+                (ID, "b".to_owned(), (2, 1)),
+                (ID, "c".to_owned(), (2, 3)),
+                (ID, "d".to_owned(), (3, 2)),
+                (ID, "e".to_owned(), (3, 4)),
+            ]
+        );
+        assert_eq!(lexer.input(), "a 1\nb c\n d e");
+        let mut lexer = lexer;
+        let c = Lexeme::new(ID, 6, 1);
+        assert_eq!(lexer.lexeme_str(&c), &lexer.input()[6..7]);
+        assert!(lexer.next().is_none());
+
+        // Errors from the second lexer are shifted; errors from the first stop the stream.
+        let mut lexer = WordLexer::new("a ").chain(WordLexer::new("b !"));
+        assert_eq!(lexer.next().unwrap().unwrap(), Lexeme::new(ID, 0, 1));
+        assert_eq!(lexer.next().unwrap().unwrap(), Lexeme::new(ID, 2, 1));
+        match lexer.next() {
+            Some(Err(LexError { idx: 4 })) => (),
+            _ => panic!()
+        }
+        let mut lexer = WordLexer::new("! ").chain(WordLexer::new("b"));
+        match lexer.next() {
+            Some(Err(LexError { idx: 0 })) => (),
+            _ => panic!()
+        }
+        assert!(lexer.next().is_none());
+
+        // An empty lexeme from the second lexer at the end of the first lexer's input is
+        // attributed to the second lexer.
+        let semi_first = |_: &WordLexer, prev: Option<&Lexeme<u8>>, _: Option<&Lexeme<u8>>| {
+            if prev.is_none() {
+                Some(Lexeme::new(SEMI, 0, 0))
+            } else {
+                None
+            }
+        };
+        let mut lexer = WordLexer::new("a").chain(WordLexer::new("b").insert_lexemes(semi_first));
+        let semi = Lexeme::new(SEMI, 1, 0);
+        assert_eq!(lexer.next().unwrap().unwrap(), Lexeme::new(ID, 0, 1));
+        assert!(!lexer.in_b(&semi));
+        assert_eq!(lexer.next().unwrap().unwrap(), semi);
+        assert!(lexer.in_b(&semi));
+        assert!(!lexer.in_b(&Lexeme::new(ID, 1, 0)));
+        assert_eq!(lexer.next().unwrap().unwrap(), Lexeme::new(ID, 1, 1));
+    }
+}
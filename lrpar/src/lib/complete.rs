@@ -0,0 +1,420 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::{fmt::Debug, hash::Hash};
+
+use cfgrammar::{PIdx, RIdx, SIdx, TIdx};
+use lrtable::{Action, StIdx};
+use num_traits::{AsPrimitive, PrimInt, Unsigned};
+
+use lex::Lexeme;
+use parser::Parser;
+
+/// The result of asking what can be completed at a given point in the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completions<StorageT> {
+    tokens: Vec<TIdx<StorageT>>,
+    items: Vec<(PIdx<StorageT>, SIdx<StorageT>)>,
+    rules: Vec<RIdx<StorageT>>,
+    partial: Option<Lexeme<StorageT>>,
+    errors: Vec<Lexeme<StorageT>>,
+    after_partial: Option<Box<Completions<StorageT>>>
+}
+
+impl<StorageT> Completions<StorageT> {
+    /// Return the tokens (in ascending order of `TIdx`) which are valid next tokens at the cursor.
+    /// This includes the EOF token if the input can validly end at the cursor.
+    pub fn tokens(&self) -> &[TIdx<StorageT>] {
+        &self.tokens
+    }
+
+    /// Return the kernel items of the states on the parse stack at the cursor, innermost first,
+    /// as `(production, dot position)` pairs. Items whose dot is at the start of a production
+    /// (and the grammar's start production) are not included.
+    pub fn items(&self) -> &[(PIdx<StorageT>, SIdx<StorageT>)] {
+        &self.items
+    }
+
+    /// Return the rules which are being parsed at the cursor, innermost first (without
+    /// duplicates).
+    pub fn rules(&self) -> &[RIdx<StorageT>] {
+        &self.rules
+    }
+
+    /// If the cursor is in the middle, or at the end, of a lexeme, return that lexeme. The lexeme
+    /// is not considered part of the prefix, so the other results describe what can appear in its
+    /// place (e.g. allowing an editor to filter completions using the partial text before the
+    /// cursor).
+    pub fn partial(&self) -> Option<&Lexeme<StorageT>> {
+        self.partial.as_ref()
+    }
+
+    /// If the cursor is at the end of the `partial` lexeme, return the completions which result
+    /// from instead considering that lexeme part of the prefix (i.e. what can follow it).
+    pub fn after_partial(&self) -> Option<&Completions<StorageT>> {
+        self.after_partial.as_ref().map(|c| &**c)
+    }
+
+    /// Return the lexemes in the prefix which could not be parsed, and which were skipped.
+    pub fn errors(&self) -> &[Lexeme<StorageT>] {
+        &self.errors
+    }
+}
+
+/// Calculate the completions at the cursor position `off` when parsing from the entry state
+/// `start_stidx`. `parser`'s lexemes must be exactly those lexemes which end at or before `off`;
+/// `partial` is the lexeme the cursor is in the middle of (if any). If `partial` is `None` and the
+/// last lexeme ends exactly at `off`, that lexeme is treated as `partial`, and the completions
+/// which result from treating it as part of the prefix are returned in `after_partial`.
+pub(crate) fn complete<StorageT: 'static + Debug + Hash + PrimInt + Unsigned>(
+    parser: &Parser<StorageT>,
+    start_stidx: StIdx,
+    off: usize,
+    partial: Option<Lexeme<StorageT>>
+) -> Completions<StorageT>
+where
+    usize: AsPrimitive<StorageT>,
+    u32: AsPrimitive<StorageT>
+{
+    let mut end_laidx = parser.lexemes.len();
+    let at_cursor = partial.is_none()
+        && parser
+            .lexemes
+            .last()
+            .map_or(false, |l| !l.is_empty() && l.end() == off);
+    if at_cursor {
+        end_laidx -= 1;
+    }
+
+    let mut pstack = vec![start_stidx];
+    let mut errors = Vec::new();
+    parse_prefix(parser, 0, end_laidx, &mut pstack, &mut errors);
+    if at_cursor {
+        let mut a_pstack = pstack.clone();
+        let mut a_errors = errors.clone();
+        parse_prefix(
+            parser,
+            end_laidx,
+            end_laidx + 1,
+            &mut a_pstack,
+            &mut a_errors
+        );
+        let after = completions(parser, off, end_laidx + 1, &a_pstack, None, a_errors, None);
+        let partial = Some(parser.lexemes[end_laidx]);
+        completions(
+            parser,
+            off,
+            end_laidx,
+            &pstack,
+            partial,
+            errors,
+            Some(after)
+        )
+    } else {
+        completions(parser, off, end_laidx, &pstack, partial, errors, None)
+    }
+}
+
+/// Parse the lexemes from `laidx` up to (but excluding) `end_laidx`. If we encounter an error we
+/// simply skip the offending lexeme, recording it in `errors`: the automatic recoverers aren't
+/// suitable here since, close to the cursor, they tend to insert tokens which take the parser
+/// away from the context the user is interested in.
+fn parse_prefix<StorageT: 'static + Debug + Hash + PrimInt + Unsigned>(
+    parser: &Parser<StorageT>,
+    mut laidx: usize,
+    end_laidx: usize,
+    pstack: &mut Vec<StIdx>,
+    errors: &mut Vec<Lexeme<StorageT>>
+) where
+    usize: AsPrimitive<StorageT>,
+    u32: AsPrimitive<StorageT>
+{
+    loop {
+        laidx = parser.lr_upto(None, laidx, end_laidx, pstack, &mut None);
+        if laidx == end_laidx {
+            break;
+        }
+        errors.push(parser.next_lexeme(laidx));
+        laidx += 1;
+    }
+}
+
+/// Calculate the completions for the parse stack `pstack`, which results from parsing the lexemes
+/// up to (but excluding) `end_laidx`.
+fn completions<StorageT: 'static + Debug + Hash + PrimInt + Unsigned>(
+    parser: &Parser<StorageT>,
+    off: usize,
+    end_laidx: usize,
+    pstack: &[StIdx],
+    partial: Option<Lexeme<StorageT>>,
+    errors: Vec<Lexeme<StorageT>>,
+    after_partial: Option<Completions<StorageT>>
+) -> Completions<StorageT>
+where
+    usize: AsPrimitive<StorageT>,
+    u32: AsPrimitive<StorageT>
+{
+    let grm = parser.grm;
+    // A token is a valid next token if, after any reductions, it can be shifted (or, for the EOF
+    // token, accepted). Since LALR-style tables can contain reductions that lead to an error, we
+    // can't simply look at the actions of the state on top of the stack.
+    let mut tokens = Vec::new();
+    for tidx in parser.stable.state_actions(*pstack.last().unwrap()) {
+        if Some(tidx) == grm.error_token_idx() {
+            continue;
+        }
+        let lexeme = Lexeme::new(StorageT::from(u32::from(tidx)).unwrap(), off, 0);
+        let mut n_pstack = pstack.to_vec();
+        let new_laidx = parser.lr_upto(
+            Some(lexeme),
+            end_laidx,
+            end_laidx + 1,
+            &mut n_pstack,
+            &mut None
+        );
+        if new_laidx > end_laidx
+            || parser.stable.action(*n_pstack.last().unwrap(), tidx) == Action::Accept
+        {
+            tokens.push(tidx);
+        }
+    }
+    tokens.sort();
+
+    let mut items = Vec::new();
+    let mut rules = Vec::new();
+    for stidx in pstack.iter().rev() {
+        let mut st_items = parser
+            .sgraph
+            .core_state(*stidx)
+            .items
+            .keys()
+            .filter(|&&(pidx, sidx)| {
+                usize::from(sidx) > 0 && grm.prod_to_rule(pidx) != grm.start_rule_idx()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        st_items.sort();
+        for &(pidx, _) in &st_items {
+            let ridx = grm.prod_to_rule(pidx);
+            if !rules.contains(&ridx) {
+                rules.push(ridx);
+            }
+        }
+        items.extend(st_items);
+    }
+
+    Completions {
+        tokens,
+        items,
+        rules,
+        partial,
+        errors,
+        after_partial: after_partial.map(Box::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{YaccGrammar, YaccKind};
+    use lrtable::{from_yacc, Minimiser};
+    use num_traits::ToPrimitive;
+
+    use super::*;
+    use parser::{
+        test::{grm_lex, SmallLexer},
+        RTParserBuilder
+    };
+
+    static LEXS: &str = "
+        [a-z]+ 'ID'
+        \\( '('
+        \\) ')'
+        , ','
+    ";
+
+    static GRMS: &str = "
+        %start Call
+        %%
+        Call: 'ID' '(' Args ')';
+        Args: Args ',' 'ID' | 'ID' | ;
+    ";
+
+    fn do_complete(input: &str, off: usize) -> (YaccGrammar<u16>, Completions<u16>) {
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, GRMS).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let lexemes = grm_lex(&grm, LEXS, input);
+        let mut lexer = SmallLexer { lexemes, i: 0 };
+        let cmpls = RTParserBuilder::new(&grm, &sgraph, &stable)
+            .complete(&mut lexer, off)
+            .unwrap();
+        (grm, cmpls)
+    }
+
+    fn tok_names(grm: &YaccGrammar<u16>, cmpls: &Completions<u16>) -> Vec<String> {
+        cmpls
+            .tokens()
+            .iter()
+            .map(|&tidx| grm.token_name(tidx).unwrap_or("$").to_owned())
+            .collect()
+    }
+
+    fn rule_names(grm: &YaccGrammar<u16>, cmpls: &Completions<u16>) -> Vec<String> {
+        cmpls
+            .rules()
+            .iter()
+            .map(|&ridx| grm.rule_name(ridx).to_owned())
+            .collect()
+    }
+
+    /// As `do_complete`, but for a cursor at the end of a lexeme, return the completions which
+    /// consider that lexeme part of the prefix.
+    fn do_complete_after(input: &str, off: usize) -> (YaccGrammar<u16>, Completions<u16>) {
+        let (grm, cmpls) = do_complete(input, off);
+        assert_eq!(cmpls.partial().unwrap().end(), off);
+        let after = cmpls.after_partial().unwrap().clone();
+        (grm, after)
+    }
+
+    #[test]
+    fn valid_tokens() {
+        let (grm, cmpls) = do_complete("f(a,b)", 0);
+        assert_eq!(tok_names(&grm, &cmpls), vec!["ID"]);
+        assert!(rule_names(&grm, &cmpls).is_empty());
+
+        let (grm, cmpls) = do_complete_after("f(a,b)", 2);
+        let mut toks = tok_names(&grm, &cmpls);
+        toks.sort();
+        assert_eq!(toks, vec![")", ",", "ID"]);
+        assert_eq!(rule_names(&grm, &cmpls), vec!["Call"]);
+
+        let (grm, cmpls) = do_complete_after("f(a,b)", 3);
+        let mut toks = tok_names(&grm, &cmpls);
+        toks.sort();
+        assert_eq!(toks, vec![")", ","]);
+        assert_eq!(rule_names(&grm, &cmpls), vec!["Args", "Call"]);
+
+        let (grm, cmpls) = do_complete_after("f(a,b)", 4);
+        assert_eq!(tok_names(&grm, &cmpls), vec!["ID"]);
+        assert_eq!(rule_names(&grm, &cmpls), vec!["Args", "Call"]);
+
+        let (grm, cmpls) = do_complete_after("f(a,b)", 6);
+        assert_eq!(cmpls.tokens(), &[grm.eof_token_idx()]);
+        assert!(cmpls.partial().is_none());
+        assert!(cmpls.after_partial().is_none());
+        assert!(cmpls.errors().is_empty());
+    }
+
+    #[test]
+    fn partial_lexeme() {
+        let (grm, cmpls) = do_complete("foo(a)", 1);
+        assert_eq!(tok_names(&grm, &cmpls), vec!["ID"]);
+        let id_tidx = grm.token_idx("ID").unwrap();
+        assert_eq!(
+            cmpls.partial(),
+            Some(&Lexeme::new(u32::from(id_tidx).to_u16().unwrap(), 0, 3))
+        );
+        assert!(cmpls.after_partial().is_none());
+
+        let (grm, cmpls) = do_complete("f(abc)", 4);
+        let mut toks = tok_names(&grm, &cmpls);
+        toks.sort();
+        assert_eq!(toks, vec![")", ",", "ID"]);
+        assert_eq!(cmpls.partial().unwrap().start(), 2);
+    }
+
+    #[test]
+    fn cursor_at_end_of_lexeme() {
+        // With the cursor just after "abc", the results describe what can appear in its place...
+        let (grm, cmpls) = do_complete("f(abc)", 5);
+        let mut toks = tok_names(&grm, &cmpls);
+        toks.sort();
+        assert_eq!(toks, vec![")", ",", "ID"]);
+        assert_eq!(rule_names(&grm, &cmpls), vec!["Call"]);
+        let id_tidx = grm.token_idx("ID").unwrap();
+        assert_eq!(
+            cmpls.partial(),
+            Some(&Lexeme::new(u32::from(id_tidx).to_u16().unwrap(), 2, 3))
+        );
+        // ...and what can follow it.
+        let after = cmpls.after_partial().unwrap();
+        let mut toks = tok_names(&grm, after);
+        toks.sort();
+        assert_eq!(toks, vec![")", ","]);
+        assert_eq!(rule_names(&grm, after), vec!["Args", "Call"]);
+        assert!(after.partial().is_none());
+
+        // At the end of the input, the last lexeme is treated in the same way.
+        let (grm, cmpls) = do_complete("f(abc", 5);
+        assert_eq!(cmpls.partial().unwrap().start(), 2);
+        let mut toks = tok_names(&grm, cmpls.after_partial().unwrap());
+        toks.sort();
+        assert_eq!(toks, vec![")", ","]);
+    }
+
+    #[test]
+    fn start_rules() {
+        let grms = GRMS.replace("%start Call", "%start Call Args");
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, &grms).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let pb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let lexemes = grm_lex(&grm, LEXS, "a,b");
+
+        let mut lexer = SmallLexer {
+            lexemes: lexemes.clone(),
+            i: 0
+        };
+        let cmpls = pb
+            .complete_rule(grm.rule_idx("Args").unwrap(), &mut lexer, 2)
+            .unwrap();
+        let cmpls = cmpls.after_partial().unwrap();
+        assert_eq!(tok_names(&grm, cmpls), vec!["ID"]);
+        assert_eq!(rule_names(&grm, cmpls), vec!["Args"]);
+        assert!(cmpls.errors().is_empty());
+
+        // The first start rule can't start with "a,".
+        let mut lexer = SmallLexer { lexemes, i: 0 };
+        let cmpls = pb.complete(&mut lexer, 2).unwrap();
+        let cmpls = cmpls.after_partial().unwrap();
+        assert_eq!(tok_names(&grm, cmpls), vec!["("]);
+        assert_eq!(cmpls.errors().len(), 1);
+    }
+
+    #[test]
+    fn errors_in_prefix() {
+        let (grm, cmpls) = do_complete_after("f((a", 4);
+        let mut toks = tok_names(&grm, &cmpls);
+        toks.sort();
+        assert_eq!(toks, vec![")", ","]);
+        assert_eq!(cmpls.errors().len(), 1);
+        assert_eq!(cmpls.errors()[0].start(), 2);
+    }
+}
@@ -276,7 +276,9 @@ where
     fn insert(&self, n: &PathFNode<StorageT>, nbrs: &mut Vec<(u16, PathFNode<StorageT>)>) {
         let laidx = n.laidx;
         for tidx in self.parser.stable.state_actions(*n.pstack.val().unwrap()) {
-            if tidx == self.parser.grm.eof_token_idx() {
+            if tidx == self.parser.grm.eof_token_idx()
+                || Some(tidx) == self.parser.grm.error_token_idx()
+            {
                 continue;
             }
 
@@ -35,7 +35,7 @@ use std::{
     convert::AsRef,
     env::{current_dir, var},
     error::Error,
-    fmt::Debug,
+    fmt::{self, Debug},
     fs::{self, read_to_string, File},
     hash::Hash,
     io::Write,
@@ -45,13 +45,13 @@ use std::{
 
 use bincode::{deserialize, serialize_into};
 use cfgrammar::{
-    yacc::{YaccGrammar, YaccKind},
-    Symbol
+    yacc::{YaccGrammar, YaccGrammarError, YaccKind},
+    PIdx, Symbol
 };
 use filetime::FileTime;
 use lrtable::{from_yacc, Minimiser, StateGraph, StateTable};
 use num_traits::{AsPrimitive, PrimInt, Unsigned};
-use regex::Regex;
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
 use typename::TypeName;
 
@@ -80,7 +80,9 @@ pub struct CTParserBuilder<StorageT = u32> {
     // function below so that, if it's changed, the grammar is rebuilt.
     recoverer: RecoveryKind,
     phantom: PhantomData<StorageT>,
-    actionkind: ActionKind
+    actionkind: ActionKind,
+    contextual_lexing: bool,
+    sync_tokens: Option<Vec<String>>
 }
 
 impl<StorageT> CTParserBuilder<StorageT>
@@ -111,7 +113,9 @@ where
         CTParserBuilder {
             recoverer: RecoveryKind::MF,
             phantom: PhantomData,
-            actionkind: ActionKind::GenericParseTree
+            actionkind: ActionKind::GenericParseTree,
+            contextual_lexing: false,
+            sync_tokens: None
         }
     }
 
@@ -121,6 +125,22 @@ where
         self
     }
 
+    /// If `b` is true, the generated parser uses contextual lexing (see
+    /// [`RTParserBuilder::contextual_lexing`](../parser/struct.RTParserBuilder.html#method.contextual_lexing)).
+    pub fn contextual_lexing(mut self, b: bool) -> Self {
+        self.contextual_lexing = b;
+        self
+    }
+
+    /// Set the synchronisation tokens used by `RecoveryKind::Panic` to the tokens named in `toks`,
+    /// overriding those declared by the grammar's `%sync` declaration (if any). See
+    /// [`RTParserBuilder::sync_tokens`](../parser/struct.RTParserBuilder.html#method.sync_tokens).
+    /// If a name in `toks` isn't one of the grammar's tokens, `process_file` returns an error.
+    pub fn sync_tokens(mut self, toks: &[&str]) -> Self {
+        self.sync_tokens = Some(toks.iter().map(|&t| t.to_owned()).collect());
+        self
+    }
+
     /// Given the filename `x/y.z` as input, statically compile the grammar `src/x/y.z` into a Rust
     /// module which can then be imported using `lrpar_mod!(x_y)`. This is a convenience function
     /// around [`process_file`](struct.CTParserBuilder.html#method.process_file) which makes it
@@ -159,6 +179,17 @@ where
     ///            (Option<Node<StorageT>>, Vec<ParseError<StorageT>>)>
     /// ```
     ///
+    /// and a type `ParseResult`, an alias for `parse`'s return type.
+    ///
+    /// If `CustomAction`s are used, the actions and programs of `inp` are placed in a separate
+    /// file (e.g. `grm.y.rs` for `grm.y`) laid out such that the errors and warnings rustc reports
+    /// for them have the same line numbers as in `inp` (and, up to the first `$n` on a line, the
+    /// same column numbers). rustc still names the generated file, not `inp`, in its messages.
+    ///
+    /// If the grammar is invalid, a [`BuildErrors`](struct.BuildErrors.html) describing every
+    /// error found is returned. If the grammar has shift/reduce or reduce/reduce conflicts, a
+    /// warning is reported (via Cargo, if called from a build script).
+    ///
     /// # Panics
     ///
     /// If `StorageT` is not big enough to index the grammar's tokens, rules, or
@@ -172,8 +203,33 @@ where
         P: AsRef<Path>,
         Q: AsRef<Path>
     {
-        let inc = read_to_string(&inp).unwrap();
-        let grm = YaccGrammar::<StorageT>::new_with_storaget(YaccKind::Eco, &inc)?;
+        let inc = read_to_string(&inp)
+            .map_err(|e| format!("Can't read {}: {}", inp.as_ref().display(), e))?;
+        let grm = match YaccGrammar::<StorageT>::new_with_all_errors(YaccKind::Eco, &inc) {
+            Ok(grm) => grm,
+            Err(errs) => {
+                let mut build_errs = BuildErrors::new(&inp, &inc);
+                for e in errs {
+                    // Parser errors' `Display` output includes the line and column, which
+                    // `BuildErrors` will show anyway.
+                    let msg = match e {
+                        YaccGrammarError::YaccParserError(ref e) => e.kind.to_string(),
+                        YaccGrammarError::GrammarValidationError(ref e) => e.to_string()
+                    };
+                    build_errs.push(msg, e.line_col());
+                }
+                return Err(Box::new(build_errs));
+            }
+        };
+        if let Some(ref toks) = self.sync_tokens {
+            let mut build_errs = BuildErrors::new(&inp, &inc);
+            for t in toks.iter().filter(|t| grm.token_idx(t).is_none()) {
+                build_errs.push(format!("Unknown token '{}' in sync_tokens", t), None);
+            }
+            if !build_errs.is_empty() {
+                return Err(Box::new(build_errs));
+            }
+        }
         let rule_ids = grm
             .tokens_map()
             .iter()
@@ -225,7 +281,29 @@ where
         // confusing than the alternatives).
         fs::remove_file(&outp_rs).ok();
 
-        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager)?;
+        let (sgraph, stable) = match from_yacc(&grm, Minimiser::Pager) {
+            Ok(x) => x,
+            Err(e) => {
+                let mut build_errs = BuildErrors::new(&inp, &inc);
+                build_errs.push(e.to_string(), None);
+                return Err(Box::new(build_errs));
+            }
+        };
+        let conflicts = [
+            (stable.shift_reduce, "shift/reduce"),
+            (stable.reduce_reduce, "reduce/reduce")
+        ]
+        .iter()
+        .filter(|&&(n, _)| n > 0)
+        .map(|&(n, kind)| format!("{} {} conflict{}", n, kind, if n == 1 { "" } else { "s" }))
+        .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            _warning(&format!(
+                "{}: {}",
+                inp.as_ref().display(),
+                conflicts.join(" and ")
+            ));
+        }
         // Because we're lazy, we don't write our own serializer. We use serde and bincode to
         // create files $out_base.grm, $out_base.sgraph, and $out_base.out_stable which contain
         // binary versions of the relevant structs, and then include those binary files into the
@@ -237,46 +315,125 @@ where
         let mut outs = String::new();
         // Header
         let mod_name = inp.as_ref().file_stem().unwrap().to_str().unwrap();
-        let actiontype = match grm.actiontype() {
-            Some(t) => t,
-            None => {
-                match self.actionkind {
-                    ActionKind::CustomAction => panic!("Action return type not defined!"),
-                    ActionKind::GenericParseTree => {
-                        "" // Dummy string that will never be used
-                    }
-                }
-            }
-        };
         outs.push_str(&format!("mod {}_y {{\n", mod_name));
+        outs.push_str(&self._gen_parser(
+            &grm,
+            &format!("include_bytes!(\"{}\")", out_grm.to_str().unwrap()),
+            &format!("include_bytes!(\"{}\")", out_sgraph.to_str().unwrap()),
+            &format!("include_bytes!(\"{}\")", out_stable.to_str().unwrap())
+        ));
+
+        if let ActionKind::CustomAction = self.actionkind {
+            // The actions and user code are written to a separate file (e.g. grm.y.rs for
+            // grm.y) which is laid out so that rustc's errors and warnings point to the same line
+            // as in the grammar file.
+            let inp_name = inp.as_ref().file_name().unwrap().to_str().unwrap();
+            let mut outp_actions = outd.as_ref().to_path_buf();
+            outp_actions.push(format!("{}.{}", inp_name, RUST_FILE_EXT));
+            let mut f = File::create(&outp_actions)?;
+            f.write_all(self.gen_actions(&grm, inp_name).as_bytes())?;
+            outs.push_str(&format!(
+                "\n    include!(\"{}\");\n",
+                outp_actions.to_str().unwrap()
+            ));
+        }
+
+        outs.push_str("}\n\n");
+
+        // Output the cache so that we can check whether the IDs map is stable.
+        outs.push_str(&cache);
+
+        let mut f = File::create(outp_rs)?;
+        f.write_all(outs.as_bytes())?;
+        Ok(rule_ids)
+    }
+
+    /// Generate the code which defines `parse`, `ParseResult`, and the `R_` constants for `grm`
+    /// (but not the functions for its actions), intended to be placed inside a module. If the
+    /// grammar has more than one start rule, a function `parse_X` is also defined for each start
+    /// rule `X`. `grm_bytes`, `sgraph_bytes`, and `stable_bytes` are expressions (of type `&[u8]`)
+    /// for the serialised grammar, state graph, and state table respectively. This is only public
+    /// so that `lrpar_macros` can use it.
+    #[doc(hidden)]
+    pub fn _gen_parser(
+        &self,
+        grm: &YaccGrammar<StorageT>,
+        grm_bytes: &str,
+        sgraph_bytes: &str,
+        stable_bytes: &str
+    ) -> String {
+        let mut outs = String::new();
+        let actiontype = self.actiontype(grm);
         outs.push_str(
-            "    use lrpar::{{Lexer, LexParseError, RecoveryKind, RTParserBuilder}};
+            "    use lrpar::{{Lexer, LexParseError, RecoveryKind, RIdx, RTParserBuilder}};
     use lrpar::ctbuilder::_reconstitute;"
         );
 
-        match self.actionkind {
+        let resultt = match self.actionkind {
             ActionKind::CustomAction => {
-                outs.push_str(&format!(
-                    "use lrpar::parser::AStackType;
-
-    pub fn parse(lexer: &mut Lexer<{storaget}>)
-          -> Result<{actiont}, LexParseError<{storaget}>>
-    {{",
+                outs.push_str("use lrpar::parser::AStackType;");
+                format!(
+                    "Result<{actiont}, LexParseError<{storaget}>>",
                     storaget = StorageT::type_name(),
                     actiont = actiontype
-                ));
+                )
             }
             ActionKind::GenericParseTree => {
-                outs.push_str(&format!(
-                    "use lrpar::Node;
+                outs.push_str("use lrpar::Node;");
+                format!(
+                    "Result<Node<{storaget}>, LexParseError<{storaget}>>",
+                    storaget = StorageT::type_name()
+                )
+            }
+        };
+        outs.push_str(&format!(
+            "
+
+    /// The type returned by `parse`.
+    #[allow(dead_code)]
+    pub type ParseResult = {resultt};
 
     pub fn parse(lexer: &mut Lexer<{storaget}>)
-          -> Result<Node<{storaget}>, LexParseError<{storaget}>>
-    {{",
-                    storaget = StorageT::type_name()
+          -> {resultt}
+    {{
+        {prefix}parse(lexer, RIdx({ridx:?}))
+    }}
+",
+            resultt = resultt,
+            storaget = StorageT::type_name(),
+            prefix = ACTION_PREFIX,
+            ridx = usize::from(grm.start_rules()[0])
+        ));
+
+        if grm.start_rules().len() > 1 {
+            for &ridx in grm.start_rules() {
+                outs.push_str(&format!(
+                    "
+    #[allow(dead_code, non_snake_case)]
+    pub fn parse_{name}(lexer: &mut Lexer<{storaget}>)
+          -> {resultt}
+    {{
+        {prefix}parse(lexer, RIdx({ridx:?}))
+    }}
+",
+                    name = grm.rule_name(ridx),
+                    resultt = resultt,
+                    storaget = StorageT::type_name(),
+                    prefix = ACTION_PREFIX,
+                    ridx = usize::from(ridx)
                 ));
             }
-        };
+        }
+
+        outs.push_str(&format!(
+            "
+    fn {prefix}parse(lexer: &mut Lexer<{storaget}>, ridx: RIdx<{storaget}>)
+          -> {resultt}
+    {{",
+            resultt = resultt,
+            storaget = StorageT::type_name(),
+            prefix = ACTION_PREFIX
+        ));
 
         // grm, sgraph, stable
         let recoverer = match self.recoverer {
@@ -285,22 +442,32 @@ where
             RecoveryKind::Panic => "Panic",
             RecoveryKind::None => "None"
         };
+        // Any other options the generated parser sets on its `RTParserBuilder`.
+        let mut options = String::new();
+        if let Some(ref toks) = self.sync_tokens {
+            let tidxs = toks
+                .iter()
+                .map(|t| format!("::lrpar::TIdx({:?})", grm.token_idx(t).unwrap().0))
+                .collect::<Vec<_>>();
+            options.push_str(&format!(
+                "\n            .sync_tokens(&[{}])",
+                tidxs.join(", ")
+            ));
+        }
 
         outs.push_str(&format!(
             "
-        let (grm, sgraph, stable) = _reconstitute(include_bytes!(\"{}\"),
-                                                  include_bytes!(\"{}\"),
-                                                  include_bytes!(\"{}\"));",
-            out_grm.to_str().unwrap(),
-            out_sgraph.to_str().unwrap(),
-            out_stable.to_str().unwrap()
+        let (grm, sgraph, stable) = _reconstitute({},
+                                                  {},
+                                                  {});",
+            grm_bytes, sgraph_bytes, stable_bytes
         ));
 
         match self.actionkind {
             ActionKind::CustomAction => {
                 // action function references
-                outs.push_str(&format!("\n        let mut actions: Vec<Option<&Fn(&str, &[AStackType<{actiont}, {}>]) -> {actiont}>> = Vec::new();\n",
-                    StorageT::type_name(),
+                outs.push_str(&format!("\n        let mut actions: Vec<Option<&Fn(&Lexer<{storaget}>, &[AStackType<{actiont}, {storaget}>]) -> {actiont}>> = Vec::new();\n",
+                    storaget=StorageT::type_name(),
                     actiont=actiontype)
                 );
                 for pidx in grm.iter_pidxs() {
@@ -316,11 +483,11 @@ where
                 }
                 outs.push_str(&format!(
                     "
-        let s = lexer.input().to_string();
         RTParserBuilder::new(&grm, &sgraph, &stable)
             .recoverer(RecoveryKind::{})
-            .parse2(lexer, &actions, &s)\n",
-                    recoverer,
+            .contextual_lexing({}){}
+            .parse2_rule(ridx, lexer, &actions)\n",
+                    recoverer, self.contextual_lexing, options
                 ));
             }
             ActionKind::GenericParseTree => {
@@ -328,8 +495,9 @@ where
                     "
         RTParserBuilder::new(&grm, &sgraph, &stable)
             .recoverer(RecoveryKind::{})
-            .parse(lexer)\n",
-                    recoverer
+            .contextual_lexing({}){}
+            .parse_rule(ridx, lexer)\n",
+                    recoverer, self.contextual_lexing, options
                 ));
             }
         };
@@ -347,77 +515,158 @@ where
                 ));
             }
         }
+        outs
+    }
 
-        match self.actionkind {
-            ActionKind::CustomAction => {
-                let re: Regex = { Regex::new(r"\$([0-9]+)").unwrap() };
-                if let Some(s) = grm.programs() {
-                    outs.push_str("\n/* User code */\n\n");
-                    outs.push_str(s);
+    /// The type returned by `grm`'s actions.
+    fn actiontype<'a>(&self, grm: &'a YaccGrammar<StorageT>) -> &'a str {
+        match grm.actiontype() {
+            Some(t) => t,
+            None => {
+                match self.actionkind {
+                    ActionKind::CustomAction => panic!("Action return type not defined!"),
+                    ActionKind::GenericParseTree => {
+                        "" // Dummy string that will never be used
+                    }
                 }
+            }
+        }
+    }
 
-                // Convert actions to functions
-                outs.push_str("\n/* Converted actions */\n\n");
-                for pidx in grm.iter_pidxs() {
-                    if let Some(s) = grm.action(pidx) {
-                        // Iterate over all $-arguments and replace them with their respective
-                        // element from the argument vector (e.g. $1 is replaced by args[0]). At
-                        // the same time extract &str from tokens and actiontype from nonterminals.
-                        outs.push_str(&format!("fn {prefix}action_{}({prefix}input: &str, {prefix}args: &[AStackType<{actiont}, {}>]) -> {actiont} {{
-",
-                            usize::from(pidx),
-                            StorageT::type_name(),
-                            prefix=ACTION_PREFIX,
-                            actiont=actiontype));
-                        for m in re.find_iter(s) {
-                            let num = match &s[m.start() + 1..m.end()].parse::<usize>() {
-                                Ok(val) => val - 1,
-                                Err(_) => unreachable!()
-                            };
-                            outs.push_str(&format!(
-                                "    let {prefix}arg_{} = match {prefix}args[{}] {{",
-                                num + 1,
-                                num,
-                                prefix = ACTION_PREFIX
-                            ));
-                            match grm.prod(pidx)[num] {
-                                Symbol::Rule(_) => outs.push_str(
-                                    "
-        AStackType::ActionType(v) => v,
-        AStackType::Lexeme(_) => unreachable!()
-    };
-"
-                                ),
-                                Symbol::Token(_) => outs.push_str(&format!(
-                                    "
-        AStackType::ActionType(_) => unreachable!(),
-        AStackType::Lexeme(l) => &{prefix}input[l.start()..l.end()]
-    }};
-",
-                                    prefix = ACTION_PREFIX
-                                ))
-                            };
-                        }
-                        let ns = re.replace_all(
-                            s,
-                            format!("{prefix}arg_$1", prefix = ACTION_PREFIX).as_str()
-                        );
-                        outs.push_str(&format!("    {}", &ns,));
-                        outs.push_str("\n}\n\n");
+    /// Generate the Rust code for `grm`'s actions (each as a function `__gt_action_<pidx>`) and
+    /// programs. Wherever possible, each action and the programs start at the same line and
+    /// column as in the grammar file `file_name`; where that isn't possible (e.g. because two
+    /// actions are on the same line) a comment notes where the code came from. `$n`s in
+    /// actions are replaced by `__gt_arg_n`s, each of which is bound to the value of the
+    /// production's `n`th symbol. Since these are longer than the `$n`s they replace, code after a
+    /// `$n` on the same line is shifted to the right.
+    fn gen_actions(&self, grm: &YaccGrammar<StorageT>, file_name: &str) -> String {
+        // Add newlines/spaces to `outs` such that the next character output will be at
+        // `line_col`. `cur_line` is the line that the end of `outs` is currently on.
+        fn move_to(
+            outs: &mut String,
+            cur_line: &mut usize,
+            line_col: Option<(usize, usize)>,
+            file_name: &str
+        ) {
+            match line_col {
+                Some((line, col)) if line > *cur_line => {
+                    for _ in *cur_line..line {
+                        outs.push('\n');
                     }
+                    *cur_line = line;
+                    outs.push_str(&" ".repeat(col - 1));
+                }
+                Some((line, col)) => {
+                    outs.push_str(&format!(" /* {}:{}:{} */ ", file_name, line, col))
+                }
+                None => {
+                    outs.push('\n');
+                    *cur_line += 1;
                 }
             }
-            ActionKind::GenericParseTree => ()
-        };
-
-        outs.push_str("}\n\n");
+        }
 
-        // Output the cache so that we can check whether the IDs map is stable.
-        outs.push_str(&cache);
+        let re = Regex::new(r"\$([0-9]+)").unwrap();
+        let mut outs = String::new();
+        let mut cur_line = 1;
+        for pidx in grm.iter_pidxs() {
+            let s = match grm.action(pidx) {
+                Some(s) => s,
+                None => continue
+            };
+            // The function's signature and the bindings of `__gt_arg_n`s must all go on one line,
+            // as the action itself may start on the line after the previous action ends. If
+            // there's room, we start that line afresh, so that it doesn't clutter rustc's
+            // messages about the previous action.
+            if !outs.is_empty() {
+                if let Some((line, _)) = grm.action_line_col(pidx) {
+                    if line > cur_line + 1 {
+                        outs.push('\n');
+                        cur_line += 1;
+                    }
+                }
+            }
+            let (sig, bindings) = self._gen_action_preamble(grm, pidx);
+            outs.push_str(&sig);
+            outs.push_str(" {");
+            outs.push_str(&bindings);
+            move_to(
+                &mut outs,
+                &mut cur_line,
+                grm.action_line_col(pidx),
+                file_name
+            );
+            let body = re.replace_all(s, |c: &Captures| _action_arg(&c[1]));
+            outs.push_str(&body);
+            cur_line += body.matches('\n').count();
+            // A `//` comment on the action's last line would swallow the closing brace.
+            if body.lines().last().map_or(false, |l| l.contains("//")) {
+                outs.push('\n');
+                cur_line += 1;
+            }
+            outs.push_str(" }");
+        }
+        if let Some(s) = grm.programs() {
+            move_to(&mut outs, &mut cur_line, grm.programs_line_col(), file_name);
+            outs.push_str(s);
+        }
+        if !outs.ends_with('\n') {
+            outs.push('\n');
+        }
+        outs
+    }
 
-        let mut f = File::create(outp_rs)?;
-        f.write_all(outs.as_bytes())?;
-        Ok(rule_ids)
+    /// Generate the signature of the function for production `pidx`'s action (without the
+    /// function's body) and the statements, which must start the function's body, binding each
+    /// `$n` used in the action to the value of the production's `n`th symbol. This is only public
+    /// so that `lrpar_macros` can use it.
+    #[doc(hidden)]
+    pub fn _gen_action_preamble(
+        &self,
+        grm: &YaccGrammar<StorageT>,
+        pidx: PIdx<StorageT>
+    ) -> (String, String) {
+        let sig = format!(
+            "fn {prefix}action_{}({prefix}lexer: &Lexer<{storaget}>, \
+             {prefix}args: &[AStackType<{actiont}, {storaget}>]) -> {actiont}",
+            usize::from(pidx),
+            storaget = StorageT::type_name(),
+            prefix = ACTION_PREFIX,
+            actiont = self.actiontype(grm)
+        );
+        let re = Regex::new(r"\$([0-9]+)").unwrap();
+        let mut args = re
+            .captures_iter(grm.action(pidx).as_ref().unwrap())
+            .map(|c| c[1].to_owned())
+            .collect::<Vec<_>>();
+        args.sort();
+        args.dedup();
+        let mut bindings = String::new();
+        for arg in args {
+            // Extract &str from tokens and actiontype from rules.
+            let num = arg.parse::<usize>().unwrap() - 1;
+            let arms = match grm.prod(pidx)[num] {
+                Symbol::Rule(_) => {
+                    "AStackType::ActionType(v) => v, AStackType::Lexeme(..) => unreachable!()"
+                        .to_owned()
+                }
+                Symbol::Token(_) => format!(
+                    "AStackType::ActionType(_) => unreachable!(), \
+                     AStackType::Lexeme(_, Some(ref t)) => &**t, \
+                     AStackType::Lexeme(l, None) => {prefix}lexer.lexeme_str(&l)",
+                    prefix = ACTION_PREFIX
+                )
+            };
+            bindings.push_str(&format!(
+                " let {} = match {prefix}args[{}] {{ {} }};",
+                _action_arg(&arg),
+                num,
+                arms,
+                prefix = ACTION_PREFIX
+            ));
+        }
+        (sig, bindings)
     }
 
     /// Generate the cache, which determines if anything's changed enough that we need to
@@ -438,6 +687,11 @@ where
 
         // Record the recoverer
         cache.push_str(&format!("   Recoverer: {:?}\n", self.recoverer));
+        cache.push_str(&format!(
+            "   Contextual lexing: {:?}\n",
+            self.contextual_lexing
+        ));
+        cache.push_str(&format!("   Sync tokens: {:?}\n", self.sync_tokens));
 
         // Record the rule IDs map
         for tidx in grm.iter_tidxs() {
@@ -467,6 +721,99 @@ where
     }
 }
 
+/// One or more errors found at build time in a grammar file (or, when used by lrlex, a lexer
+/// file). When displayed, each error names the file, line, and column, and shows the offending
+/// line of the source with a caret beneath the relevant column.
+pub struct BuildErrors {
+    path: PathBuf,
+    src: String,
+    errs: Vec<(String, Option<(usize, usize)>)>
+}
+
+impl BuildErrors {
+    /// Create an empty set of errors for the file `path`, whose contents are `src`.
+    pub fn new<P: AsRef<Path>>(path: P, src: &str) -> Self {
+        BuildErrors {
+            path: path.as_ref().to_path_buf(),
+            src: src.to_owned(),
+            errs: Vec::new()
+        }
+    }
+
+    /// Add an error with the message `msg` which, if `line_col` is not `None`, relates to the
+    /// given line and column (both starting from 1) of the source.
+    pub fn push(&mut self, msg: String, line_col: Option<(usize, usize)>) {
+        self.errs.push((msg, line_col));
+    }
+
+    /// How many errors have been added?
+    pub fn len(&self) -> usize {
+        self.errs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errs.is_empty()
+    }
+}
+
+impl Error for BuildErrors {}
+
+impl fmt::Display for BuildErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, &(ref msg, line_col)) in self.errs.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "error: {}", msg)?;
+            match line_col {
+                Some((line, col)) => {
+                    let gutter = " ".repeat(line.to_string().len());
+                    let src_line = self.src.lines().nth(line - 1).unwrap_or("");
+                    // Line the caret up with the column, copying any tabs before it so that it
+                    // still lines up when the source line contains them.
+                    let mut caret = src_line
+                        .chars()
+                        .take(col - 1)
+                        .map(|c| if c == '\t' { '\t' } else { ' ' })
+                        .collect::<String>();
+                    let indent = col - 1 - caret.chars().count();
+                    caret.push_str(&" ".repeat(indent));
+                    writeln!(f, "{}--> {}:{}:{}", gutter, self.path.display(), line, col)?;
+                    writeln!(f, "{} |", gutter)?;
+                    writeln!(f, "{} | {}", line, src_line)?;
+                    writeln!(f, "{} | {}^", gutter, caret)?;
+                }
+                None => writeln!(f, " --> {}", self.path.display())?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BuildErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Report the warning `msg`: via Cargo if we're being called from a build script, or on stderr
+/// otherwise. This function is also used by lrlex's `LexerBuilder`.
+#[doc(hidden)]
+pub fn _warning(msg: &str) {
+    if var("OUT_DIR").is_ok() {
+        println!("cargo:warning={}", msg);
+    } else {
+        eprintln!("Warning: {}", msg);
+    }
+}
+
+/// The name of the variable which `$n` (where `n` is the string `n`) in an action is renamed to.
+/// This is only public so that `lrpar_macros` can use it.
+#[doc(hidden)]
+pub fn _action_arg(n: &str) -> String {
+    format!("{}arg_{}", ACTION_PREFIX, n)
+}
+
 /// This function is called by generated files; it exists so that generated files don't require a
 /// dependency on serde and rmps.
 #[doc(hidden)]
@@ -484,3 +831,125 @@ pub fn _reconstitute<'a, StorageT: Deserialize<'a> + Hash + PrimInt + Unsigned>(
     let stable = deserialize(stable_buf).unwrap();
     (grm, sgraph, stable)
 }
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use super::{ActionKind, BuildErrors, CTParserBuilder};
+    use cfgrammar::yacc::{YaccGrammar, YaccKind};
+
+    #[test]
+    fn test_build_errors() {
+        let mut errs = BuildErrors::new("src/x.y", "%start A\n%%\nA:\tB 'c';\n");
+        assert!(errs.is_empty());
+        errs.push("Unknown reference to rule 'B'".to_string(), Some((3, 4)));
+        errs.push("Accept/reduce conflict".to_string(), None);
+        assert_eq!(errs.len(), 2);
+        assert_eq!(
+            errs.to_string(),
+            "error: Unknown reference to rule 'B'
+ --> src/x.y:3:4
+  |
+3 | A:\tB 'c';
+  |   \t^
+
+error: Accept/reduce conflict
+ --> src/x.y
+"
+        );
+        assert_eq!(format!("{:?}", errs), errs.to_string());
+    }
+
+    #[test]
+    fn test_gen_actions() {
+        let src = "%start A
+%type T
+%%
+A: B 'b' { f($1,
+              $2) }
+ | 'a' { $1.len() } | { 0 } ;
+
+B: 'c' { 1 // one
+  };
+%%
+fn f(x: T, _: &str) -> T { x }
+";
+        let grm = YaccGrammar::<u8>::new_with_storaget(YaccKind::Eco, src).unwrap();
+        let outs = CTParserBuilder::<u8>::new()
+            .action_kind(ActionKind::CustomAction)
+            .gen_actions(&grm, "x.y");
+        // Each action, and the programs, should start at the same line and column as in `src`.
+        let lines = outs.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 11);
+        assert!(lines[0].starts_with("fn __gt_action_0("));
+        assert!(lines[0].contains(
+            "let __gt_arg_2 = match __gt_args[1] { AStackType::ActionType(_) => unreachable!(), \
+             AStackType::Lexeme(_, Some(ref t)) => &**t, \
+             AStackType::Lexeme(l, None) => __gt_lexer.lexeme_str(&l) };"
+        ));
+        assert_eq!(lines[3], "           f(__gt_arg_1,");
+        assert!(lines[4].starts_with("              __gt_arg_2) }fn __gt_action_1("));
+        assert!(lines[5].starts_with("         __gt_arg_1.len() }fn __gt_action_2("));
+        // Two actions on the same line can't both start at the right column.
+        assert!(lines[5].ends_with("{ /* x.y:6:25 */ 0 }"));
+        assert!(lines[6].starts_with("fn __gt_action_3("));
+        // The closing brace mustn't end up in the action's comment.
+        assert_eq!(lines[7], "         1 // one");
+        assert_eq!(lines[8], " }");
+        assert_eq!(lines[10], "fn f(x: T, _: &str) -> T { x }");
+    }
+
+    #[test]
+    fn test_error_productions() {
+        let outd = env::temp_dir().join(format!("lrpar_ctbuilder_{}", process::id()));
+        fs::create_dir_all(&outd).unwrap();
+        let inp = outd.join("err.y");
+        fs::write(&inp, "%start S\n%%\nS: S T | ;\nT: 'a' ';' | error ';';\n").unwrap();
+        let rule_ids = CTParserBuilder::<u8>::new()
+            .process_file(&inp, &outd)
+            .unwrap();
+        // `error` isn't a token the lexer needs to produce.
+        let mut names = rule_ids.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec![";", "a"]);
+        assert!(outd.join("err_y.rs").is_file());
+        fs::remove_dir_all(&outd).unwrap();
+    }
+
+    #[test]
+    fn test_gen_parser_multiple_starts() {
+        let gen = |src| {
+            let grm = YaccGrammar::<u8>::new_with_storaget(YaccKind::Original, src).unwrap();
+            let outs = CTParserBuilder::<u8>::new()._gen_parser(&grm, "G", "S", "T");
+            (grm, outs)
+        };
+        let (grm, outs) = gen("%start B A\n%%\nA: 'a';\nB: A 'b';");
+        // `parse` parses the first start rule.
+        let b_ridx = usize::from(grm.rule_idx("B").unwrap());
+        assert!(outs.contains(&format!("__gt_parse(lexer, RIdx({}))", b_ridx)));
+        assert!(outs.contains("pub fn parse_A("));
+        assert!(outs.contains("pub fn parse_B("));
+        assert!(outs.contains(".parse_rule(ridx, lexer)"));
+
+        let (_, outs) = gen("%start B\n%%\nA: 'a';\nB: A 'b';");
+        assert!(!outs.contains("pub fn parse_"));
+    }
+
+    #[test]
+    fn test_gen_parser_sync_tokens() {
+        let grm =
+            YaccGrammar::<u8>::new_with_storaget(YaccKind::Original, "%start A\n%%\nA: 'a' 'b';")
+                .unwrap();
+        let outs = CTParserBuilder::<u8>::new()._gen_parser(&grm, "G", "S", "T");
+        assert!(!outs.contains(".sync_tokens("));
+        let outs = CTParserBuilder::<u8>::new()
+            .sync_tokens(&["b", "a"])
+            ._gen_parser(&grm, "G", "S", "T");
+        assert!(outs.contains(&format!(
+            ".sync_tokens(&[::lrpar::TIdx({}), ::lrpar::TIdx({})])",
+            grm.token_idx("b").unwrap().0,
+            grm.token_idx("a").unwrap().0
+        )));
+    }
+}
@@ -0,0 +1,528 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Turn the repair sequences found by a recoverer into concrete changes to the user's input.
+//! This is useful for tools which want to offer "quick fixes" for parsing errors.
+
+use cfgrammar::{yacc::YaccGrammar, TIdx};
+use num_traits::{AsPrimitive, PrimInt, Unsigned};
+
+use lex::Lexeme;
+use parser::{ParseError, ParseRepair};
+
+/// How whitespace is placed around text inserted (or deleted) by a repair.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Spacing {
+    /// Insert text exactly as it was rendered, with no additional whitespace.
+    None,
+    /// Add a single space between an edit and a neighbouring character only when both are "word"
+    /// characters (alphanumerics or `_`), i.e. when they would otherwise run together.
+    Minimal,
+    /// Always separate an edit from neighbouring non-whitespace text with a single space.
+    Always
+}
+
+/// A single change to an input: the bytes `start..end` are to be replaced with `text`.
+/// Insertions have `start == end`; deletions have an empty `text`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String
+}
+
+/// A `RepairApplier` converts repair sequences into `TextEdit`s and/or fixed input text.
+pub struct RepairApplier<'a, StorageT: 'a> {
+    grm: &'a YaccGrammar<StorageT>,
+    spacing: Spacing,
+    token_text: Option<&'a Fn(TIdx<StorageT>) -> String>
+}
+
+impl<'a, StorageT: 'static + PrimInt + Unsigned> RepairApplier<'a, StorageT>
+where
+    usize: AsPrimitive<StorageT>
+{
+    /// Create a new `RepairApplier` for the grammar `grm`. By default inserted tokens are
+    /// rendered using their `%epp` string (or their name if they don't have one) and spacing
+    /// follows [`Spacing::Minimal`](enum.Spacing.html).
+    pub fn new(grm: &'a YaccGrammar<StorageT>) -> Self {
+        RepairApplier {
+            grm,
+            spacing: Spacing::Minimal,
+            token_text: None
+        }
+    }
+
+    /// Set the spacing policy used when inserting and deleting text to `spacing`.
+    pub fn spacing(mut self, spacing: Spacing) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Use `f` to render the text of inserted tokens (instead of their `%epp` strings).
+    pub fn token_text(mut self, f: &'a Fn(TIdx<StorageT>) -> String) -> Self {
+        self.token_text = Some(f);
+        self
+    }
+
+    /// Return the `TextEdit`s (in ascending order of position) which apply the repair sequence
+    /// `err.repairs()[rpr_idx]` to `input`. `lexemes` must be all the lexemes of `input` (e.g. as
+    /// returned by `Lexer::all_lexemes`).
+    ///
+    /// # Panics
+    ///
+    /// If `rpr_idx` is not a valid index into `err.repairs()`.
+    pub fn error_edits(
+        &self,
+        input: &str,
+        lexemes: &[Lexeme<StorageT>],
+        err: &ParseError<StorageT>,
+        rpr_idx: usize
+    ) -> Vec<TextEdit> {
+        let rprs = &err.repairs()[rpr_idx];
+        let mut laidx = lexeme_idx(lexemes, err.lexeme());
+        let mut edits: Vec<TextEdit> = Vec::new();
+        let mut i = 0;
+        while i < rprs.len() {
+            match rprs[i] {
+                ParseRepair::Shift(_) => {
+                    laidx += 1;
+                    i += 1;
+                    continue;
+                }
+                // The lexemes deleted after recovering with an error production need not be
+                // adjacent, since the parser may shift other lexemes in between.
+                ParseRepair::Delete(l) if lexemes.get(laidx) != Some(&l) => {
+                    laidx = lexeme_idx(lexemes, &l);
+                }
+                _ => ()
+            }
+
+            // We group together a run of inserts followed by a run of deletes (either of which
+            // may be empty) and turn them into a single edit, since their spacing depends on each
+            // other.
+            let start = insert_off(lexemes, laidx);
+            let mut end = start;
+            let mut texts = Vec::new();
+            while i < rprs.len() {
+                match rprs[i] {
+                    ParseRepair::Insert(tidx) => texts.push(self.render(tidx)),
+                    _ => break
+                }
+                i += 1;
+            }
+            while i < rprs.len() {
+                match rprs[i] {
+                    // A lexeme which isn't in `lexemes` is only deleted if it starts an edit, so
+                    // that we always make progress.
+                    ParseRepair::Delete(l)
+                        if lexemes.get(laidx) == Some(&l) || (texts.is_empty() && end == start) =>
+                    {
+                        end = end.max(l.end());
+                        laidx += 1;
+                    }
+                    _ => break
+                }
+                i += 1;
+            }
+
+            let left = left_neighbour(input, &edits, start);
+            let right = input[end..].chars().next();
+            let text = self.join(&texts, left, right);
+            match edits.last_mut() {
+                Some(ref mut e) if e.end == start => {
+                    e.end = end;
+                    e.text.push_str(&text);
+                    continue;
+                }
+                _ => ()
+            }
+            edits.push(TextEdit { start, end, text });
+        }
+        edits
+    }
+
+    /// Return the `TextEdit`s (in ascending order of position) which apply, for each error
+    /// `errs[i]`, the repair sequence `errs[i].repairs()[rpr_idxs[i]]`. Passing `0` for every
+    /// error applies the repairs the parser itself used to recover. Since each repair sequence
+    /// affects how later input is parsed, choosing other repair sequences can lead to later errors
+    /// being meaningless: any edit which overlaps with an earlier edit is thus ignored.
+    ///
+    /// # Panics
+    ///
+    /// If `errs` and `rpr_idxs` are of different lengths, or if any `rpr_idxs[i]` is not a valid
+    /// index into `errs[i].repairs()`.
+    pub fn edits(
+        &self,
+        input: &str,
+        lexemes: &[Lexeme<StorageT>],
+        errs: &[ParseError<StorageT>],
+        rpr_idxs: &[usize]
+    ) -> Vec<TextEdit> {
+        assert_eq!(errs.len(), rpr_idxs.len());
+        let mut edits: Vec<TextEdit> = Vec::new();
+        for (err, &rpr_idx) in errs.iter().zip(rpr_idxs.iter()) {
+            for e in self.error_edits(input, lexemes, err, rpr_idx) {
+                if edits.last().map_or(true, |x| x.end <= e.start) {
+                    edits.push(e);
+                }
+            }
+        }
+        edits
+    }
+
+    /// Return a version of `input` with the repair sequences chosen by `rpr_idxs` applied (see
+    /// [`edits`](#method.edits) for details).
+    pub fn apply(
+        &self,
+        input: &str,
+        lexemes: &[Lexeme<StorageT>],
+        errs: &[ParseError<StorageT>],
+        rpr_idxs: &[usize]
+    ) -> String {
+        apply_edits(input, &self.edits(input, lexemes, errs, rpr_idxs))
+    }
+
+    fn render(&self, tidx: TIdx<StorageT>) -> String {
+        match self.token_text {
+            Some(f) => f(tidx),
+            None => self
+                .grm
+                .token_epp(tidx)
+                .or_else(|| self.grm.token_name(tidx))
+                .unwrap_or("")
+                .to_owned()
+        }
+    }
+
+    /// Join `texts` together, adding spaces (between each other and the `left` and `right`
+    /// neighbouring characters) as dictated by this applier's spacing policy.
+    fn join(&self, texts: &[String], left: Option<char>, right: Option<char>) -> String {
+        let mut s = String::new();
+        for t in texts {
+            let prev = if s.is_empty() { left } else { s.chars().last() };
+            if self.needs_space(prev, t.chars().next()) {
+                s.push(' ');
+            }
+            s.push_str(t);
+        }
+        let prev = if s.is_empty() { left } else { s.chars().last() };
+        if self.needs_space(prev, right) {
+            s.push(' ');
+        }
+        s
+    }
+
+    fn needs_space(&self, left: Option<char>, right: Option<char>) -> bool {
+        match (left, right) {
+            (Some(l), Some(r)) => match self.spacing {
+                Spacing::None => false,
+                Spacing::Minimal => is_word_char(l) && is_word_char(r),
+                Spacing::Always => !l.is_whitespace() && !r.is_whitespace()
+            },
+            _ => false
+        }
+    }
+}
+
+/// Return a copy of `input` with `edits` applied. `edits` must be sorted in ascending order of
+/// position and must not overlap.
+pub fn apply_edits(input: &str, edits: &[TextEdit]) -> String {
+    let mut s = String::with_capacity(input.len());
+    let mut i = 0;
+    for e in edits {
+        assert!(i <= e.start && e.start <= e.end);
+        s.push_str(&input[i..e.start]);
+        s.push_str(&e.text);
+        i = e.end;
+    }
+    s.push_str(&input[i..]);
+    s
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Return the index of `lexeme` in `lexemes`. If `lexeme` is the (artificial) EOF lexeme, this
+/// will be `lexemes.len()`.
+fn lexeme_idx<StorageT: PrimInt + Unsigned>(
+    lexemes: &[Lexeme<StorageT>],
+    lexeme: &Lexeme<StorageT>
+) -> usize {
+    match lexemes.binary_search_by_key(&lexeme.start(), |l| l.start()) {
+        Ok(mut i) => {
+            // Zero-length lexemes mean that several lexemes can share the same start position.
+            while i > 0 && lexemes[i - 1].start() == lexeme.start() {
+                i -= 1;
+            }
+            while i < lexemes.len() && lexemes[i].start() == lexeme.start() && lexemes[i] != *lexeme
+            {
+                i += 1;
+            }
+            i
+        }
+        Err(i) => i
+    }
+}
+
+/// Return the position that a token inserted before the lexeme at `laidx` should be placed at.
+/// This mirrors the position the parser itself gives to inserted tokens.
+fn insert_off<StorageT: PrimInt + Unsigned>(lexemes: &[Lexeme<StorageT>], laidx: usize) -> usize {
+    if laidx < lexemes.len() {
+        lexemes[laidx].start()
+    } else if let Some(l) = lexemes.last() {
+        l.end()
+    } else {
+        0
+    }
+}
+
+/// Return the character immediately before `off` once `edits` have been applied.
+fn left_neighbour(input: &str, edits: &[TextEdit], off: usize) -> Option<char> {
+    match edits.last() {
+        Some(e) if e.end == off => match e.text.chars().last() {
+            Some(c) => Some(c),
+            None => input[..e.start].chars().last()
+        },
+        _ => input[..off].chars().last()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser::{
+        test::{do_parse, grm_lex},
+        RecoveryKind
+    };
+
+    fn check_fix(lexs: &str, grms: &str, input: &str, spacing: Spacing, expected: &str) {
+        let (grm, pr) = do_parse(RecoveryKind::MF, lexs, grms, input);
+        let errs = pr.unwrap_err().1;
+        let lexemes = grm_lex(&grm, lexs, input);
+        let rpr_idxs = vec![0; errs.len()];
+        let fixed = RepairApplier::new(&grm)
+            .spacing(spacing)
+            .apply(input, &lexemes, &errs, &rpr_idxs);
+        assert_eq!(fixed, expected);
+    }
+
+    /// Check that the repairs MF finds for `input` lead to the edits `expected_edits` (as
+    /// `(start, end, text)` triples) and the fixed text `expected` under each spacing policy, in
+    /// the order `Minimal`, `Always`, `None`.
+    fn check_edits(
+        lexs: &str,
+        grms: &str,
+        input: &str,
+        expected_edits: &[&[(usize, usize, &str)]],
+        expected: &[&str]
+    ) {
+        let (grm, pr) = do_parse(RecoveryKind::MF, lexs, grms, input);
+        let errs = pr.unwrap_err().1;
+        let lexemes = grm_lex(&grm, lexs, input);
+        let rpr_idxs = vec![0; errs.len()];
+        for (i, &spacing) in [Spacing::Minimal, Spacing::Always, Spacing::None]
+            .iter()
+            .enumerate()
+        {
+            let edits = RepairApplier::new(&grm)
+                .spacing(spacing)
+                .edits(input, &lexemes, &errs, &rpr_idxs);
+            assert_eq!(
+                edits,
+                expected_edits[i]
+                    .iter()
+                    .map(|&(start, end, text)| TextEdit {
+                        start,
+                        end,
+                        text: text.to_owned()
+                    })
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(apply_edits(input, &edits), expected[i]);
+        }
+    }
+
+    #[test]
+    fn insert_at_eof() {
+        let lexs = "[a-z]+ 'ID'
+                    \\( '('
+                    \\) ')'";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' ')';
+";
+        check_fix(lexs, grms, "f(", Spacing::Minimal, "f()");
+        check_fix(lexs, grms, "f(", Spacing::Always, "f( )");
+        check_fix(lexs, grms, "f(", Spacing::None, "f()");
+    }
+
+    #[test]
+    fn insert_word() {
+        let lexs = "[0-9]+ 'INT'
+                    \\+ '+'
+                    ; ';'";
+        let grms = "%start S
+%epp INT '1'
+%%
+S: 'INT' '+' 'INT' ';';
+";
+        // MF inserts an INT before the ';'.
+        check_fix(lexs, grms, "2+;", Spacing::Minimal, "2+1;");
+        check_fix(lexs, grms, "2+;", Spacing::Always, "2+ 1 ;");
+    }
+
+    #[test]
+    fn spacing_between_words() {
+        let lexs = "[a-z]+ 'ID'
+                    ; ';'";
+        let grms = "%start S
+%epp ID 'x'
+%%
+S: 'ID' 'ID' 'ID' ';';
+";
+        check_fix(lexs, grms, "a;", Spacing::Minimal, "a x x;");
+        check_fix(lexs, grms, "a;", Spacing::None, "axx;");
+    }
+
+    #[test]
+    fn delete() {
+        let lexs = "[0-9]+ 'INT'
+                    \\+ '+'
+                    \\( '('
+                    \\) ')'";
+        let grms = "%start E
+%epp INT '1'
+%%
+E: E '+' T | T;
+T: 'INT' | '(' E ')';
+";
+        // MF's first repair deletes the second '+'.
+        check_edits(
+            lexs,
+            grms,
+            "2++3",
+            &[&[(2, 3, "")], &[(2, 3, " ")], &[(2, 3, "")]],
+            &["2+3", "2+ 3", "2+3"]
+        );
+        // MF's first repair inserts an INT and then deletes the ')', which become a single edit.
+        check_edits(
+            lexs,
+            grms,
+            "2+)",
+            &[&[(2, 3, "1")], &[(2, 3, " 1")], &[(2, 3, "1")]],
+            &["2+1", "2+ 1", "2+1"]
+        );
+    }
+
+    #[test]
+    fn error_production_deletes() {
+        let lexs = "\\( '('
+                    \\) ')'
+                    \\+ '+'
+                    [a-z]+ 'ID'";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: '(' Inner ')';
+Inner: 'ID' | 'ID' '+' 'ID' | error;
+";
+        // Recovering with an error production records the discarded lexemes as deletions, which
+        // are merged when adjacent.
+        check_edits(
+            lexs,
+            grms,
+            "(a)(++)",
+            &[&[(4, 6, "")], &[(4, 6, " ")], &[(4, 6, "")]],
+            &["(a)()", "(a)( )", "(a)()"]
+        );
+        // Lexemes discarded while still recovering from an earlier error are recorded as part of
+        // that error's repairs.
+        check_edits(
+            lexs,
+            grms,
+            "(+)(+)",
+            &[
+                &[(1, 2, ""), (4, 5, "")],
+                &[(1, 2, " "), (4, 5, " ")],
+                &[(1, 2, ""), (4, 5, "")]
+            ],
+            &["()()", "( )( )", "()()"]
+        );
+    }
+
+    #[test]
+    fn token_text_callback() {
+        let lexs = "[a-z]+ 'ID'
+                    \\( '('
+                    \\) ')'";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' ')';
+";
+        let input = "f(";
+        let (grm, pr) = do_parse(RecoveryKind::MF, lexs, grms, input);
+        let errs = pr.unwrap_err().1;
+        let lexemes = grm_lex(&grm, lexs, input);
+        let f = |tidx| format!("<{}>", grm.token_name(tidx).unwrap());
+        let edits = RepairApplier::new(&grm)
+            .token_text(&f)
+            .edits(input, &lexemes, &errs, &[0]);
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                start: 2,
+                end: 2,
+                text: "<)>".to_owned()
+            }]
+        );
+        assert_eq!(apply_edits(input, &edits), "f(<)>");
+    }
+
+    #[test]
+    fn apply_multiple_edits() {
+        let edits = vec![
+            TextEdit {
+                start: 0,
+                end: 1,
+                text: "".to_owned()
+            },
+            TextEdit {
+                start: 2,
+                end: 2,
+                text: "xy".to_owned()
+            },
+        ];
+        assert_eq!(apply_edits("abc", &edits), "bxyc");
+    }
+}
@@ -0,0 +1,302 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A `Lexer` built from an iterator of tokens, for pairing lrpar grammars with hand-written or
+//! third-party tokenizers.
+
+use std::{cell::RefCell, collections::HashMap, hash::Hash, ops::Range};
+
+use cfgrammar::{yacc::YaccGrammar, TIdx};
+use num_traits::{AsPrimitive, PrimInt, Unsigned};
+
+use lex::{LexError, Lexeme, Lexer};
+use lineindex::LineIndex;
+
+/// A reference to one of a grammar's tokens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenRef<'a, StorageT> {
+    /// The token with the given name.
+    Name(&'a str),
+    /// The token with the given index.
+    Idx(TIdx<StorageT>),
+    /// Input which the tokenizer could not lex, which is turned into an error lexeme (see
+    /// [`Lexeme::new_error`](../lex/struct.Lexeme.html#method.new_error)).
+    Error
+}
+
+/// Types which can be converted into a [`TokenRef`](enum.TokenRef.html): token names (`&str` and
+/// `String`), `TIdx`s, and `TokenRef`s themselves.
+pub trait AsTokenRef<StorageT> {
+    fn as_token_ref(&self) -> TokenRef<StorageT>;
+}
+
+impl<'a, StorageT: Copy> AsTokenRef<StorageT> for TokenRef<'a, StorageT> {
+    fn as_token_ref(&self) -> TokenRef<StorageT> {
+        *self
+    }
+}
+
+impl<'a, StorageT> AsTokenRef<StorageT> for &'a str {
+    fn as_token_ref(&self) -> TokenRef<StorageT> {
+        TokenRef::Name(self)
+    }
+}
+
+impl<StorageT> AsTokenRef<StorageT> for String {
+    fn as_token_ref(&self) -> TokenRef<StorageT> {
+        TokenRef::Name(self)
+    }
+}
+
+impl<StorageT: Copy> AsTokenRef<StorageT> for TIdx<StorageT> {
+    fn as_token_ref(&self) -> TokenRef<StorageT> {
+        TokenRef::Idx(*self)
+    }
+}
+
+/// A `Lexer` which takes its lexemes from an iterator of `(token, byte range)` pairs, where each
+/// token is a name, a `TIdx`, or a [`TokenRef`](enum.TokenRef.html). Each distinct token name is
+/// looked up in the grammar only once. Line and column information is only calculated if it is
+/// asked for.
+///
+/// ```text
+/// // my_tokenizer produces, e.g., ("ID", 0..1), ("=", 2..3), ...
+/// let mut lexer = IterLexer::new(&grm, input, my_tokenizer(input));
+/// let pt = RTParserBuilder::new(&grm, &sgraph, &stable).parse(&mut lexer);
+/// ```
+///
+/// If the iterator produces a token which isn't in the grammar (including the grammar's EOF
+/// token), or a range which isn't within `input`, `next` returns a `LexError`. Both indicate a
+/// mismatch between the tokenizer and the grammar or input, rather than a problem with the input.
+pub struct IterLexer<'a, StorageT, I> {
+    grm: &'a YaccGrammar<StorageT>,
+    input: &'a str,
+    iter: I,
+    tok_ids: HashMap<String, StorageT>,
+    line_index: RefCell<Option<LineIndex<'a>>>
+}
+
+impl<'a, StorageT, I, T> IterLexer<'a, StorageT, I>
+where
+    StorageT: 'static + Hash + PrimInt + Unsigned,
+    usize: AsPrimitive<StorageT>,
+    I: Iterator<Item = (T, Range<usize>)>,
+    T: AsTokenRef<StorageT>
+{
+    /// Create a lexer for `input`, whose tokens (for the grammar `grm`) are produced by `iter`.
+    pub fn new<J>(grm: &'a YaccGrammar<StorageT>, input: &'a str, iter: J) -> Self
+    where
+        J: IntoIterator<Item = (T, Range<usize>), IntoIter = I>
+    {
+        IterLexer {
+            grm,
+            input,
+            iter: iter.into_iter(),
+            tok_ids: HashMap::new(),
+            line_index: RefCell::new(None)
+        }
+    }
+
+    /// Return the token ID of `tok` (`None` for `TokenRef::Error`), or `Err` if `tok` isn't one
+    /// of the grammar's tokens.
+    fn tok_id(&mut self, tok: TokenRef<StorageT>) -> Result<Option<StorageT>, ()> {
+        match tok {
+            TokenRef::Name(n) => {
+                if let Some(&tok_id) = self.tok_ids.get(n) {
+                    return Ok(Some(tok_id));
+                }
+                let tidx = self.grm.token_idx(n).ok_or(())?;
+                self.tok_ids.insert(n.to_owned(), tidx.0);
+                Ok(Some(tidx.0))
+            }
+            TokenRef::Idx(tidx) => {
+                if tidx >= self.grm.tokens_len() || tidx == self.grm.eof_token_idx() {
+                    return Err(());
+                }
+                Ok(Some(tidx.0))
+            }
+            TokenRef::Error => Ok(None)
+        }
+    }
+}
+
+impl<'a, StorageT, I, T> Lexer<StorageT> for IterLexer<'a, StorageT, I>
+where
+    StorageT: 'static + Hash + PrimInt + Unsigned,
+    usize: AsPrimitive<StorageT>,
+    I: Iterator<Item = (T, Range<usize>)>,
+    T: AsTokenRef<StorageT>
+{
+    fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        let (tok, r) = self.iter.next()?;
+        if r.start > r.end
+            || r.end > self.input.len()
+            || !self.input.is_char_boundary(r.start)
+            || !self.input.is_char_boundary(r.end)
+        {
+            return Some(Err(LexError {
+                idx: r.start.min(self.input.len())
+            }));
+        }
+        Some(match self.tok_id(tok.as_token_ref()) {
+            Ok(Some(tok_id)) => Ok(Lexeme::new(tok_id, r.start, r.end - r.start)),
+            Ok(None) => Ok(Lexeme::new_error(r.start, r.end - r.start)),
+            Err(()) => Err(LexError { idx: r.start })
+        })
+    }
+
+    fn line_and_col(&self, l: &Lexeme<StorageT>) -> Result<(usize, usize), ()> {
+        let mut line_index = self.line_index.borrow_mut();
+        if line_index.is_none() {
+            *line_index = Some(LineIndex::new(self.input));
+        }
+        line_index.as_ref().unwrap().line_and_col(l.start())
+    }
+
+    fn input(&self) -> &str {
+        self.input
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cfgrammar::yacc::YaccKind;
+    use lrtable::{from_yacc, Minimiser};
+    use parser::{LexParseError, Node, RTParserBuilder};
+
+    const GRM: &str = "%start Stmts
+%%
+Stmts: Stmts Stmt | Stmt;
+Stmt: 'ID' '=' 'INT' ';';";
+
+    /// A hand-written tokenizer for the language of `GRM`.
+    fn tokenize(s: &str) -> Vec<(&'static str, Range<usize>)> {
+        let mut toks = Vec::new();
+        let mut i = 0;
+        while let Some(c) = s[i..].chars().next() {
+            let len = if c.is_alphanumeric() {
+                s[i..]
+                    .find(|d: char| !d.is_alphanumeric())
+                    .unwrap_or(s.len() - i)
+            } else {
+                c.len_utf8()
+            };
+            let name = match c {
+                ' ' | '\n' => {
+                    i += len;
+                    continue;
+                }
+                '=' => "=",
+                ';' => ";",
+                _ if c.is_numeric() => "INT",
+                _ => "ID"
+            };
+            toks.push((name, i..i + len));
+            i += len;
+        }
+        toks
+    }
+
+    #[test]
+    fn test_names() {
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, GRM).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let s = "x = 1;\n  yy = 23;";
+        let mut lexer = IterLexer::new(&grm, s, tokenize(s));
+        let pt = RTParserBuilder::new(&grm, &sgraph, &stable)
+            .parse(&mut lexer)
+            .unwrap();
+        let mut lexemes = Vec::new();
+        let mut todo = vec![&pt];
+        while let Some(n) = todo.pop() {
+            match *n {
+                Node::Term { lexeme, .. } => lexemes.push(lexeme),
+                Node::Nonterm { ref nodes, .. } => todo.extend(nodes.iter().rev())
+            }
+        }
+        assert_eq!(lexemes.len(), 8);
+        assert_eq!(lexer.lexeme_str(&lexemes[4]), "yy");
+        assert_eq!(lexer.line_and_col(&lexemes[4]), Ok((2, 3)));
+        assert_eq!(lexer.line_and_col(&lexemes[7]), Ok((2, 10)));
+        assert_eq!(lexemes[4].tok_id(), grm.token_idx("ID").unwrap().0);
+        assert_eq!(lexer.tok_ids.len(), 4);
+    }
+
+    #[test]
+    fn test_tidxs_and_errors() {
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, GRM).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let tidx = |n| TokenRef::Idx(grm.token_idx(n).unwrap());
+        let s = "x = ?1;";
+        let toks = vec![
+            (tidx("ID"), 0..1),
+            (TokenRef::Name("="), 2..3),
+            (TokenRef::Error, 4..5),
+            (tidx("INT"), 5..6),
+            (tidx(";"), 6..7),
+        ];
+        let mut lexer = IterLexer::new(&grm, s, toks);
+        match RTParserBuilder::new(&grm, &sgraph, &stable).parse(&mut lexer) {
+            Err(LexParseError::ParseError {
+                tree: Some(_),
+                errors: errs,
+                ..
+            }) => {
+                assert_eq!(errs.len(), 1);
+                assert!(errs[0].lexeme().is_error());
+                assert_eq!(errs[0].lexeme().start(), 4);
+            }
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn test_unknown_tokens() {
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, GRM).unwrap();
+        let mut lexer = IterLexer::new(&grm, "x 1", vec![("ID", 0..1), ("NUM", 2..3)]);
+        assert!(lexer.next().unwrap().is_ok());
+        assert_eq!(lexer.next().unwrap().unwrap_err().idx, 2);
+        let toks = vec![(grm.eof_token_idx(), 0..1), (grm.tokens_len(), 0..1)];
+        let mut lexer = IterLexer::new(&grm, "1", toks);
+        assert_eq!(lexer.next().unwrap().unwrap_err().idx, 0);
+        assert_eq!(lexer.next().unwrap().unwrap_err().idx, 0);
+    }
+
+    #[test]
+    fn test_bad_range() {
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, GRM).unwrap();
+        let mut lexer = IterLexer::new(&grm, "1", vec![("INT", 0..2), ("INT", 3..4)]);
+        assert_eq!(lexer.next().unwrap().unwrap_err().idx, 0);
+        assert_eq!(lexer.next().unwrap().unwrap_err().idx, 1);
+    }
+}
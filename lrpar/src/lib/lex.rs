@@ -1,4 +1,4 @@
-use std::{error::Error, fmt, hash::Hash, mem::size_of};
+use std::{error::Error, fmt, hash::Hash, mem::size_of, rc::Rc};
 
 use num_traits::{self, PrimInt, Unsigned};
 
@@ -15,18 +15,57 @@ impl fmt::Display for LexError {
     }
 }
 
-/// Roughly speaking, `Lexer` is an iterator which collectively produces `Lexeme`s, as well as
-/// collecting the newlines encountered so that it can later optionally answer queries of the form
-/// "what's the line and column number of lexeme L".
+/// Roughly speaking, `Lexer` is an iterator which collectively produces `Lexeme`s, and which can
+/// optionally answer queries of the form "what's the line and column number of lexeme L". Callers
+/// who want columns in characters or UTF-16 units can build a
+/// [`LineIndex`](../struct.LineIndex.html) over `input()`.
 pub trait Lexer<StorageT: Hash + PrimInt + Unsigned> {
     /// Return the next `Lexeme` in the input or a `LexError`. Returns `None` if the input has been
     /// fully lexed (or if an error occurred which prevents further lexing).
     fn next(&mut self) -> Option<Result<Lexeme<StorageT>, LexError>>;
-    /// Return the line and column number of a `Lexeme`, or `Err` if it is out of bounds, or no
-    /// line number information was collected.
+    /// Return the next `Lexeme` in the input or a `LexError`, given that only lexemes with the
+    /// token IDs in `acceptable` would be valid at this point. Parsers call this, rather than
+    /// `next`, when contextual lexing is enabled (see
+    /// [`RTParserBuilder::contextual_lexing`](../parser/struct.RTParserBuilder.html#method.contextual_lexing)).
+    /// Lexers can use `acceptable` to resolve ambiguities (e.g. to treat a word as a keyword only
+    /// where the keyword is valid); the default implementation ignores it and calls `next`.
+    fn next_acceptable(
+        &mut self,
+        _acceptable: &[StorageT]
+    ) -> Option<Result<Lexeme<StorageT>, LexError>> {
+        self.next()
+    }
+    /// Return the line and (byte) column number of a `Lexeme`, or `Err` if it is out of bounds, or
+    /// no line number information is available.
     fn line_and_col(&self, &Lexeme<StorageT>) -> Result<(usize, usize), ()>;
+    /// Return the lexer's input. Lexers which don't hold all of their input in memory at once
+    /// return only the part they currently hold.
     fn input(&self) -> &str;
 
+    /// Return the length, in bytes, of all the input the lexer has read so far. The default
+    /// implementation returns `input().len()`; lexers which don't hold all of their input in
+    /// memory at once must override this.
+    fn input_len(&self) -> usize {
+        self.input().len()
+    }
+
+    /// Return the text of the lexeme `l`, which must have been produced by this lexer. The
+    /// default implementation slices `input()`; lexers which don't hold all of their input in
+    /// memory at once must override this, and may return `""` for lexemes which are no longer in
+    /// memory (see `owned_lexeme_str`).
+    fn lexeme_str(&self, l: &Lexeme<StorageT>) -> &str {
+        &self.input()[l.start()..l.end()]
+    }
+
+    /// Return an owned copy of the text of `l`, which must be the lexeme most recently returned
+    /// by `next` or `next_acceptable`, if `lexeme_str` may not be able to return it later. Parsers
+    /// call this for every lexeme they lex, handing the text to actions (and storing it in parse
+    /// trees) and dropping it once it is no longer needed. The default implementation returns
+    /// `None`; lexers which don't hold all of their input in memory at once must override this.
+    fn owned_lexeme_str(&self, _l: &Lexeme<StorageT>) -> Option<Rc<str>> {
+        None
+    }
+
     /// Return all this lexer's remaining lexemes or a `LexError` if there was a problem when lexing.
     fn all_lexemes(&mut self) -> Result<Vec<Lexeme<StorageT>>, LexError> {
         let mut lxs = Vec::new();
@@ -82,3 +121,19 @@ impl<StorageT: Copy> Lexeme<StorageT> {
         self.len == 0
     }
 }
+
+impl<StorageT: PrimInt> Lexeme<StorageT> {
+    /// Create an error lexeme, which covers input that could not be lexed. Error lexemes have the
+    /// token ID `StorageT::max_value()` which, since a grammar's token IDs are always less than
+    /// the number of tokens, never clashes with a real token. The parser treats error lexemes as
+    /// a syntax error from which its recoverers must recover (in practice, by deleting the error
+    /// lexeme).
+    pub fn new_error(start: usize, len: usize) -> Self {
+        Lexeme::new(StorageT::max_value(), start, len)
+    }
+
+    /// Is this an error lexeme?
+    pub fn is_error(&self) -> bool {
+        self.tok_id == StorageT::max_value()
+    }
+}
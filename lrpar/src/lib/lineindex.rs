@@ -0,0 +1,224 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Map byte offsets in an input to line and column numbers.
+//!
+//! A `LineIndex` records the start of every line in an input once, after which each query is a
+//! binary search. It is independent of any `Lexer`: it can be built for any string, and can answer
+//! queries about offsets which a lexer has not yet reached.
+
+/// How a `LineIndex` counts columns. All columns are 1-based.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnKind {
+    /// Columns count bytes.
+    Byte,
+    /// Columns count Unicode scalar values (i.e. Rust `char`s). A tab advances the column to the
+    /// next tab stop (see [`LineIndex::tab_width`]).
+    Char,
+    /// Columns count UTF-16 code units, as used by, for example, the Language Server Protocol.
+    Utf16
+}
+
+/// An index of the lines in an input string. Lines are separated by `\n`; a `\r` preceding a
+/// `\n` is treated as the last character of its line.
+#[derive(Debug)]
+pub struct LineIndex<'a> {
+    s: &'a str,
+    /// The byte offset of the start of each line. The first element is always 0.
+    line_starts: Vec<usize>,
+    tab_width: usize
+}
+
+impl<'a> LineIndex<'a> {
+    /// Build a line index for `s`. By default, a tab counts as a single character column.
+    pub fn new(s: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            s.bytes()
+                .enumerate()
+                .filter(|&(_, b)| b == b'\n')
+                .map(|(i, _)| i + 1)
+        );
+        LineIndex {
+            s,
+            line_starts,
+            tab_width: 1
+        }
+    }
+
+    /// Set the distance between tab stops when counting `ColumnKind::Char` columns. A tab moves
+    /// the column on to the next multiple of `tab_width` (plus one, since columns are 1-based).
+    /// Panics if `tab_width` is 0.
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        assert!(tab_width > 0);
+        self.tab_width = tab_width;
+        self
+    }
+
+    /// The input this index was built for.
+    pub fn input(&self) -> &'a str {
+        self.s
+    }
+
+    /// The number of lines in the input. An empty input, or one ending in a newline, has an
+    /// (empty) final line.
+    pub fn num_lines(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Return the 1-based line number containing byte offset `off`, or `Err` if `off` is greater
+    /// than the length of the input. An offset equal to the length of the input is considered to
+    /// be on the last line.
+    pub fn line(&self, off: usize) -> Result<usize, ()> {
+        if off > self.s.len() {
+            return Err(());
+        }
+        Ok(match self.line_starts.binary_search(&off) {
+            Ok(i) => i + 1,
+            Err(i) => i
+        })
+    }
+
+    /// Return the byte range of the 1-based line `line`, excluding its terminating newline, or
+    /// `None` if there is no such line.
+    pub fn line_span(&self, line: usize) -> Option<(usize, usize)> {
+        if line == 0 || line > self.line_starts.len() {
+            return None;
+        }
+        let start = self.line_starts[line - 1];
+        let end = match self.line_starts.get(line) {
+            Some(&e) => e - 1,
+            None => self.s.len()
+        };
+        Some((start, end))
+    }
+
+    /// Return the 1-based line and byte column of byte offset `off`, or `Err` if `off` is greater
+    /// than the length of the input.
+    pub fn line_and_col(&self, off: usize) -> Result<(usize, usize), ()> {
+        self.line_and_col_kind(off, ColumnKind::Byte)
+    }
+
+    /// Return the 1-based line and column of byte offset `off`, with the column counted as
+    /// specified by `kind`. Returns `Err` if `off` is greater than the length of the input or, for
+    /// `ColumnKind::Char` and `ColumnKind::Utf16`, if `off` is not on a character boundary.
+    pub fn line_and_col_kind(&self, off: usize, kind: ColumnKind) -> Result<(usize, usize), ()> {
+        let line = self.line(off)?;
+        let line_start = self.line_starts[line - 1];
+        let col = match kind {
+            ColumnKind::Byte => off - line_start,
+            ColumnKind::Char => {
+                if !self.s.is_char_boundary(off) {
+                    return Err(());
+                }
+                let mut col = 0;
+                for c in self.s[line_start..off].chars() {
+                    if c == '\t' {
+                        col = (col / self.tab_width + 1) * self.tab_width;
+                    } else {
+                        col += 1;
+                    }
+                }
+                col
+            }
+            ColumnKind::Utf16 => {
+                if !self.s.is_char_boundary(off) {
+                    return Err(());
+                }
+                self.s[line_start..off].chars().map(|c| c.len_utf16()).sum()
+            }
+        };
+        Ok((line, col + 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let li = LineIndex::new("");
+        assert_eq!(li.num_lines(), 1);
+        assert_eq!(li.line(0), Ok(1));
+        assert_eq!(li.line(1), Err(()));
+        assert_eq!(li.line_span(1), Some((0, 0)));
+        assert_eq!(li.line_span(2), None);
+
+        let li = LineIndex::new("ab\n\ncd\r\ne\n");
+        assert_eq!(li.num_lines(), 5);
+        let lines = (0..11).map(|i| li.line(i).unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 1, 1, 2, 3, 3, 3, 3, 4, 4, 5]);
+        assert_eq!(li.line(11), Err(()));
+        assert_eq!(li.line_span(0), None);
+        assert_eq!(li.line_span(1), Some((0, 2)));
+        assert_eq!(li.line_span(2), Some((3, 3)));
+        assert_eq!(li.line_span(3), Some((4, 7)));
+        assert_eq!(li.line_span(4), Some((8, 9)));
+        assert_eq!(li.line_span(5), Some((10, 10)));
+        assert_eq!(li.line_and_col(5), Ok((3, 2)));
+        assert_eq!(li.line_and_col(10), Ok((5, 1)));
+    }
+
+    #[test]
+    fn test_columns() {
+        // 'é' is 2 bytes in UTF-8 and 1 unit in UTF-16; '😀' is 4 bytes in UTF-8 and 2 units in
+        // UTF-16.
+        let s = "x\n\u{e9}\u{1F600}y";
+        let li = LineIndex::new(s);
+        let y = s.find('y').unwrap();
+        assert_eq!(li.line_and_col_kind(y, ColumnKind::Byte), Ok((2, 7)));
+        assert_eq!(li.line_and_col_kind(y, ColumnKind::Char), Ok((2, 3)));
+        assert_eq!(li.line_and_col_kind(y, ColumnKind::Utf16), Ok((2, 4)));
+        assert_eq!(li.line_and_col_kind(3, ColumnKind::Byte), Ok((2, 2)));
+        assert_eq!(li.line_and_col_kind(3, ColumnKind::Char), Err(()));
+        assert_eq!(li.line_and_col_kind(3, ColumnKind::Utf16), Err(()));
+    }
+
+    #[test]
+    fn test_tabs() {
+        let s = "\ta\t\tb\n ab\tc";
+        let li = LineIndex::new(s);
+        assert_eq!(li.line_and_col_kind(1, ColumnKind::Char), Ok((1, 2)));
+        assert_eq!(li.line_and_col_kind(4, ColumnKind::Char), Ok((1, 5)));
+        let li = LineIndex::new(s).tab_width(4);
+        assert_eq!(li.line_and_col_kind(1, ColumnKind::Char), Ok((1, 5)));
+        assert_eq!(li.line_and_col_kind(2, ColumnKind::Char), Ok((1, 6)));
+        assert_eq!(li.line_and_col_kind(3, ColumnKind::Char), Ok((1, 9)));
+        assert_eq!(li.line_and_col_kind(4, ColumnKind::Char), Ok((1, 13)));
+        assert_eq!(li.line_and_col_kind(10, ColumnKind::Char), Ok((2, 5)));
+        assert_eq!(li.line_and_col_kind(11, ColumnKind::Char), Ok((2, 6)));
+        // Tabs don't affect byte or UTF-16 columns.
+        assert_eq!(li.line_and_col_kind(4, ColumnKind::Byte), Ok((1, 5)));
+        assert_eq!(li.line_and_col_kind(4, ColumnKind::Utf16), Ok((1, 5)));
+    }
+}
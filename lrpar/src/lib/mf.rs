@@ -270,7 +270,9 @@ where
     fn insert(&self, n: &PathFNode<StorageT>, nbrs: &mut Vec<(u16, u16, PathFNode<StorageT>)>) {
         let top_pstack = *n.pstack.val().unwrap();
         for tidx in self.parser.stable.state_shifts(top_pstack) {
-            if tidx == self.parser.grm.eof_token_idx() {
+            if tidx == self.parser.grm.eof_token_idx()
+                || Some(tidx) == self.parser.grm.error_token_idx()
+            {
                 continue;
             }
 
@@ -690,7 +692,12 @@ where
 
         let mut table = Vec::new();
         table.resize(states_len * tokens_len, u16::max_value());
-        table[usize::from(stable.final_state) * tokens_len + usize::from(grm.eof_token_idx())] = 0;
+        // Each of the grammar's start rules has its own accepting state.
+        for stidx in sgraph.iter_stidxs() {
+            if stable.action(stidx, grm.eof_token_idx()) == Action::Accept {
+                table[usize::from(stidx) * tokens_len + usize::from(grm.eof_token_idx())] = 0;
+            }
+        }
         loop {
             let mut chgd = false;
             for stidx in sgraph.iter_stidxs() {
@@ -769,6 +776,10 @@ where
     }
 
     pub(crate) fn dist(&self, stidx: StIdx, tidx: TIdx<StorageT>) -> u16 {
+        if tidx >= self.tokens_len {
+            // Tokens which aren't part of the grammar (i.e. error lexemes) can never be reached.
+            return u16::max_value();
+        }
         let d = self
             .table
             .get(usize::from(stidx) * usize::from(self.tokens_len) + usize::from(tidx))
@@ -45,17 +45,27 @@ extern crate serde;
 extern crate typename;
 extern crate vob;
 
+pub mod adaptors;
+pub use adaptors::LexerExt;
 mod astar;
+pub mod complete;
+pub use complete::Completions;
 mod cpctplus;
 pub mod ctbuilder;
+pub mod fix;
+pub use fix::{apply_edits, RepairApplier, Spacing, TextEdit};
+pub mod iterlexer;
+pub use iterlexer::{AsTokenRef, IterLexer, TokenRef};
 pub mod lex;
 pub use lex::{LexError, Lexeme, Lexer};
+pub mod lineindex;
+pub use lineindex::{ColumnKind, LineIndex};
 mod panic;
 pub mod parser;
 pub use parser::{LexParseError, Node, ParseError, ParseRepair, RTParserBuilder, RecoveryKind};
 mod mf;
 
-pub use ctbuilder::{ActionKind, CTParserBuilder};
+pub use ctbuilder::{ActionKind, BuildErrors, CTParserBuilder};
 
 /// A convenience macro for including statically compiled `.y` files. A file `src/x.y` which is
 /// statically compiled by lrpar can then be used in a crate with `lrpar_mod!(x)`.
@@ -67,4 +77,4 @@ macro_rules! lrpar_mod {
 }
 
 #[doc(hidden)]
-pub use cfgrammar::RIdx;
+pub use cfgrammar::{RIdx, TIdx};
@@ -30,13 +30,15 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::{fmt::Debug, hash::Hash, time::Instant};
+use std::{cmp, fmt::Debug, hash::Hash, time::Instant};
 
 use lrtable::{Action, StIdx};
 use num_traits::{AsPrimitive, PrimInt, Unsigned};
 
 use parser::{Node, ParseRepair, Parser, Recoverer};
 
+const PARSE_AT_LEAST: usize = 3; // N in Corchuelo et al.
+
 struct Panic;
 
 pub(crate) fn recoverer<'a, StorageT: 'static + Debug + Hash + PrimInt + Unsigned>(
@@ -60,8 +62,12 @@ where
         parser: &Parser<StorageT>,
         in_laidx: usize,
         in_pstack: &mut Vec<StIdx>,
-        _: &mut Vec<Node<StorageT>>
+        in_tstack: &mut Vec<Node<StorageT>>
     ) -> (usize, Vec<Vec<ParseRepair<StorageT>>>) {
+        if !parser.sync_tokens.is_empty() {
+            return self.recover_sync(finish_by, parser, in_laidx, in_pstack, in_tstack);
+        }
+
         // This recoverer is based on that in Compiler Design in C by Allen I. Holub p.348.
         //
         // It doesn't really fit into our recoverer mould very well: it can't always flesh out a
@@ -94,3 +100,157 @@ where
         (in_laidx, vec![])
     }
 }
+
+impl Panic {
+    /// Recover using the parser's synchronisation tokens: skip input up to the next
+    /// synchronisation token and then pop the stack to a state that can continue parsing from
+    /// that token or, failing that, from the lexeme following it. This is the approach commonly
+    /// used by hand-written recursive descent parsers.
+    fn recover_sync<StorageT: 'static + Debug + Hash + PrimInt + Unsigned>(
+        &self,
+        finish_by: Instant,
+        parser: &Parser<StorageT>,
+        in_laidx: usize,
+        in_pstack: &mut Vec<StIdx>,
+        in_tstack: &mut Vec<Node<StorageT>>
+    ) -> (usize, Vec<Vec<ParseRepair<StorageT>>>)
+    where
+        usize: AsPrimitive<StorageT>,
+        u32: AsPrimitive<StorageT>
+    {
+        let is_sync = |laidx| {
+            laidx < parser.lexemes.len() && parser.sync_tokens.contains(&parser.next_tidx(laidx))
+        };
+        for laidx in in_laidx..parser.lexemes.len() + 1 {
+            if Instant::now() >= finish_by {
+                break;
+            }
+            if !(is_sync(laidx)
+                || laidx == parser.lexemes.len()
+                || laidx > in_laidx && is_sync(laidx - 1))
+            {
+                continue;
+            }
+            // Find the state which allows us to parse the most lexemes (up to PARSE_AT_LEAST)
+            // from laidx onwards, preferring states nearer the top of the stack.
+            let end_laidx = cmp::min(laidx + PARSE_AT_LEAST, parser.lexemes.len() + 1);
+            let mut best: Option<(usize, usize)> = None; // (stack index, lexemes parsed)
+            for st_i in (0..in_pstack.len()).rev() {
+                let mut pstack = in_pstack[..st_i + 1].to_vec();
+                let new_laidx = parser.lr_upto(None, laidx, end_laidx, &mut pstack, &mut None);
+                let parsed = if new_laidx < end_laidx
+                    && parser
+                        .stable
+                        .action(*pstack.last().unwrap(), parser.next_tidx(new_laidx))
+                        == Action::Accept
+                {
+                    PARSE_AT_LEAST
+                } else {
+                    new_laidx - laidx
+                };
+                if parsed > best.map_or(0, |(_, p)| p) {
+                    best = Some((st_i, parsed));
+                    if parsed == PARSE_AT_LEAST {
+                        break;
+                    }
+                }
+            }
+            if let Some((st_i, _)) = best {
+                let rprs = (in_laidx..laidx)
+                    .map(|j| ParseRepair::Delete(parser.next_lexeme(j)))
+                    .collect();
+                in_pstack.truncate(st_i + 1);
+                in_tstack.truncate(st_i);
+                return (laidx, vec![rprs]);
+            }
+        }
+        (in_laidx, vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_traits::ToPrimitive;
+
+    use lex::Lexeme;
+    use parser::{test::do_parse, ParseRepair, RecoveryKind};
+
+    const LEXS: &str = "= '='
+                        \\+ '+'
+                        ; ';'
+                        [a-z] 'ID'";
+
+    #[test]
+    fn sync_tokens() {
+        let grms = "%start Stmts
+%sync ';'
+%%
+Stmts: Stmts Stmt | Stmt;
+Stmt: 'ID' '=' Expr ';';
+Expr: Expr '+' 'ID' | 'ID';
+";
+
+        let us = "a=b=c+d;e=f;";
+        let (grm, pr) = do_parse(RecoveryKind::Panic, LEXS, grms, us);
+        let (pt, errs) = pr.unwrap_err();
+        assert_eq!(
+            pt.unwrap().pp(&grm, us),
+            "Stmts
+ Stmts
+  Stmt
+   ID a
+   = =
+   Expr
+    ID b
+   ; ;
+ Stmt
+  ID e
+  = =
+  Expr
+   ID f
+  ; ;
+"
+        );
+        assert_eq!(errs.len(), 1);
+        let tok_id = |n| usize::from(grm.token_idx(n).unwrap()).to_u16().unwrap();
+        assert_eq!(
+            errs[0].repairs(),
+            &vec![vec![
+                ParseRepair::Delete(Lexeme::new(tok_id("="), 3, 1)),
+                ParseRepair::Delete(Lexeme::new(tok_id("ID"), 4, 1)),
+                ParseRepair::Delete(Lexeme::new(tok_id("+"), 5, 1)),
+                ParseRepair::Delete(Lexeme::new(tok_id("ID"), 6, 1)),
+            ]]
+        );
+
+        // No state can shift the ';', so parsing continues after it.
+        let us = "a=+;e=f;";
+        let (_, pr) = do_parse(RecoveryKind::Panic, LEXS, grms, us);
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 1);
+        assert_eq!(
+            errs[0].repairs(),
+            &vec![vec![
+                ParseRepair::Delete(Lexeme::new(tok_id("+"), 2, 1)),
+                ParseRepair::Delete(Lexeme::new(tok_id(";"), 3, 1)),
+            ]]
+        );
+    }
+
+    #[test]
+    fn no_sync_tokens() {
+        // Without %sync, panic mode resumes at the first lexeme that any state on the stack can
+        // handle, so the second '=' is treated as if "a" had been followed by it.
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | Stmt;
+Stmt: 'ID' '=' Expr ';';
+Expr: Expr '+' 'ID' | 'ID';
+";
+        let (_, pr) = do_parse(RecoveryKind::Panic, LEXS, grms, "a=b=c+d;e=f;");
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].repairs(), &vec![vec![]]);
+    }
+}
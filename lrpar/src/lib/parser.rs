@@ -31,29 +31,38 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::{
+    cell::Cell,
     error::Error,
     fmt::{self, Debug, Display},
     hash::Hash,
     marker::PhantomData,
+    rc::Rc,
     time::{Duration, Instant}
 };
 
 use cactus::Cactus;
 use cfgrammar::{yacc::YaccGrammar, RIdx, TIdx};
 use lrtable::{Action, StIdx, StIdxStorageT, StateGraph, StateTable};
-use num_traits::{AsPrimitive, PrimInt, Unsigned, Zero};
+use num_traits::{AsPrimitive, PrimInt, Unsigned};
 
+use complete::{self, Completions};
 use cpctplus;
 use lex::{LexError, Lexeme, Lexer};
 use mf;
 use panic;
 
 const RECOVERY_TIME_BUDGET: u64 = 500; // milliseconds
+/// After recovering using an error production, the number of lexemes which must be shifted before
+/// new errors are reported (the same as Yacc).
+const ERROR_SHIFTS: u8 = 3;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Node<StorageT> {
     Term {
-        lexeme: Lexeme<StorageT>
+        lexeme: Lexeme<StorageT>,
+        /// The lexeme's text, if the lexer couldn't guarantee to return it later (see
+        /// [`Lexer::owned_lexeme_str`](../trait.Lexer.html#method.owned_lexeme_str)).
+        text: Option<Rc<str>>
     },
     Nonterm {
         ridx: RIdx<StorageT>,
@@ -75,10 +84,18 @@ where
                 s.push_str(" ");
             }
             match *e {
-                Node::Term { lexeme } => {
+                Node::Term { lexeme, ref text } => {
                     let tidx = TIdx(lexeme.tok_id());
-                    let tn = grm.token_name(tidx).unwrap();
-                    let lt = &input[lexeme.start()..lexeme.start() + lexeme.len()];
+                    let tn = if Some(tidx) == grm.error_token_idx() {
+                        "error"
+                    } else {
+                        grm.token_name(tidx).unwrap()
+                    };
+                    let lt = match *text {
+                        Some(ref t) => &**t,
+                        None if lexeme.is_empty() => "",
+                        None => &input[lexeme.start()..lexeme.start() + lexeme.len()]
+                    };
                     s.push_str(&format!("{} {}\n", tn, lt));
                 }
                 Node::Nonterm { ridx, ref nodes } => {
@@ -95,19 +112,29 @@ where
 
 pub(crate) type PStack = Vec<StIdx>; // Parse stack
 pub(crate) type TStack<StorageT> = Vec<Node<StorageT>>; // Parse tree stack
+/// The owned text (if any) of each lexeme, which the parser takes as it shifts lexemes (see
+/// `Lexer::owned_lexeme_str`). This may be shorter than the lexemes it describes.
+pub(crate) type Texts = Vec<Cell<Option<Rc<str>>>>;
 
+/// A value on the stack passed to actions: either the result of an action or a lexeme (along
+/// with its text, if the lexer couldn't guarantee that `Lexer::lexeme_str` can return it).
 pub enum AStackType<ActionT, StorageT> {
     ActionType(ActionT),
-    Lexeme(Lexeme<StorageT>)
+    Lexeme(Lexeme<StorageT>, Option<Rc<str>>)
 }
 
 pub struct Parser<'a, StorageT: 'a + Eq + Hash> {
     pub rcvry_kind: RecoveryKind,
     pub grm: &'a YaccGrammar<StorageT>,
     pub token_cost: &'a Fn(TIdx<StorageT>) -> u8,
+    pub sync_tokens: &'a [TIdx<StorageT>],
+    pub max_errors: Option<usize>,
+    pub max_repairs: Option<usize>,
+    pub suppress_within: usize,
     pub sgraph: &'a StateGraph<StorageT>,
     pub stable: &'a StateTable<StorageT>,
-    pub lexemes: &'a [Lexeme<StorageT>]
+    pub lexemes: &'a [Lexeme<StorageT>],
+    pub texts: &'a [Cell<Option<Rc<str>>>]
 }
 
 impl<'a, StorageT: 'static + Debug + Hash + PrimInt + Unsigned> Parser<'a, StorageT>
@@ -119,78 +146,152 @@ where
         rcvry_kind: RecoveryKind,
         grm: &YaccGrammar<StorageT>,
         token_cost: F,
+        sync_tokens: &[TIdx<StorageT>],
+        max_errors: Option<usize>,
+        max_repairs: Option<usize>,
+        suppress_within: usize,
         sgraph: &StateGraph<StorageT>,
         stable: &StateTable<StorageT>,
-        lexemes: &[Lexeme<StorageT>]
-    ) -> Result<Node<StorageT>, (Option<Node<StorageT>>, Vec<ParseError<StorageT>>)>
+        start_stidx: StIdx,
+        lexemes: &[Lexeme<StorageT>],
+        texts: &[Cell<Option<Rc<str>>>]
+    ) -> Result<Node<StorageT>, LexParseError<StorageT>>
     where
         F: Fn(TIdx<StorageT>) -> u8
     {
-        for tidx in grm.iter_tidxs() {
-            assert!(token_cost(tidx) > 0);
-        }
+        let token_cost = Parser::checked_token_cost(grm, token_cost);
         let psr = Parser {
             rcvry_kind,
             grm,
             token_cost: &token_cost,
+            sync_tokens,
+            max_errors,
+            max_repairs,
+            suppress_within,
             sgraph,
             stable,
-            lexemes
+            lexemes,
+            texts
         };
-        let mut pstack = vec![StIdx::from(StIdxStorageT::zero())];
+        let mut pstack = vec![start_stidx];
         let mut tstack: Vec<Node<StorageT>> = Vec::new();
         let mut errors: Vec<ParseError<StorageT>> = Vec::new();
-        let accpt = psr.lr::<u64>(0, &mut pstack, &mut tstack, &mut errors, None);
+        let mut truncated = false;
+        let mut suppressed = false;
+        let accpt = psr.lr::<u64>(
+            0,
+            &mut pstack,
+            &mut tstack,
+            &mut errors,
+            &mut truncated,
+            &mut suppressed,
+            None
+        );
         match (accpt, errors.is_empty()) {
             (true, true) => Ok(tstack.drain(..).nth(0).unwrap()),
-            (true, false) => Err((Some(tstack.drain(..).nth(0).unwrap()), errors)),
-            (false, false) => Err((None, errors)),
+            (true, false) => Err(LexParseError::ParseError {
+                tree: Some(tstack.drain(..).nth(0).unwrap()),
+                errors,
+                truncated,
+                suppressed
+            }),
+            (false, false) => Err(LexParseError::ParseError {
+                tree: None,
+                errors,
+                truncated,
+                suppressed
+            }),
             (false, true) => panic!("Internal error")
         }
     }
 
+    /// Check that `token_cost` gives every token in `grm` a cost greater than 0, and return a
+    /// function which gives the same costs, and a cost of 1 to error lexemes (which aren't part
+    /// of the grammar, so `token_cost` can't be expected to know about them).
+    fn checked_token_cost<F>(
+        grm: &YaccGrammar<StorageT>,
+        token_cost: F
+    ) -> impl Fn(TIdx<StorageT>) -> u8
+    where
+        F: Fn(TIdx<StorageT>) -> u8
+    {
+        for tidx in grm.iter_tidxs() {
+            assert!(token_cost(tidx) > 0);
+        }
+        move |tidx| {
+            if tidx.0 == StorageT::max_value() {
+                1
+            } else {
+                token_cost(tidx)
+            }
+        }
+    }
+
     fn parse2<F, ActionT>(
         rcvry_kind: RecoveryKind,
         grm: &YaccGrammar<StorageT>,
         token_cost: F,
+        sync_tokens: &[TIdx<StorageT>],
+        max_errors: Option<usize>,
+        max_repairs: Option<usize>,
+        suppress_within: usize,
         sgraph: &StateGraph<StorageT>,
         stable: &StateTable<StorageT>,
+        start_stidx: StIdx,
         lexemes: &[Lexeme<StorageT>],
-        actions: &[Option<&Fn(&str, &[AStackType<ActionT, StorageT>]) -> ActionT>],
-        input: &str
-    ) -> Result<ActionT, (Option<Node<StorageT>>, Vec<ParseError<StorageT>>)>
+        texts: &[Cell<Option<Rc<str>>>],
+        actions: &[Option<&Fn(&Lexer<StorageT>, &[AStackType<ActionT, StorageT>]) -> ActionT>],
+        lexer: &Lexer<StorageT>
+    ) -> Result<ActionT, LexParseError<StorageT>>
     where
         F: Fn(TIdx<StorageT>) -> u8
     {
-        for tidx in grm.iter_tidxs() {
-            assert!(token_cost(tidx) > 0);
-        }
+        let token_cost = Parser::checked_token_cost(grm, token_cost);
         let psr = Parser {
             rcvry_kind,
             grm,
             token_cost: &token_cost,
+            sync_tokens,
+            max_errors,
+            max_repairs,
+            suppress_within,
             sgraph,
             stable,
-            lexemes
+            lexemes,
+            texts
         };
-        let mut pstack = vec![StIdx::from(StIdxStorageT::zero())];
+        let mut pstack = vec![start_stidx];
         let mut tstack: Vec<Node<StorageT>> = Vec::new();
         let mut errors: Vec<ParseError<StorageT>> = Vec::new();
         let mut astack: Vec<AStackType<ActionT, StorageT>> = Vec::new();
+        let mut truncated = false;
+        let mut suppressed = false;
         let accpt = psr.lr(
             0,
             &mut pstack,
             &mut tstack,
             &mut errors,
-            Some((&actions, &mut astack, &input))
+            &mut truncated,
+            &mut suppressed,
+            Some((&actions, &mut astack, lexer))
         );
         match (accpt, errors.is_empty()) {
             (true, true) => match astack.drain(..).nth(0).unwrap() {
                 AStackType::ActionType(u) => Ok(u),
-                AStackType::Lexeme(_) => unreachable!()
+                AStackType::Lexeme(..) => unreachable!()
             },
-            (true, false) => Err((Some(tstack.drain(..).nth(0).unwrap()), errors)),
-            (false, false) => Err((None, errors)),
+            (true, false) => Err(LexParseError::ParseError {
+                tree: Some(tstack.drain(..).nth(0).unwrap()),
+                errors,
+                truncated,
+                suppressed
+            }),
+            (false, false) => Err(LexParseError::ParseError {
+                tree: None,
+                errors,
+                truncated,
+                suppressed
+            }),
             (false, true) => panic!("Internal error")
         }
     }
@@ -207,22 +308,33 @@ where
     ///
     /// Return `true` if the parse reached an accept state (i.e. all the input was consumed,
     /// possibly after making repairs) or `false` (i.e. some of the input was not consumed, even
-    /// after possibly making repairs) otherwise.
+    /// after possibly making repairs) otherwise. `truncated` is set to `true` if errors or repair
+    /// sequences were omitted from `errors` because of `max_errors` or `max_repairs`;
+    /// `suppressed` is set to `true` if errors were omitted because of `suppress_within`.
     pub fn lr<ActionT>(
         &self,
         mut laidx: usize,
         pstack: &mut PStack,
         tstack: &mut TStack<StorageT>,
         errors: &mut Vec<ParseError<StorageT>>,
+        truncated: &mut bool,
+        suppressed: &mut bool,
         mut actiondata: Option<(
-            &[Option<&Fn(&str, &[AStackType<ActionT, StorageT>]) -> ActionT>],
+            &[Option<&Fn(&Lexer<StorageT>, &[AStackType<ActionT, StorageT>]) -> ActionT>],
             &mut Vec<AStackType<ActionT, StorageT>>,
-            &str
+            &Lexer<StorageT>
         )>
     ) -> bool {
         let mut recoverer = None;
         let mut recovery_budget = Duration::from_millis(RECOVERY_TIME_BUDGET);
         let mut action_vec: Vec<AStackType<ActionT, StorageT>> = Vec::new();
+        // As in Yacc, after recovering using an error production, new errors aren't reported until
+        // `ERROR_SHIFTS` lexemes have been shifted: this counts the shifts still needed.
+        let mut err_shifts: u8 = 0;
+        // Was the most recent recovery using an error production recorded in `errors`?
+        let mut recorded = false;
+        // The position up to which the most recent repair extended (if there has been one).
+        let mut last_repair_laidx = None;
         loop {
             let stidx = *pstack.last().unwrap();
             let la_tidx = self.next_tidx(laidx);
@@ -239,22 +351,34 @@ where
                     pstack.push(self.stable.goto(prior, ridx).unwrap());
 
                     // Process actions
-                    if let Some((actions, ref mut astack, input)) = actiondata {
+                    if let Some((actions, ref mut astack, lexer)) = actiondata {
                         action_vec.clear();
                         action_vec.extend(astack.drain(pop_idx - 1..));
                         if let Some(f) = actions[usize::from(pidx)] {
-                            astack.push(AStackType::ActionType(f(input, &action_vec)));
+                            astack.push(AStackType::ActionType(f(lexer, &action_vec)));
                         }
                     }
                 }
                 Action::Shift(state_id) => {
                     let la_lexeme = self.next_lexeme(laidx);
-                    tstack.push(Node::Term { lexeme: la_lexeme });
-                    pstack.push(state_id);
+                    let text = self.take_text(laidx);
+                    // When running actions, the text is only needed until the action which
+                    // consumes the lexeme has run, so it isn't kept in the parse tree too.
                     if let Some((_, ref mut astack, _)) = actiondata {
-                        astack.push(AStackType::Lexeme(la_lexeme));
+                        tstack.push(Node::Term {
+                            lexeme: la_lexeme,
+                            text: None
+                        });
+                        astack.push(AStackType::Lexeme(la_lexeme, text));
+                    } else {
+                        tstack.push(Node::Term {
+                            lexeme: la_lexeme,
+                            text
+                        });
                     }
+                    pstack.push(state_id);
                     laidx += 1;
+                    err_shifts = err_shifts.saturating_sub(1);
                 }
                 Action::Accept => {
                     debug_assert_eq!(la_tidx, self.grm.eof_token_idx());
@@ -262,6 +386,69 @@ where
                     return true;
                 }
                 Action::Error => {
+                    if let Some(err_tidx) = self.grm.error_token_idx() {
+                        let la_lexeme = self.next_lexeme(laidx);
+                        if err_shifts == ERROR_SHIFTS {
+                            // We've just recovered using an error production, but the lookahead
+                            // still can't be parsed: discard it (we can't discard EOF though).
+                            if laidx == self.lexemes.len() {
+                                return false;
+                            }
+                            if recorded {
+                                errors.last_mut().unwrap().repairs[0]
+                                    .push(ParseRepair::Delete(la_lexeme));
+                            }
+                            laidx += 1;
+                            last_repair_laidx = Some(laidx);
+                            continue;
+                        }
+                        if err_shifts > 0 {
+                            // We're still recovering from the previous error, so we recover
+                            // again without reporting a new error.
+                            if let Some(err_lexeme) =
+                                self.shift_error_token(err_tidx, laidx, pstack, tstack)
+                            {
+                                if let Some((_, ref mut astack, _)) = actiondata {
+                                    astack.truncate(tstack.len() - 1);
+                                    astack.push(AStackType::Lexeme(err_lexeme, None));
+                                }
+                                err_shifts = ERROR_SHIFTS;
+                                last_repair_laidx = Some(laidx);
+                                continue;
+                            }
+                        }
+                        if self.max_errors.map_or(false, |m| errors.len() >= m) {
+                            *truncated = true;
+                            return false;
+                        }
+                        if let Some(err_lexeme) =
+                            self.shift_error_token(err_tidx, laidx, pstack, tstack)
+                        {
+                            if let Some((_, ref mut astack, _)) = actiondata {
+                                astack.truncate(tstack.len() - 1);
+                                astack.push(AStackType::Lexeme(err_lexeme, None));
+                            }
+                            recorded = !self.suppress(laidx, last_repair_laidx);
+                            if recorded {
+                                errors.push(ParseError {
+                                    stidx,
+                                    lexeme: la_lexeme,
+                                    repairs: vec![vec![]]
+                                });
+                            } else {
+                                *suppressed = true;
+                            }
+                            err_shifts = ERROR_SHIFTS;
+                            last_repair_laidx = Some(laidx);
+                            continue;
+                        }
+                    }
+
+                    if self.max_errors.map_or(false, |m| errors.len() >= m) {
+                        *truncated = true;
+                        return false;
+                    }
+
                     if recoverer.is_none() {
                         recoverer = Some(match self.rcvry_kind {
                             RecoveryKind::CPCTPlus => cpctplus::recoverer(self),
@@ -291,21 +478,73 @@ where
                         .checked_sub(after - before)
                         .unwrap_or_else(|| Duration::new(0, 0));
                     let keep_going = !repairs.is_empty();
-                    let la_lexeme = self.next_lexeme(laidx);
-                    errors.push(ParseError {
-                        stidx,
-                        lexeme: la_lexeme,
-                        repairs
-                    });
+                    if self.suppress(laidx, last_repair_laidx) {
+                        *suppressed = true;
+                    } else {
+                        let mut repairs = repairs;
+                        if let Some(m) = self.max_repairs {
+                            if repairs.len() > m {
+                                repairs.truncate(m);
+                                *truncated = true;
+                            }
+                        }
+                        let la_lexeme = self.next_lexeme(laidx);
+                        errors.push(ParseError {
+                            stidx,
+                            lexeme: la_lexeme,
+                            repairs
+                        });
+                    }
                     if !keep_going {
                         return false;
                     }
                     laidx = new_laidx;
+                    last_repair_laidx = Some(laidx);
                 }
             }
         }
     }
 
+    /// Should an error detected at `laidx` be suppressed, given that the most recent repair (if
+    /// any) extended up to `last_repair_laidx`?
+    fn suppress(&self, laidx: usize, last_repair_laidx: Option<usize>) -> bool {
+        match last_repair_laidx {
+            Some(l) => laidx < l + self.suppress_within,
+            None => false
+        }
+    }
+
+    /// Perform the first stage of Yacc's error recovery: pop `pstack` (and `tstack`) until a state
+    /// which can shift the `error` pseudo-token `err_tidx` is found, and then shift it. On success,
+    /// return the (zero-length) lexeme shifted for the `error` token; if no state on `pstack` can
+    /// shift it, return `None` (in which case `pstack` and `tstack` are left unchanged).
+    fn shift_error_token(
+        &self,
+        err_tidx: TIdx<StorageT>,
+        laidx: usize,
+        pstack: &mut PStack,
+        tstack: &mut TStack<StorageT>
+    ) -> Option<Lexeme<StorageT>> {
+        for i in (0..pstack.len()).rev() {
+            if let Action::Shift(stidx) = self.stable.action(pstack[i], err_tidx) {
+                let err_lexeme = Lexeme::new(
+                    StorageT::from(u32::from(err_tidx)).unwrap(),
+                    self.next_lexeme(laidx).start(),
+                    0
+                );
+                pstack.truncate(i + 1);
+                tstack.truncate(i);
+                pstack.push(stidx);
+                tstack.push(Node::Term {
+                    lexeme: err_lexeme,
+                    text: None
+                });
+                return Some(err_lexeme);
+            }
+        }
+        None
+    }
+
     /// Parse from `laidx` up to (but excluding) `end_laidx` mutating `pstack` as parsing occurs.
     /// Returns the index of the token it parsed up to (by definition <= end_laidx: can be less if
     /// the input is < end_laidx, or if an error is encountered). Does not do any form of error
@@ -344,12 +583,15 @@ where
                 }
                 Action::Shift(state_id) => {
                     if let Some(ref mut tstack_uw) = *tstack {
-                        let la_lexeme = if let Some(l) = lexeme_prefix {
-                            l
+                        let (la_lexeme, text) = if let Some(l) = lexeme_prefix {
+                            (l, None)
                         } else {
-                            self.next_lexeme(laidx)
+                            (self.next_lexeme(laidx), self.take_text(laidx))
                         };
-                        tstack_uw.push(Node::Term { lexeme: la_lexeme });
+                        tstack_uw.push(Node::Term {
+                            lexeme: la_lexeme,
+                            text
+                        });
                     }
                     pstack.push(state_id);
                     laidx += 1;
@@ -390,6 +632,11 @@ where
         }
     }
 
+    /// Take the owned text (if there is any) of the lexeme at `laidx`.
+    fn take_text(&self, laidx: usize) -> Option<Rc<str>> {
+        self.texts.get(laidx).and_then(|t| t.take())
+    }
+
     /// Return the `TIdx` of the next lexeme (if `laidx` == `self.lexemes.len()` this will be the
     /// EOF `TIdx`).
     pub(crate) fn next_tidx(&self, laidx: usize) -> TIdx<StorageT> {
@@ -445,12 +692,15 @@ where
                 }
                 Action::Shift(state_id) => {
                     if let Some(ref mut tstack_uw) = *tstack {
-                        let la_lexeme = if let Some(l) = lexeme_prefix {
-                            l
+                        let (la_lexeme, text) = if let Some(l) = lexeme_prefix {
+                            (l, None)
                         } else {
-                            self.next_lexeme(laidx)
+                            (self.next_lexeme(laidx), self.take_text(laidx))
                         };
-                        tstack_uw.push(Node::Term { lexeme: la_lexeme });
+                        tstack_uw.push(Node::Term {
+                            lexeme: la_lexeme,
+                            text
+                        });
                     }
                     pstack = pstack.child(state_id);
                     laidx += 1;
@@ -493,7 +743,21 @@ pub enum RecoveryKind {
 #[derive(Debug)]
 pub enum LexParseError<StorageT> {
     LexError(LexError),
-    ParseError(Option<Node<StorageT>>, Vec<ParseError<StorageT>>)
+    /// The input could not be parsed without errors.
+    ParseError {
+        /// A parse tree, if the parser was able to recover from all errors.
+        tree: Option<Node<StorageT>>,
+        /// The errors encountered.
+        errors: Vec<ParseError<StorageT>>,
+        /// `true` if parsing stopped early because of
+        /// [`RTParserBuilder::max_errors`](struct.RTParserBuilder.html#method.max_errors), or if
+        /// repair sequences were omitted because of
+        /// [`RTParserBuilder::max_repairs`](struct.RTParserBuilder.html#method.max_repairs).
+        truncated: bool,
+        /// `true` if errors were not recorded because of
+        /// [`RTParserBuilder::suppress_within`](struct.RTParserBuilder.html#method.suppress_within).
+        suppressed: bool
+    }
 }
 
 impl<StorageT: Debug> Error for LexParseError<StorageT> {}
@@ -502,7 +766,7 @@ impl<StorageT: Debug> fmt::Display for LexParseError<StorageT> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             LexParseError::LexError(ref e) => Display::fmt(e, f),
-            LexParseError::ParseError(_, ref e) => e.fmt(f)
+            LexParseError::ParseError { ref errors, .. } => errors.fmt(f)
         }
     }
 }
@@ -517,7 +781,12 @@ impl<StorageT> From<(Option<Node<StorageT>>, Vec<ParseError<StorageT>>)>
     for LexParseError<StorageT>
 {
     fn from(err: (Option<Node<StorageT>>, Vec<ParseError<StorageT>>)) -> LexParseError<StorageT> {
-        LexParseError::ParseError(err.0, err.1)
+        LexParseError::ParseError {
+            tree: err.0,
+            errors: err.1,
+            truncated: false,
+            suppressed: false
+        }
     }
 }
 
@@ -527,6 +796,11 @@ pub struct RTParserBuilder<'a, StorageT: Eq + Hash + 'a> {
     stable: &'a StateTable<StorageT>,
     recoverer: RecoveryKind,
     term_costs: &'a Fn(TIdx<StorageT>) -> u8,
+    sync_tokens: Option<&'a [TIdx<StorageT>]>,
+    max_errors: Option<usize>,
+    max_repairs: Option<usize>,
+    suppress_within: usize,
+    contextual_lexing: bool,
     phantom: PhantomData<StorageT>
 }
 
@@ -546,11 +820,20 @@ where
             stable,
             recoverer: RecoveryKind::MF,
             term_costs: &|_| 1,
+            sync_tokens: None,
+            max_errors: None,
+            max_repairs: None,
+            suppress_within: 0,
+            contextual_lexing: false,
             phantom: PhantomData
         }
     }
 
-    /// Set the recoverer for this parser to `rk`.
+    /// Set the recoverer for this parser to `rk`. If the grammar contains Yacc-style error
+    /// productions (i.e. productions referencing the `error` pseudo-token), they are always tried
+    /// first when an error is encountered: `rk` is only used if no state on the parse stack can
+    /// shift the `error` token. Passing `RecoveryKind::None` thus means that only error productions
+    /// are used to recover from errors.
     pub fn recoverer(mut self, rk: RecoveryKind) -> Self {
         self.recoverer = rk;
         self
@@ -561,42 +844,262 @@ where
         self
     }
 
+    /// Set the synchronisation tokens used by `RecoveryKind::Panic` to `toks`, overriding those
+    /// declared by the grammar's `%sync` declaration (if any).
+    pub fn sync_tokens(mut self, toks: &'a [TIdx<StorageT>]) -> Self {
+        self.sync_tokens = Some(toks);
+        self
+    }
+
+    /// Stop parsing once `n` errors have been recorded and another error is encountered. `n` must
+    /// be greater than 0.
+    pub fn max_errors(mut self, n: usize) -> Self {
+        assert!(n > 0);
+        self.max_errors = Some(n);
+        self
+    }
+
+    /// Keep at most `n` repair sequences for each error. `n` must be greater than 0. Note that
+    /// the parser always recovers using the first repair sequence, which is thus always kept.
+    pub fn max_repairs(mut self, n: usize) -> Self {
+        assert!(n > 0);
+        self.max_repairs = Some(n);
+        self
+    }
+
+    /// Don't record errors which are detected less than `n` lexemes after the end of the previous
+    /// repair: such errors are often caused by the previous repair having been imperfect. The
+    /// parser still recovers from suppressed errors.
+    pub fn suppress_within(mut self, n: usize) -> Self {
+        self.suppress_within = n;
+        self
+    }
+
+    /// If `b` is true, pull lexemes from the lexer one at a time with `Lexer::next_acceptable`,
+    /// passing the tokens which the parser could accept at that point, rather than lexing the
+    /// input up front with `Lexer::all_lexemes`. Once a syntax error is encountered, the rest of
+    /// the input is lexed with `Lexer::all_lexemes` (since the parser can no longer be sure of
+    /// which tokens are acceptable) before the parser recovers from the error as normal.
+    pub fn contextual_lexing(mut self, b: bool) -> Self {
+        self.contextual_lexing = b;
+        self
+    }
+
+    /// Return the entry state for the start rule `ridx`. Panics if `ridx` is not one of the
+    /// grammar's start rules.
+    fn start_stidx(&self, ridx: RIdx<StorageT>) -> StIdx {
+        match self.grm.start_rules().iter().position(|&x| x == ridx) {
+            // There is one entry state per start rule, so the cast is safe.
+            Some(i) => StIdx::from(i as StIdxStorageT),
+            None => panic!("'{}' is not a start rule", self.grm.rule_name(ridx))
+        }
+    }
+
+    /// Return all of `lexer`'s lexemes (and the owned text of those for which `lexer` provides
+    /// it), using contextual lexing (starting from state `start_stidx`) if it is enabled.
+    fn lex(
+        &self,
+        lexer: &mut Lexer<StorageT>,
+        start_stidx: StIdx
+    ) -> Result<(Vec<Lexeme<StorageT>>, Texts), LexError> {
+        let mut lexemes = Vec::new();
+        let mut texts = Vec::new();
+        let mut contextual = self.contextual_lexing;
+        let mut pstack = vec![start_stidx];
+        let mut acceptable = Vec::new();
+        loop {
+            let r = if contextual {
+                acceptable.clear();
+                acceptable.extend(
+                    self.stable
+                        .state_actions(*pstack.last().unwrap())
+                        .map(|tidx| tidx.0)
+                );
+                lexer.next_acceptable(&acceptable)
+            } else {
+                lexer.next()
+            };
+            match r {
+                Some(Ok(l)) => {
+                    if let Some(t) = lexer.owned_lexeme_str(&l) {
+                        while texts.len() < lexemes.len() {
+                            texts.push(Cell::new(None));
+                        }
+                        texts.push(Cell::new(Some(t)));
+                    }
+                    lexemes.push(l);
+                }
+                Some(Err(e)) => return Err(e),
+                None => return Ok((lexemes, texts))
+            }
+            if !contextual {
+                continue;
+            }
+            // Shift the new lexeme (performing any reductions necessary beforehand) so that the
+            // top of `pstack` is the state in which the next lexeme will be parsed.
+            let psr = Parser {
+                rcvry_kind: RecoveryKind::None,
+                grm: self.grm,
+                token_cost: self.term_costs,
+                sync_tokens: &[],
+                max_errors: None,
+                max_repairs: None,
+                suppress_within: 0,
+                sgraph: self.sgraph,
+                stable: self.stable,
+                lexemes: &lexemes,
+                texts: &[]
+            };
+            let laidx = lexemes.len() - 1;
+            if psr.lr_upto(None, laidx, laidx + 1, &mut pstack, &mut None) == laidx {
+                // The lexeme can't be parsed, so the rest of the input is lexed normally.
+                contextual = false;
+            }
+        }
+    }
+
     /// Parse input. On success return a parse tree. On failure, return a `LexParseError`: a
     /// `LexError` means that no parse tree was produced; a `ParseError` may (if its first element
-    /// is `Some(...)`) return a parse tree (with parts filled in by this builder's recoverer).
+    /// is `Some(...)`) return a parse tree (with parts filled in by this builder's recoverer), and
+    /// records whether any errors were omitted because of this builder's error limits.
     pub fn parse(
         &self,
         lexer: &mut Lexer<StorageT>
     ) -> Result<Node<StorageT>, LexParseError<StorageT>> {
-        Ok(Parser::parse(
+        self.parse_rule(self.grm.start_rules()[0], lexer)
+    }
+
+    /// As [`parse`](#method.parse), but parse input as the start rule `ridx` (i.e. one of the
+    /// rules named in the grammar's `%start` declaration) rather than the first start rule.
+    /// Panics if `ridx` is not a start rule.
+    pub fn parse_rule(
+        &self,
+        ridx: RIdx<StorageT>,
+        lexer: &mut Lexer<StorageT>
+    ) -> Result<Node<StorageT>, LexParseError<StorageT>> {
+        let start_stidx = self.start_stidx(ridx);
+        let (lexemes, texts) = self.lex(lexer, start_stidx)?;
+        Parser::parse(
             self.recoverer,
             self.grm,
             self.term_costs,
+            self.sync_tokens.unwrap_or_else(|| self.grm.sync_tokens()),
+            self.max_errors,
+            self.max_repairs,
+            self.suppress_within,
             self.sgraph,
             self.stable,
-            &lexer.all_lexemes()?[..]
-        )?)
+            start_stidx,
+            &lexemes,
+            &texts
+        )
     }
 
     /// Parse input. On success return a parse tree. On failure, return a `LexParseError`: a
     /// `LexError` means that no parse tree was produced; a `ParseError` may (if its first element
-    /// is `Some(...)`) return a parse tree (with parts filled in by this builder's recoverer).
+    /// is `Some(...)`) return a parse tree (with parts filled in by this builder's recoverer), and
+    /// records whether any errors were omitted because of this builder's error limits.
+    ///
+    /// Each action is passed `lexer` and the values of the production's symbols. The text of a
+    /// lexeme is the `Rc<str>` in its `AStackType::Lexeme` if there is one (see
+    /// `Lexer::owned_lexeme_str`), and can otherwise be obtained with `Lexer::lexeme_str`.
     pub fn parse2<ActionT>(
         &self,
         lexer: &mut Lexer<StorageT>,
-        actions: &[Option<&Fn(&str, &[AStackType<ActionT, StorageT>]) -> ActionT>],
-        input: &str
+        actions: &[Option<&Fn(&Lexer<StorageT>, &[AStackType<ActionT, StorageT>]) -> ActionT>]
     ) -> Result<ActionT, LexParseError<StorageT>> {
-        Ok(Parser::parse2(
+        self.parse2_rule(self.grm.start_rules()[0], lexer, actions)
+    }
+
+    /// As [`parse2`](#method.parse2), but parse input as the start rule `ridx` (i.e. one of the
+    /// rules named in the grammar's `%start` declaration) rather than the first start rule.
+    /// Panics if `ridx` is not a start rule.
+    pub fn parse2_rule<ActionT>(
+        &self,
+        ridx: RIdx<StorageT>,
+        lexer: &mut Lexer<StorageT>,
+        actions: &[Option<&Fn(&Lexer<StorageT>, &[AStackType<ActionT, StorageT>]) -> ActionT>]
+    ) -> Result<ActionT, LexParseError<StorageT>> {
+        let start_stidx = self.start_stidx(ridx);
+        let (lexemes, texts) = self.lex(lexer, start_stidx)?;
+        Parser::parse2(
             self.recoverer,
             self.grm,
             self.term_costs,
+            self.sync_tokens.unwrap_or_else(|| self.grm.sync_tokens()),
+            self.max_errors,
+            self.max_repairs,
+            self.suppress_within,
             self.sgraph,
             self.stable,
-            &lexer.all_lexemes()?[..],
+            start_stidx,
+            &lexemes,
+            &texts,
             actions,
-            input
-        )?)
+            lexer
+        )
+    }
+
+    /// Calculate what could be completed at byte offset `off` in the input: the valid next tokens,
+    /// the rules being parsed, and the lexeme (if any) the cursor is in the middle, or at the end,
+    /// of (along with what could follow that lexeme if the cursor is at its end). Only the
+    /// input before `off` is considered. Lexemes in that prefix which cause parse errors are
+    /// skipped (and reported in the result) rather than being repaired. A `LexError` is returned
+    /// only if lexing fails before `off`.
+    pub fn complete(
+        &self,
+        lexer: &mut Lexer<StorageT>,
+        off: usize
+    ) -> Result<Completions<StorageT>, LexError> {
+        self.complete_rule(self.grm.start_rules()[0], lexer, off)
+    }
+
+    /// As [`complete`](#method.complete), but for input parsed as the start rule `ridx` (i.e. one
+    /// of the rules named in the grammar's `%start` declaration) rather than the first start
+    /// rule. Panics if `ridx` is not a start rule.
+    pub fn complete_rule(
+        &self,
+        ridx: RIdx<StorageT>,
+        lexer: &mut Lexer<StorageT>,
+        off: usize
+    ) -> Result<Completions<StorageT>, LexError> {
+        let start_stidx = self.start_stidx(ridx);
+        let mut lexemes = Vec::new();
+        let mut partial = None;
+        while let Some(r) = lexer.next() {
+            match r {
+                Ok(l) => {
+                    if l.end() <= off {
+                        lexemes.push(l);
+                    } else {
+                        if l.start() < off {
+                            partial = Some(l);
+                        }
+                        break;
+                    }
+                }
+                Err(e) => {
+                    if e.idx < off {
+                        return Err(e);
+                    }
+                    break;
+                }
+            }
+        }
+        let psr = Parser {
+            rcvry_kind: RecoveryKind::None,
+            grm: self.grm,
+            token_cost: self.term_costs,
+            sync_tokens: self.sync_tokens.unwrap_or_else(|| self.grm.sync_tokens()),
+            max_errors: None,
+            max_repairs: None,
+            suppress_within: 0,
+            sgraph: self.sgraph,
+            stable: self.stable,
+            lexemes: &lexemes,
+            texts: &[]
+        };
+        Ok(complete::complete(&psr, start_stidx, off, partial))
     }
 }
 
@@ -682,13 +1185,7 @@ pub(crate) mod test {
     ) {
         let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, grms).unwrap();
         let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
-        let rule_ids = grm
-            .tokens_map()
-            .iter()
-            .map(|(&n, &i)| (n.to_owned(), u32::from(i).to_u16().unwrap()))
-            .collect();
-        let lexer_rules = small_lexer(lexs, rule_ids);
-        let lexemes = small_lex(lexer_rules, input);
+        let lexemes = grm_lex(&grm, lexs, input);
         let mut lexer = SmallLexer { lexemes, i: 0 };
         let costs_tidx = costs
             .iter()
@@ -700,7 +1197,7 @@ pub(crate) mod test {
             .parse(&mut lexer)
         {
             Ok(r) => Ok(r),
-            Err(LexParseError::ParseError(r1, r2)) => Err((r1, r2)),
+            Err(LexParseError::ParseError { tree, errors, .. }) => Err((tree, errors)),
             _ => unreachable!()
         };
         (grm, r)
@@ -715,9 +1212,9 @@ pub(crate) mod test {
     // lrlex as a dependency of lrpar). The format is the same as lrlex *except*:
     //   * The initial "%%" isn't needed, and only "'" is valid as a rule name delimiter.
     //   * "Unnamed" rules aren't allowed (e.g. you can't have a rule which discards whitespaces).
-    struct SmallLexer<StorageT> {
-        lexemes: Vec<Lexeme<StorageT>>,
-        i: usize
+    pub(crate) struct SmallLexer<StorageT> {
+        pub(crate) lexemes: Vec<Lexeme<StorageT>>,
+        pub(crate) i: usize
     }
 
     impl<StorageT: Hash + PrimInt + Unsigned> Lexer<StorageT> for SmallLexer<StorageT> {
@@ -739,7 +1236,7 @@ pub(crate) mod test {
         }
     }
 
-    fn small_lexer(lexs: &str, ids_map: HashMap<String, u16>) -> Vec<(u16, Regex)> {
+    pub(crate) fn small_lexer(lexs: &str, ids_map: HashMap<String, u16>) -> Vec<(u16, Regex)> {
         let mut rules = Vec::new();
         for l in lexs.split("\n").map(|x| x.trim()).filter(|x| !x.is_empty()) {
             assert!(l.rfind("'") == Some(l.len() - 1));
@@ -754,7 +1251,7 @@ pub(crate) mod test {
         rules
     }
 
-    fn small_lex(rules: Vec<(u16, Regex)>, input: &str) -> Vec<Lexeme<u16>> {
+    pub(crate) fn small_lex(rules: Vec<(u16, Regex)>, input: &str) -> Vec<Lexeme<u16>> {
         let mut lexemes = vec![];
         let mut i = 0;
         while i < input.len() {
@@ -776,6 +1273,16 @@ pub(crate) mod test {
         lexemes
     }
 
+    /// Lex `input` with the `small_lexer` rules `lexs`, using `grm`'s token IDs.
+    pub(crate) fn grm_lex(grm: &YaccGrammar<u16>, lexs: &str, input: &str) -> Vec<Lexeme<u16>> {
+        let rule_ids = grm
+            .tokens_map()
+            .iter()
+            .map(|(&n, &i)| (n.to_owned(), u32::from(i).to_u16().unwrap()))
+            .collect();
+        small_lex(small_lexer(lexs, rule_ids), input)
+    }
+
     #[test]
     fn simple_parse() {
         // From p4 of https://www.cs.umd.edu/class/spring2014/cmsc430/lectures/lec07.pdf
@@ -910,4 +1417,373 @@ Call: 'ID' '(' ')';";
         let err_tok_id = usize::from(grm.token_idx("ID").unwrap()).to_u16().unwrap();
         assert_eq!(errs[0].lexeme(), &Lexeme::new(err_tok_id, 2, 1));
     }
+
+    #[test]
+    fn error_productions() {
+        let lexs = "\\( '('
+                    \\) ')'
+                    ; ';'
+                    \\+ '+'
+                    [a-zA-Z_][a-zA-Z_0-9]* 'ID'";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' ';' | '(' Inner ')';
+Inner: 'ID' | 'ID' '+' 'ID' | error;";
+
+        let (grm, pr) = do_parse(RecoveryKind::None, &lexs, &grms, "a;(++)");
+        let (pt, errs) = pr.unwrap_err();
+        assert_eq!(
+            pt.unwrap().pp(&grm, "a;(++)"),
+            "Stmts
+ Stmts
+  Stmts
+  Stmt
+   ID a
+   ; ;
+ Stmt
+  ( (
+  Inner
+   error 
+  ) )
+"
+        );
+        assert_eq!(errs.len(), 1);
+        let plus_tok_id = usize::from(grm.token_idx("+").unwrap()).to_u16().unwrap();
+        assert_eq!(errs[0].lexeme(), &Lexeme::new(plus_tok_id, 3, 1));
+        assert_eq!(
+            errs[0].repairs(),
+            &vec![vec![
+                ParseRepair::Delete(Lexeme::new(plus_tok_id, 3, 1)),
+                ParseRepair::Delete(Lexeme::new(plus_tok_id, 4, 1)),
+            ]]
+        );
+
+        // Each recovery using an error production is reported as a separate error...
+        let (_, pr) = do_parse(RecoveryKind::None, &lexs, &grms, "(+)(a+)");
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 2);
+
+        // ...unless, as in Yacc, fewer than 3 lexemes have been shifted since the previous
+        // recovery, in which case the parser recovers again without reporting a new error.
+        let (grm, pr) = do_parse(RecoveryKind::None, &lexs, &grms, "(+)(+)");
+        let (pt, errs) = pr.unwrap_err();
+        assert_eq!(
+            pt.unwrap().pp(&grm, "(+)(+)"),
+            "Stmts
+ Stmts
+  Stmts
+  Stmt
+   ( (
+   Inner
+    error 
+   ) )
+ Stmt
+  ( (
+  Inner
+   error 
+  ) )
+"
+        );
+        assert_eq!(errs.len(), 1);
+        assert_eq!(
+            errs[0].repairs(),
+            &vec![vec![
+                ParseRepair::Delete(Lexeme::new(plus_tok_id, 1, 1)),
+                ParseRepair::Delete(Lexeme::new(plus_tok_id, 4, 1)),
+            ]]
+        );
+
+        // There is no error production which can be used to recover from an error outside
+        // parentheses, so we either fail or fall back to the normal recoverer.
+        let (_, pr) = do_parse(RecoveryKind::None, &lexs, &grms, "a(a)");
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_none());
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].repairs().is_empty());
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "a(a)(+)");
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 2);
+        let semi_tidx = grm.token_idx(";").unwrap();
+        assert!(errs[0]
+            .repairs()
+            .iter()
+            .any(|rprs| rprs[0] == ParseRepair::Insert(semi_tidx)));
+        assert_eq!(errs[1].repairs().len(), 1);
+    }
+
+    fn do_parse_with_limits<F>(
+        lexs: &str,
+        grms: &str,
+        input: &str,
+        f: F
+    ) -> (Option<Node<u16>>, Vec<ParseError<u16>>, bool, bool)
+    where
+        F: for<'a> Fn(RTParserBuilder<'a, u16>) -> RTParserBuilder<'a, u16>
+    {
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, grms).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let lexemes = grm_lex(&grm, lexs, input);
+        let mut lexer = SmallLexer { lexemes, i: 0 };
+        match f(RTParserBuilder::new(&grm, &sgraph, &stable)).parse(&mut lexer) {
+            Err(LexParseError::ParseError {
+                tree,
+                errors,
+                truncated,
+                suppressed
+            }) => (tree, errors, truncated, suppressed),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn error_lexemes() {
+        let lexs = "[0-9]+ 'INT'
+                    \\+ '+'
+                    \\* '*'";
+        let grms = "%start Expr
+%%
+Expr: Term '+' Expr | Term ;
+Term: 'INT' '*' Term | 'INT' ;";
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, grms).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        // Simulate an error-tolerant lexer lexing "2+3?+4*5*6++7".
+        let mut lexemes = grm_lex(&grm, lexs, "2+3");
+        lexemes.push(Lexeme::new_error(3, 1));
+        lexemes.extend(
+            grm_lex(&grm, lexs, "+4*5*6++7")
+                .iter()
+                .map(|l| Lexeme::new(l.tok_id(), l.start() + 4, l.len()))
+        );
+
+        for &rk in &[
+            RecoveryKind::CPCTPlus,
+            RecoveryKind::MF,
+            RecoveryKind::Panic
+        ] {
+            let mut lexer = SmallLexer {
+                lexemes: lexemes.clone(),
+                i: 0
+            };
+            match RTParserBuilder::new(&grm, &sgraph, &stable)
+                .recoverer(rk)
+                .parse(&mut lexer)
+            {
+                Err(LexParseError::ParseError {
+                    tree: Some(_),
+                    errors: errs,
+                    truncated: false,
+                    ..
+                }) => {
+                    assert_eq!(errs.len(), 2);
+                    assert!(errs[0].lexeme().is_error());
+                    assert!(errs[0]
+                        .repairs()
+                        .iter()
+                        .all(|rprs| rprs.contains(&ParseRepair::Delete(Lexeme::new_error(3, 1)))));
+                    assert_eq!(errs[1].lexeme().start(), 11);
+                }
+                _ => panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn parse_rule() {
+        let lexs = "[a-zA-Z_] 'ID'
+                    \\+ '+'";
+        let grms = "%start E T
+%%
+E: T '+' E
+ | T;
+T: 'ID';
+";
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, grms).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let parse = |rule, input| {
+            let lexemes = grm_lex(&grm, lexs, input);
+            RTParserBuilder::new(&grm, &sgraph, &stable).parse_rule(
+                grm.rule_idx(rule).unwrap(),
+                &mut SmallLexer { lexemes, i: 0 }
+            )
+        };
+
+        assert_eq!(parse("T", "a").unwrap().pp(&grm, "a"), "T\n ID a\n");
+        assert_eq!(
+            parse("E", "a+b").unwrap().pp(&grm, "a+b"),
+            "E\n T\n  ID a\n + +\n E\n  T\n   ID b\n"
+        );
+        // A T can't be followed by anything, so errors are recovered from within T.
+        match parse("T", "a+b") {
+            Err(LexParseError::ParseError {
+                tree: Some(pt),
+                errors: errs,
+                truncated: false,
+                ..
+            }) => {
+                assert_eq!(pt.pp(&grm, "a+b"), "T\n ID a\n");
+                assert_eq!(errs.len(), 1);
+                assert_eq!(errs[0].lexeme().start(), 1);
+            }
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "'T' is not a start rule")]
+    fn parse_rule_not_start() {
+        let grm = YaccGrammar::<u16>::new_with_storaget(
+            YaccKind::Original,
+            "%start E\n%%\nE: T;\nT: 'ID';"
+        )
+        .unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        RTParserBuilder::new(&grm, &sgraph, &stable)
+            .parse_rule(
+                grm.rule_idx("T").unwrap(),
+                &mut SmallLexer {
+                    lexemes: vec![],
+                    i: 0
+                }
+            )
+            .ok();
+    }
+
+    #[test]
+    fn error_limits() {
+        let lexs = "a 'A'
+                    b 'B'
+                    c 'C'";
+        let grms = "%start S
+%%
+S: S 'A' 'B' | 'C';";
+        let input = "cabbababbababbabab";
+
+        let (pt, errs, truncated, suppressed) = do_parse_with_limits(&lexs, &grms, input, |b| b);
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 3);
+        assert!(errs.iter().any(|e| e.repairs().len() > 1));
+        assert!(!truncated);
+        assert!(!suppressed);
+
+        let (pt, errs, truncated, suppressed) =
+            do_parse_with_limits(&lexs, &grms, input, |b| b.max_errors(2));
+        assert!(pt.is_none());
+        assert_eq!(errs.len(), 2);
+        assert!(truncated);
+        assert!(!suppressed);
+
+        let (pt, errs, truncated, suppressed) =
+            do_parse_with_limits(&lexs, &grms, input, |b| b.max_errors(3));
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 3);
+        assert!(!truncated);
+        assert!(!suppressed);
+
+        let (pt, errs, truncated, suppressed) =
+            do_parse_with_limits(&lexs, &grms, input, |b| b.max_repairs(1));
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 3);
+        assert!(errs.iter().all(|e| e.repairs().len() == 1));
+        assert!(truncated);
+        assert!(!suppressed);
+
+        let (pt, errs, truncated, suppressed) =
+            do_parse_with_limits(&lexs, &grms, input, |b| b.suppress_within(100));
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 1);
+        assert!(!truncated);
+        assert!(suppressed);
+    }
+
+    /// A lexer for space-separated words, which treats "where" as a keyword only if the keyword is
+    /// acceptable (or if it isn't lexing contextually).
+    struct KeywordLexer<'a> {
+        s: &'a str,
+        i: usize,
+        ids: [u16; 3]
+    }
+
+    impl<'a> Lexer<u16> for KeywordLexer<'a> {
+        fn next(&mut self) -> Option<Result<Lexeme<u16>, LexError>> {
+            let all = self.ids;
+            self.next_acceptable(&all)
+        }
+
+        fn next_acceptable(&mut self, acceptable: &[u16]) -> Option<Result<Lexeme<u16>, LexError>> {
+            while self.s[self.i..].starts_with(' ') {
+                self.i += 1;
+            }
+            if self.i == self.s.len() {
+                return None;
+            }
+            let len = self.s[self.i..].find(' ').unwrap_or(self.s.len() - self.i);
+            let [id, kw, semi] = self.ids;
+            let tok_id = match &self.s[self.i..self.i + len] {
+                "where" if acceptable.contains(&kw) => kw,
+                ";" => semi,
+                _ => id
+            };
+            let l = Lexeme::new(tok_id, self.i, len);
+            self.i += len;
+            Some(Ok(l))
+        }
+
+        fn line_and_col(&self, _: &Lexeme<u16>) -> Result<(usize, usize), ()> {
+            unreachable!();
+        }
+
+        fn input(&self) -> &str {
+            self.s
+        }
+    }
+
+    #[test]
+    fn contextual_lexing() {
+        let grms = "%start S
+%token ';'
+%%
+S: S 'ID' | 'ID' | S 'WHERE' 'ID';";
+        let grm = YaccGrammar::<u16>::new_with_storaget(YaccKind::Original, grms).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let tok_id = |n| u32::from(grm.token_idx(n).unwrap()).to_u16().unwrap();
+        let ids = [tok_id("ID"), tok_id("WHERE"), tok_id(";")];
+        let parse = |s, contextual| {
+            RTParserBuilder::new(&grm, &sgraph, &stable)
+                .contextual_lexing(contextual)
+                .parse(&mut KeywordLexer { s, i: 0, ids })
+        };
+        let names = |pt: &Node<u16>| {
+            let mut names = Vec::new();
+            let mut todo = vec![pt];
+            while let Some(n) = todo.pop() {
+                match *n {
+                    Node::Term { lexeme, .. } => {
+                        names.push(grm.token_name(TIdx(lexeme.tok_id())).unwrap())
+                    }
+                    Node::Nonterm { ref nodes, .. } => todo.extend(nodes.iter().rev())
+                }
+            }
+            names.join(" ")
+        };
+
+        let s = "where x where where";
+        assert!(parse(s, false).is_err());
+        assert_eq!(names(&parse(s, true).unwrap()), "ID ID WHERE ID");
+        // After a syntax error, the rest of the input is lexed without context.
+        match parse("x ; where y", true) {
+            Err(LexParseError::ParseError {
+                tree: Some(pt),
+                errors: errs,
+                ..
+            }) => {
+                assert_eq!(errs.len(), 1);
+                assert_eq!(names(&pt), "ID WHERE ID");
+            }
+            _ => panic!()
+        }
+    }
 }
@@ -0,0 +1,380 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! This crate provides the `grammar!` macro, which compiles a grammar (and, optionally, a lexer)
+//! written inline in Rust source code into a parser, without the need for a `build.rs`.
+//!
+//! `lrpar` doesn't re-export `grammar!`: this crate uses `lrpar` and `lrlex` (which itself depends
+//! on `lrpar`) to generate the parser, so `lrpar` depending on this crate would create a
+//! dependency cycle. Crates using the macro must therefore depend on `lrpar_macros` (as well as
+//! `lrpar` and, if the macro defines a lexer, `lrlex`) directly.
+
+extern crate bincode;
+extern crate cfgrammar;
+extern crate lrlex;
+extern crate lrpar;
+extern crate lrtable;
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate serde;
+extern crate syn;
+
+mod source;
+
+use std::{collections::HashMap, iter::once};
+
+use bincode::serialize;
+use cfgrammar::yacc::{YaccGrammar, YaccGrammarError, YaccKind};
+use lrlex::{build_lex, LexerBuilder};
+use lrpar::{ctbuilder::_action_arg, ActionKind, CTParserBuilder};
+use lrtable::{from_yacc, Minimiser};
+use proc_macro2::{Delimiter, Group, Ident, Literal, Span, TokenStream, TokenTree};
+use serde::Serialize;
+use syn::Lit;
+
+use source::Source;
+
+/// An error message, and the span it should be reported at.
+type MacroError = (String, Span);
+
+/// Compile a grammar into a parser at compile time. The grammar is written as in a `.y` file,
+/// except that, since it must consist of Rust tokens, multi-character token names must be written
+/// in double quotes (e.g. `"INT"` rather than `'INT'`). The grammar can optionally be preceded by a
+/// lexer, written as `lexer { ... }`, each of whose rules is a regular expression followed either
+/// by a token name or by `;` (for input which should be skipped), both as string literals.
+///
+/// The macro expands to the contents of a module (so it is best placed in a module of its own)
+/// defining what [`CTParserBuilder`](../lrpar/struct.CTParserBuilder.html) would for the same grammar:
+/// `parse`, `ParseResult`, the `R_` constants, and, if the grammar has more than one start rule,
+/// a `parse_X` function for each start rule `X`. If the grammar declares an action type with
+/// `%type`, `CustomAction`s are used (with the grammar's actions and programs section);
+/// otherwise `parse` returns a parse tree. If there is a lexer, the module also contains what
+/// [`LexerParserBuilder`](../lrlex/struct.LexerParserBuilder.html) would generate: `lexerdef`, the
+/// `T_` constants (for token names which are valid identifiers), and a `parse_str` function which
+/// lexes and parses a string. `StorageT` is `u32`.
+///
+/// Errors in the grammar or lexer (including tokens used in the grammar but not defined in the
+/// lexer) are reported as compile errors pointing to the relevant token. Shift/reduce and
+/// reduce/reduce conflicts are not reported.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// mod calc {
+///     grammar! {
+///         lexer {
+///             "[0-9]+" "INT"
+///             "\\+" "+"
+///             "[ \t]+" ;
+///         }
+///         %start Expr
+///         %type u64
+///         %%
+///         Expr: Expr "+" Term { $1 + $3 } | Term { $1 } ;
+///         Term: "INT" { int($1) } ;
+///         %%
+///         fn int(s: &str) -> u64 {
+///             s.parse().unwrap()
+///         }
+///     }
+/// }
+///
+/// assert_eq!(calc::parse_str("1 + 2").unwrap(), 3);
+/// ```
+#[proc_macro]
+pub fn grammar(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    match expand(input.into()) {
+        Ok(ts) => ts.into(),
+        Err(errs) => errs
+            .into_iter()
+            .map(|(msg, span)| quote_spanned!(span=> compile_error!(#msg);))
+            .collect::<TokenStream>()
+            .into()
+    }
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, Vec<MacroError>> {
+    let mut toks = input.into_iter().collect::<Vec<_>>();
+    let lexer = match (toks.first(), toks.get(1)) {
+        (Some(&TokenTree::Ident(ref i)), Some(&TokenTree::Group(ref g)))
+            if i == "lexer" && g.delimiter() == Delimiter::Brace =>
+        {
+            Some(g.clone())
+        }
+        _ => None
+    };
+    if lexer.is_some() {
+        toks.drain(..2);
+    }
+
+    let src = Source::new(toks.into_iter().collect());
+    let grm = match YaccGrammar::<u32>::new_with_all_errors(YaccKind::Eco, src.text()) {
+        Ok(grm) => grm,
+        Err(errs) => {
+            return Err(errs
+                .iter()
+                .map(|e| {
+                    let msg = match *e {
+                        YaccGrammarError::YaccParserError(ref e) => e.kind.to_string(),
+                        YaccGrammarError::GrammarValidationError(ref e) => e.to_string()
+                    };
+                    (msg, src.span(e.line_col()))
+                })
+                .collect());
+        }
+    };
+    let (sgraph, stable) =
+        from_yacc(&grm, Minimiser::Pager).map_err(|e| vec![(e.to_string(), Span::call_site())])?;
+
+    let actionkind = match grm.actiontype() {
+        Some(_) => ActionKind::CustomAction,
+        None => ActionKind::GenericParseTree
+    };
+    let pb = CTParserBuilder::<u32>::new().action_kind(actionkind);
+    // Rather than writing the grammar and tables to files and using `include_bytes!` (as
+    // `CTParserBuilder` does), we embed them as byte strings.
+    let mut outs = pb._gen_parser(&grm, &bytes(&grm), &bytes(&sgraph), &bytes(&stable));
+    if let Some(ref g) = lexer {
+        outs.push_str(&gen_lexer(g, &grm, &src)?);
+    }
+    let mut ts = outs.parse::<TokenStream>().unwrap();
+
+    if grm.actiontype().is_some() {
+        // The actions are made from the tokens the user wrote, so that rustc's messages about
+        // them point to the right place.
+        for pidx in grm.iter_pidxs() {
+            if grm.action(pidx).is_none() {
+                continue;
+            }
+            let action = src.action(grm.action_line_col(pidx).unwrap()).unwrap();
+            let (sig, bindings) = pb._gen_action_preamble(&grm, pidx);
+            let mut body = bindings.parse::<TokenStream>().unwrap();
+            body.extend(replace_dollars(action.stream()));
+            let mut body = Group::new(Delimiter::Brace, body);
+            body.set_span(action.span());
+            ts.extend(sig.parse::<TokenStream>().unwrap());
+            ts.extend(once(TokenTree::Group(body)));
+        }
+        ts.extend(src.programs());
+    }
+    Ok(ts)
+}
+
+/// Check the lexer `g` and generate the code for it which `LexerParserBuilder` would generate
+/// for the equivalent `.l` file.
+fn gen_lexer(g: &Group, grm: &YaccGrammar<u32>, src: &Source) -> Result<String, Vec<MacroError>> {
+    const EXPECTED_NAME: &str = "Expected a token name (as a string literal) or ';'";
+    let mut l_src = "%%\n".to_owned();
+    // The span of each rule's regular expression: rule `i` is on line `i + 2` of `l_src`.
+    let mut rule_spans = Vec::new();
+    let mut toks = g.stream().into_iter();
+    while let Some(tt) = toks.next() {
+        let re = str_lit(&tt).ok_or_else(|| {
+            vec![(
+                "Expected a regular expression (as a string literal)".to_owned(),
+                tt.span()
+            )]
+        })?;
+        // A newline would end the rule early.
+        l_src.push_str(&re.replace('\n', "\\n").replace('\r', "\\r"));
+        match toks.next() {
+            Some(TokenTree::Punct(ref p)) if p.as_char() == ';' => l_src.push_str(" ;\n"),
+            Some(ref name_tt) => match str_lit(name_tt) {
+                Some(name) => l_src.push_str(&format!(" \"{}\"\n", name)),
+                None => return Err(vec![(EXPECTED_NAME.to_owned(), name_tt.span())])
+            },
+            None => return Err(vec![(EXPECTED_NAME.to_owned(), tt.span())])
+        }
+        rule_spans.push(tt.span());
+    }
+    let mut lexerdef = build_lex::<u32>(&l_src).map_err(|e| {
+        // Errors which aren't on a rule's line (e.g. on the `%%` line) relate to the whole lexer.
+        let line = e.line_col().0;
+        let span = if line >= 2 && line - 2 < rule_spans.len() {
+            rule_spans[line - 2]
+        } else {
+            g.span()
+        };
+        vec![(e.kind.to_string(), span)]
+    })?;
+
+    let rule_ids = grm
+        .tokens_map()
+        .iter()
+        .map(|(&n, &i)| (n.to_owned(), i.as_storaget()))
+        .collect::<HashMap<_, _>>();
+    {
+        let owned_map = rule_ids
+            .iter()
+            .map(|(n, &i)| (&**n, i))
+            .collect::<HashMap<_, _>>();
+        if let (Some(missing), _) = lexerdef.set_rule_ids(&owned_map) {
+            let mut missing = missing.into_iter().collect::<Vec<_>>();
+            missing.sort();
+            return Err(missing
+                .iter()
+                .map(|n| {
+                    (
+                        format!("Token '{}' is not defined in the lexer", n),
+                        src.token_span(n)
+                    )
+                })
+                .collect());
+        }
+    }
+    let lb = LexerBuilder::<u32>::new().rule_ids_map(rule_ids);
+    let mut outs = lb
+        ._gen_lexer(&lexerdef)
+        .map_err(|e| vec![(e.to_string(), g.span())])?;
+    // The lexer and parser are both in the module the macro expands into.
+    outs.push_str(&lb._gen_parse_str("self", "self"));
+    Ok(outs)
+}
+
+/// An expression for the serialised form (of type `&[u8]`) of `x`.
+fn bytes<T: Serialize>(x: &T) -> String {
+    Literal::byte_string(&serialize(x).unwrap()).to_string()
+}
+
+/// If `tt` is a string literal, return its value.
+fn str_lit(tt: &TokenTree) -> Option<String> {
+    match *tt {
+        TokenTree::Literal(ref l) => match Lit::new(l.clone()) {
+            Lit::Str(s) => Some(s.value()),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+/// Replace each `$n` in `ts` with the variable which `CTParserBuilder::_gen_action_preamble` binds
+/// to the value of the production's `n`th symbol, keeping the `$`'s span.
+fn replace_dollars(ts: TokenStream) -> TokenStream {
+    let mut out = Vec::new();
+    let mut toks = ts.into_iter().peekable();
+    while let Some(tt) = toks.next() {
+        match tt {
+            TokenTree::Punct(ref p) if p.as_char() == '$' => {
+                let n = match toks.peek() {
+                    Some(&TokenTree::Literal(ref l)) => Some(l.to_string()),
+                    _ => None
+                };
+                if let Some(n) = n.filter(|n| n.chars().all(|c| c.is_ascii_digit())) {
+                    out.push(TokenTree::Ident(Ident::new(&_action_arg(&n), p.span())));
+                    toks.next();
+                    continue;
+                }
+            }
+            TokenTree::Group(ref g) => {
+                let mut ng = Group::new(g.delimiter(), replace_dollars(g.stream()));
+                ng.set_span(g.span());
+                out.push(TokenTree::Group(ng));
+                continue;
+            }
+            _ => ()
+        }
+        out.push(tt);
+    }
+    out.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::expand;
+
+    /// Expand `s`, returning the output without spaces (since how tokens are separated when
+    /// they're turned back into a string can vary).
+    fn expand_str(s: &str) -> Result<String, Vec<String>> {
+        expand(s.parse().unwrap())
+            .map(|ts| ts.to_string().replace(' ', ""))
+            .map_err(|errs| errs.into_iter().map(|(msg, _)| msg).collect())
+    }
+
+    #[test]
+    fn test_expand() {
+        let outs = expand_str(
+            "lexer {
+                 \"[0-9]+\" \"INT\"
+                 \"\\\\+\" \"+\"
+                 \"[ \\t]+\" ;
+             }
+             %start Expr
+             %type u64
+             %%
+             Expr: Expr \"+\" \"INT\" { $1 + int($3) } | \"INT\" { int($1) } ;
+             %%
+             fn int(s: &str) -> u64 { s.parse().unwrap() }"
+        )
+        .unwrap();
+        assert!(outs.contains("pubfnparse("));
+        assert!(outs.contains("pubfnlexerdef("));
+        assert!(outs.contains("pubfnparse_str("));
+        assert!(outs.contains("pubconstT_INT"));
+        assert!(outs.contains(";__gt_arg_1+int(__gt_arg_3)}"));
+        assert!(outs.contains("fnint(s:&str)->u64"));
+
+        // Without an action type, a parse tree is generated.
+        let outs = expand_str("%start A %% A: \"a\";").unwrap();
+        assert!(outs.contains("Node<"));
+        assert!(!outs.contains("parse_str"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            expand_str("%start A %% A: B C;"),
+            Err(vec![
+                "Unknown reference to rule 'B'".to_owned(),
+                "Unknown reference to rule 'C'".to_owned()
+            ])
+        );
+        assert_eq!(
+            expand_str("lexer { \"a\" \"A\" } %start S %% S: \"A\" \"B\" \"C\";"),
+            Err(vec![
+                "Token 'B' is not defined in the lexer".to_owned(),
+                "Token 'C' is not defined in the lexer".to_owned()
+            ])
+        );
+        assert_eq!(
+            expand_str("lexer { \"a\" \"A\" \"(\" \"B\" } %start S %% S: \"A\";"),
+            Err(vec!["Invalid regular expression".to_owned()])
+        );
+        assert_eq!(
+            expand_str("lexer { \"a\" 1 } %start S %% S: \"A\";"),
+            Err(vec![
+                "Expected a token name (as a string literal) or ';'".to_owned()
+            ])
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use proc_macro2::{Delimiter, Group, Spacing, Span, TokenStream, TokenTree};
+
+/// A grammar given as Rust tokens, converted back into the text of a `.y` file. Since Rust's
+/// tokeniser discards whitespace, the text is laid out afresh: each `%` declaration starts a new
+/// line; tokens which Rust considers joined (e.g. `%%`, `$1`, and `::`) are not separated; and
+/// all other tokens are separated by a space.
+pub struct Source {
+    text: String,
+    /// The offset in `text` of each token (including groups), and the token's span.
+    spans: Vec<(usize, Span)>,
+    /// The offset in `text` of each action's opening brace, and the action itself.
+    actions: Vec<(usize, Group)>,
+    /// The tokens in the programs section.
+    programs: Vec<TokenTree>,
+    /// Which section (0: declarations; 1: rules; 2: programs) we are currently in.
+    section: usize
+}
+
+impl Source {
+    pub fn new(ts: TokenStream) -> Self {
+        let mut src = Source {
+            text: String::new(),
+            spans: Vec::new(),
+            actions: Vec::new(),
+            programs: Vec::new(),
+            section: 0
+        };
+        src.render(ts, true);
+        src
+    }
+
+    /// The `.y` text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The span of the token at the line and column (both starting from 1) `line_col` of the
+    /// text (or, if that's within whitespace, the token before it). If `line_col` is `None`,
+    /// the span of the whole macro call is returned.
+    pub fn span(&self, line_col: Option<(usize, usize)>) -> Span {
+        let off = match line_col {
+            Some(lc) => self.off(lc),
+            None => return Span::call_site()
+        };
+        self.spans
+            .iter()
+            .rev()
+            .find(|&&(s_off, _)| s_off <= off)
+            .map(|&(_, span)| span)
+            .unwrap_or_else(Span::call_site)
+    }
+
+    /// The action which starts at the line and column `line_col` of the text.
+    pub fn action(&self, line_col: (usize, usize)) -> Option<&Group> {
+        // The action's code starts after its opening brace (and any whitespace), so we want the
+        // last action opened before `line_col`.
+        let off = self.off(line_col);
+        self.actions
+            .iter()
+            .rev()
+            .find(|&&(a_off, _)| a_off < off)
+            .map(|&(_, ref g)| g)
+    }
+
+    /// The span of the first use in the rules of the token `name` (or, if there isn't one, the
+    /// span of the whole macro call).
+    pub fn token_span(&self, name: &str) -> Span {
+        let dq = format!("\"{}\"", name);
+        let sq = format!("'{}'", name);
+        let rules_start = self.text.find("%%").unwrap_or(0);
+        self.spans
+            .iter()
+            .find(|&&(off, _)| {
+                off > rules_start
+                    && (self.text[off..].starts_with(&dq) || self.text[off..].starts_with(&sq))
+            })
+            .map(|&(_, span)| span)
+            .unwrap_or_else(Span::call_site)
+    }
+
+    /// The tokens of the programs section.
+    pub fn programs(&self) -> TokenStream {
+        self.programs.iter().cloned().collect()
+    }
+
+    /// Convert `line_col` into an offset into the text.
+    fn off(&self, (line, col): (usize, usize)) -> usize {
+        let line_off = if line <= 1 {
+            0
+        } else {
+            self.text
+                .match_indices('\n')
+                .nth(line - 2)
+                .map(|(i, _)| i + 1)
+                .unwrap_or_else(|| self.text.len())
+        };
+        self.text[line_off..]
+            .char_indices()
+            .nth(col - 1)
+            .map(|(i, _)| line_off + i)
+            .unwrap_or_else(|| self.text.len())
+    }
+
+    fn render(&mut self, ts: TokenStream, top: bool) {
+        // Whether the next token should be joined to the previous one.
+        let mut glue = true;
+        // Whether the previous (top-level) token was a `%` joined to the next token.
+        let mut prev_pct = false;
+        for tt in ts {
+            if top && self.section == 2 {
+                self.programs.push(tt.clone());
+            }
+            let is_pct = match tt {
+                TokenTree::Punct(ref p) => p.as_char() == '%',
+                _ => false
+            };
+            if !glue {
+                // Declarations (some of which run to the end of the line) start on a new line.
+                self.text.push(if top && is_pct { '\n' } else { ' ' });
+            }
+            let off = self.text.len();
+            self.spans.push((off, tt.span()));
+            glue = false;
+            match tt {
+                TokenTree::Punct(ref p) => {
+                    self.text.push(p.as_char());
+                    glue = p.spacing() == Spacing::Joint
+                        || p.as_char() == '$'
+                        || (top && is_pct && !prev_pct);
+                    if top && is_pct && prev_pct {
+                        self.section += 1;
+                    }
+                    prev_pct = top && is_pct && p.spacing() == Spacing::Joint;
+                    continue;
+                }
+                TokenTree::Group(ref g) => {
+                    if top && self.section == 1 && g.delimiter() == Delimiter::Brace {
+                        self.actions.push((off, g.clone()));
+                    }
+                    let (open, close) = match g.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", "")
+                    };
+                    self.text.push_str(open);
+                    self.render(g.stream(), false);
+                    self.text.push_str(close);
+                }
+                TokenTree::Ident(ref i) => self.text.push_str(&i.to_string()),
+                TokenTree::Literal(ref l) => self.text.push_str(&l.to_string())
+            }
+            prev_pct = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Source;
+
+    #[test]
+    fn test_render() {
+        let src = Source::new(
+            "%start Expr
+             %type u64
+             %%
+             Expr: Expr \"+\" Term { $1 + f(&$3) } | Term;
+             Term: 'x' {
+                 0
+             };
+             %%
+             fn f<'a>(x: &'a u64) -> u64 { *x }"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            src.text(),
+            "%start Expr
+%type u64
+%% Expr : Expr \"+\" Term {$1 + f (&$3)} | Term ; Term : 'x' {0} ;
+%% fn f <'a > (x : &'a u64) -> u64 {* x}"
+        );
+        assert_eq!(src.actions.len(), 2);
+        assert!(src.programs().to_string().starts_with("fn f"));
+        // `$1` is at line 3, column 26.
+        assert_eq!(src.off((3, 26)), src.text().find("$1").unwrap());
+        assert!(src.action((3, 26)).is_some());
+        assert!(src.action((3, 1)).is_none());
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests which invoke `grammar!` and use the parsers it generates.
+
+extern crate lrlex;
+extern crate lrpar;
+#[macro_use]
+extern crate lrpar_macros;
+
+use lrpar::{LexParseError, Node};
+
+mod calc {
+    grammar! {
+        lexer {
+            "[0-9]+" "INT"
+            "\\+" "+"
+            "\\*" "*"
+            "[ \t]+" ;
+        }
+        %start Expr
+        %type u64
+        %%
+        Expr: Expr "+" Term { $1 + $3 } | Term { $1 } ;
+        Term: Term "*" "INT" { $1 * int($3) } | "INT" { int($1) } ;
+        %%
+        fn int(s: &str) -> u64 {
+            s.parse().unwrap()
+        }
+    }
+}
+
+mod tree {
+    grammar! {
+        lexer {
+            "[a-z]+" "ID"
+            "," ","
+            "[ ]+" ;
+        }
+        %start Ids
+        %%
+        Ids: Ids "," "ID" | "ID" ;
+    }
+}
+
+#[test]
+fn test_actions() {
+    assert_eq!(calc::parse_str("2 + 3 * 4").unwrap(), 14);
+    assert_eq!(calc::parse_str("7").unwrap(), 7);
+    match calc::parse_str("2 + + 3") {
+        Err(LexParseError::ParseError { errors, .. }) => assert!(!errors.is_empty()),
+        _ => panic!()
+    }
+    match calc::parse_str("2 - 3") {
+        Err(LexParseError::LexError(e)) => assert_eq!(e.idx, 2),
+        _ => panic!()
+    }
+}
+
+#[test]
+fn test_parse_tree() {
+    match tree::parse_str("a, bc, d").unwrap() {
+        Node::Nonterm { ridx, ref nodes } => {
+            assert_eq!(ridx, lrpar::RIdx(tree::R_IDS));
+            assert_eq!(nodes.len(), 3);
+        }
+        _ => panic!()
+    }
+}
@@ -165,13 +165,17 @@ where
     let mut core_states = Vec::new();
     let mut edges: Vec<HashMap<Symbol<StorageT>, StIdx>> = Vec::new();
 
-    let mut state0 = Itemset::new(grm);
+    // Each of the grammar's start rules has its own entry state: state i is the entry state for
+    // the ith production of the start rule (and thus for the ith of grm.start_rules()).
     let mut ctx = Vob::from_elem(usize::from(grm.tokens_len()), false);
     ctx.set(usize::from(grm.eof_token_idx()), true);
-    state0.add(grm.start_prod(), SIdx(StorageT::zero()), &ctx);
-    closed_states.push(None);
-    core_states.push(state0);
-    edges.push(HashMap::new());
+    for &pidx in grm.rule_to_prods(grm.start_rule_idx()) {
+        let mut state = Itemset::new(grm);
+        state.add(pidx, SIdx(StorageT::zero()), &ctx);
+        closed_states.push(None);
+        core_states.push(state);
+        edges.push(HashMap::new());
+    }
 
     // We maintain two lists of which rules and tokens we've seen; when processing a given
     // state there's no point processing a rule or token more than once.
@@ -190,7 +194,7 @@ where
         cnd_rule_weaklies.push(Vec::new());
     }
 
-    let mut todo = 1; // How many None values are there in closed_states?
+    let mut todo = closed_states.len(); // How many None values are there in closed_states?
     let mut todo_off = 0; // Offset in closed states to start searching for the next todo.
     while todo > 0 {
         debug_assert_eq!(core_states.len(), closed_states.len());
@@ -321,24 +325,28 @@ where
             .drain(..)
             .zip(closed_states.drain(..).map(|x| x.unwrap()))
             .collect(),
-        edges
+        edges,
+        grm.rule_to_prods(grm.start_rule_idx()).len()
     );
     StateGraph::new(gc_states, gc_edges)
 }
 
-/// Garbage collect `zip_states` (of `(core_states, closed_state)`) and `edges`. Returns a new pair
-/// with unused states and their corresponding edges removed.
+/// Garbage collect `zip_states` (of `(core_states, closed_state)`) and `edges`, where the first
+/// `start_states_len` states are the entry states. Returns a new pair with unused states and their
+/// corresponding edges removed. Since the entry states are never removed, they keep their indexes.
 fn gc<StorageT: Eq + Hash + PrimInt>(
     mut states: Vec<(Itemset<StorageT>, Itemset<StorageT>)>,
-    mut edges: Vec<HashMap<Symbol<StorageT>, StIdx>>
+    mut edges: Vec<HashMap<Symbol<StorageT>, StIdx>>,
+    start_states_len: usize
 ) -> (
     Vec<(Itemset<StorageT>, Itemset<StorageT>)>,
     Vec<HashMap<Symbol<StorageT>, StIdx>>
 ) {
     // First of all, do a simple pass over all states. All state indexes reachable from the
-    // start state will be inserted into the 'seen' set.
+    // entry states will be inserted into the 'seen' set.
     let mut todo = HashSet::new();
-    todo.insert(StIdx(0));
+    // start_states_len <= states.len(), which fits in StIdxStorageT, so the cast is safe.
+    todo.extend((0..start_states_len).map(|x| StIdx(x as StIdxStorageT)));
     let mut seen = HashSet::new();
     while !todo.is_empty() {
         // XXX This is the clumsy way we're forced to do what we'd prefer to be:
@@ -40,6 +40,8 @@ use itemset::Itemset;
 use StIdx;
 use StIdxStorageT;
 
+/// An LR state graph. There is one entry state for each of the grammar's start rules: state `i`
+/// is the entry state for the `i`th of `YaccGrammar::start_rules`.
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct StateGraph<StorageT: Eq + Hash> {
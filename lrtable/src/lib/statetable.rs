@@ -99,6 +99,8 @@ pub struct StateTable<StorageT> {
     pub reduce_reduce: u64,
     /// The number of shift/reduce errors encountered.
     pub shift_reduce: u64,
+    /// The state in which input is accepted when parsing from the grammar's first start rule.
+    /// Each other start rule has its own accepting state.
     pub final_state: StIdx
 }
 
@@ -176,7 +178,8 @@ where
                     state_actions.set(off as usize, true);
                     match StateTable::decode(actions[off as usize]) {
                         Action::Reduce(r_pidx) => {
-                            if pidx == grm.start_prod() && tidx == usize::from(grm.eof_token_idx())
+                            if grm.prod_to_rule(pidx) == grm.start_rule_idx()
+                                && tidx == usize::from(grm.eof_token_idx())
                             {
                                 return Err(StateTableError {
                                     kind: StateTableErrorKind::AcceptReduceConflict,
@@ -199,10 +202,13 @@ where
                             })
                         }
                         Action::Error => {
-                            if pidx == grm.start_prod() && tidx == usize::from(grm.eof_token_idx())
+                            if grm.prod_to_rule(pidx) == grm.start_rule_idx()
+                                && tidx == usize::from(grm.eof_token_idx())
                             {
-                                assert!(final_state.is_none());
-                                final_state = Some(stidx);
+                                if pidx == grm.start_prod() {
+                                    assert!(final_state.is_none());
+                                    final_state = Some(stidx);
+                                }
                                 actions[off as usize] = StateTable::encode(Action::Accept);
                             } else {
                                 actions[off as usize] = StateTable::encode(Action::Reduce(pidx));
@@ -349,8 +355,13 @@ where
         }
     }
 
-    /// Return the action for `stidx` and `sym`, or `None` if there isn't any.
+    /// Return the action for `stidx` and `sym`, or `Action::Error` if there isn't any (including
+    /// if `sym` is not a token in the grammar).
     pub fn action(&self, stidx: StIdx, tidx: TIdx<StorageT>) -> Action<StorageT> {
+        if tidx >= self.tokens_len {
+            // A token that isn't part of the grammar (e.g. an error lexeme) is always an error.
+            return Action::Error;
+        }
         StateTable::decode(
             self.actions
                 .get(usize::from(stidx), usize::from(tidx))
@@ -590,6 +601,9 @@ mod test {
         assert_reduce(s7, grm.eof_token_idx(), "Expr", 0);
         assert_reduce(s8, grm.token_idx("-").unwrap(), "Term", 0);
         assert_reduce(s8, grm.eof_token_idx(), "Term", 0);
+        // Tokens outside the grammar (e.g. error lexemes) are always errors.
+        assert_eq!(st.action(s0, grm.tokens_len()), Action::Error);
+        assert_eq!(st.action(s8, TIdx(u32::max_value())), Action::Error);
 
         let mut s4_actions = HashSet::new();
         s4_actions.extend(&[grm.token_idx("-").unwrap(),
@@ -621,6 +635,62 @@ mod test {
         assert_eq!(st.goto(s6, grm.rule_idx("Factor").unwrap()).unwrap(), s3);
     }
 
+    #[test]
+    fn test_multiple_starts() {
+        let grm = YaccGrammar::new(
+            YaccKind::Original,
+            &"
+            %start Expr Term
+            %%
+            Expr : Expr '+' Term | Term;
+            Term : 'id';
+          "
+        )
+        .unwrap();
+        let sg = pager_stategraph(&grm);
+        let st = StateTable::new(&grm, &sg).unwrap();
+
+        // State 0 is the entry state for Expr and state 1 for Term.
+        let s0 = StIdx(0);
+        let s1 = StIdx(1);
+        let expr = Symbol::Rule(grm.rule_idx("Expr").unwrap());
+        let term = Symbol::Rule(grm.rule_idx("Term").unwrap());
+        let s0_expr = sg.edge(s0, expr).unwrap();
+        let s1_term = sg.edge(s1, term).unwrap();
+        assert!(sg.edge(s1, expr).is_none());
+        assert_eq!(st.action(s0_expr, grm.eof_token_idx()), Action::Accept);
+        assert_eq!(st.final_state, s0_expr);
+        assert_eq!(st.action(s1_term, grm.eof_token_idx()), Action::Accept);
+        assert_eq!(
+            st.action(s1_term, grm.token_idx("+").unwrap()),
+            Action::Error
+        );
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_error_token() {
+        let grm = YaccGrammar::new(YaccKind::Original, &"
+            %start Stmts
+            %%
+            Stmts : Stmts Stmt | ;
+            Stmt : 'x' ';' | error ';';
+          ").unwrap();
+        let sg = pager_stategraph(&grm);
+        let st = StateTable::new(&grm, &sg).unwrap();
+        assert_eq!(st.shift_reduce, 0);
+        assert_eq!(st.reduce_reduce, 0);
+
+        let error_tidx = grm.error_token_idx().unwrap();
+        let s0 = StIdx(0);
+        let s1 = sg.edge(s0, Symbol::Rule(grm.rule_idx("Stmts").unwrap())).unwrap();
+        let s2 = sg.edge(s1, Symbol::Token(error_tidx)).unwrap();
+        assert_eq!(st.action(s1, error_tidx), Action::Shift(s2));
+        assert!(st.state_shifts(s1).any(|tidx| tidx == error_tidx));
+        assert_eq!(st.action(s2, grm.token_idx(";").unwrap()),
+                   Action::Shift(sg.edge(s2, Symbol::Token(grm.token_idx(";").unwrap())).unwrap()));
+    }
+
     #[test]
     #[rustfmt::skip]
     fn test_default_reduce_reduce() {
@@ -190,7 +190,9 @@ fn main() {
     }
 
     let input = read_file(&matches.free[2]);
-    let mut lexer = lexerdef.lexer(&input);
+    // Since the lexer is error tolerant, input that can't be lexed is reported as a parse error
+    // (with the recoverer's repairs deleting it), allowing us to report all subsequent errors.
+    let mut lexer = lexerdef.error_tolerant_lexer(&input);
     let pb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(recoverykind);
     match pb.parse(&mut lexer) {
         Ok(pt) => println!("{}", pt.pp(&grm, &input)),
@@ -198,12 +200,17 @@ fn main() {
             println!("Lexing error at position {}", e.idx);
             process::exit(1);
         }
-        Err(LexParseError::ParseError(o_pt, errs)) => {
-            match o_pt {
+        Err(LexParseError::ParseError {
+            tree,
+            errors,
+            truncated,
+            ..
+        }) => {
+            match tree {
                 Some(pt) => println!("{}", pt.pp(&grm, &input)),
                 None => println!("Unable to repair input sufficiently to produce parse tree.\n")
             }
-            for e in errs {
+            for e in errors {
                 let (line, col) = lexer.line_and_col(e.lexeme()).unwrap();
                 if e.repairs().is_empty() {
                     println!("Error at line {} col {}. No repairs found.", line, col);
@@ -237,6 +244,9 @@ fn main() {
                     println!("  {}{}: {}", " ".repeat(padding), i + 1, out.join(", "));
                 }
             }
+            if truncated {
+                println!("Too many errors: not all errors have been reported.");
+            }
             process::exit(1);
         }
     }